@@ -0,0 +1,93 @@
+//! Deprecated method and tool names
+//!
+//! Clients pinned to old names keep working through an alias table (env
+//! `ALIASES_PATH`) mapping each old name to its current one, e.g.
+//!
+//! ```json
+//! {
+//!   "methods": { "tools/get": "tools/list" },
+//!   "tools": { "add_items": "add_to_cart" }
+//! }
+//! ```
+//!
+//! Every call through an alias logs a deprecation warning and is counted in the
+//! admin metrics.
+
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+/// Old JSON-RPC method and tool names mapped to their current names
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct Aliases {
+    /// Old JSON-RPC method names
+    #[serde(default)]
+    pub methods: HashMap<String, String>,
+
+    /// Old tool names
+    #[serde(default)]
+    pub tools: HashMap<String, String>,
+}
+
+impl Aliases {
+    /// Returns the current name of a method and, when `name` is an alias, the
+    /// deprecation warning to log
+    pub fn method<'a>(&'a self, name: &'a str) -> (&'a str, Option<String>) {
+        resolve(&self.methods, "Method", name)
+    }
+
+    /// Returns the current name of a tool and, when `name` is an alias, the
+    /// deprecation warning to log
+    pub fn tool<'a>(&'a self, name: &'a str) -> (&'a str, Option<String>) {
+        resolve(&self.tools, "Tool", name)
+    }
+
+    /// Number of aliased method and tool names
+    pub fn len(&self) -> usize {
+        self.methods.len() + self.tools.len()
+    }
+}
+
+/// Loads an alias table from a JSON file
+pub fn load_aliases(path: &Path) -> Result<Aliases, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read aliases {:?}: {}", path, e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("Invalid aliases {:?}: {}", path, e))
+}
+
+/// Looks a name up in one table, returning the current name and, for an alias,
+/// the deprecation warning to log
+fn resolve<'a>(
+    table: &'a HashMap<String, String>,
+    kind: &str,
+    name: &'a str,
+) -> (&'a str, Option<String>) {
+    match table.get(name) {
+        Some(current) => (
+            current,
+            Some(format!(
+                "{} {:?} is deprecated; use {:?}",
+                kind, name, current
+            )),
+        ),
+        None => (name, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alias_resolution_warns() {
+        let tools = HashMap::from([("add_items".to_string(), "add_to_cart".to_string())]);
+
+        assert_eq!(
+            resolve(&tools, "Tool", "add_items"),
+            (
+                "add_to_cart",
+                Some(r#"Tool "add_items" is deprecated; use "add_to_cart""#.to_string())
+            )
+        );
+        assert_eq!(resolve(&tools, "Tool", "checkout"), ("checkout", None));
+    }
+}
@@ -0,0 +1,341 @@
+//! Product catalog for the shopping cart application
+//!
+//! The catalog is optional: it is loaded from a JSON file (env `CATALOG_PATH`)
+//! containing an array of products. Without it, carts accept any product.
+
+use crate::model::CartItem;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, path::Path};
+
+// =============================================================================
+// Data Models
+// =============================================================================
+
+/// A product known to the store
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Product {
+    /// Product name, matched case-insensitively against cart item names
+    pub name: String,
+
+    /// Authoritative unit price
+    #[serde(default)]
+    pub price: Option<f64>,
+
+    /// Units available; `None` means stock is not tracked
+    #[serde(default)]
+    pub stock: Option<u32>,
+
+    /// Optional product category
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Collection of products keyed by normalized name
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    products: HashMap<String, Product>,
+}
+
+/// A client-supplied price that disagrees with the catalog
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceMismatch {
+    pub name: String,
+    pub client_price: f64,
+    pub catalog_price: f64,
+}
+
+/// A cart line requesting more units than are in stock
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StockShortage {
+    pub name: String,
+    pub requested: u32,
+    pub available: u32,
+}
+
+/// Remaining stock for a cart line, letting widgets disable "add" at the limit
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemAvailability {
+    pub name: String,
+
+    /// Units still available after this cart's quantity; omitted when stock is not tracked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_stock: Option<u32>,
+}
+
+/// Result of checking a cart against the catalog
+#[derive(Debug, Default, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    /// True when no problems were found
+    pub valid: bool,
+
+    /// Names of cart items that are not in the catalog
+    pub unknown_products: Vec<String>,
+
+    /// Items whose client price differs from the catalog price
+    pub price_mismatches: Vec<PriceMismatch>,
+
+    /// Items requesting more units than available
+    pub out_of_stock: Vec<StockShortage>,
+}
+
+// =============================================================================
+// Catalog
+// =============================================================================
+
+impl Catalog {
+    /// Builds a catalog from a list of products
+    pub fn new(products: Vec<Product>) -> Self {
+        Self {
+            products: products
+                .into_iter()
+                .map(|p| (normalize_name(&p.name), p))
+                .collect(),
+        }
+    }
+
+    /// Loads a catalog from a JSON file containing an array of products
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read catalog {:?}: {}", path, e))?;
+        let products: Vec<Product> =
+            serde_json::from_str(&raw).map_err(|e| format!("Invalid catalog {:?}: {}", path, e))?;
+        Ok(Self::new(products))
+    }
+
+    /// Returns true when no products are configured
+    pub fn is_empty(&self) -> bool {
+        self.products.is_empty()
+    }
+
+    /// Returns true when any product tracks its stock
+    pub fn tracks_stock(&self) -> bool {
+        self.products.values().any(|p| p.stock.is_some())
+    }
+
+    /// Looks up a product by name (case-insensitive)
+    pub fn get(&self, name: &str) -> Option<&Product> {
+        self.products.get(&normalize_name(name))
+    }
+
+    /// Finds the product whose name is closest to `name` within `max_distance` edits
+    pub fn closest_match(&self, name: &str, max_distance: usize) -> Option<&Product> {
+        let needle = normalize_name(name);
+        self.products
+            .iter()
+            .map(|(key, product)| (edit_distance(&needle, key), product))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by(|(da, a), (db, b)| da.cmp(db).then_with(|| a.name.cmp(&b.name)))
+            .map(|(_, product)| product)
+    }
+
+    /// Renames near-miss item names to their catalog spelling, returning a warning per correction
+    pub fn correct_names(&self, items: &mut [CartItem], max_distance: usize) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for item in items.iter_mut() {
+            if self.get(&item.name).is_some() {
+                continue;
+            }
+            if let Some(product) = self.closest_match(&item.name, max_distance) {
+                warnings.push(format!(
+                    "Corrected \"{}\" to \"{}\"",
+                    item.name, product.name
+                ));
+                item.name = product.name.clone();
+            }
+        }
+        warnings
+    }
+
+    /// Replaces client-supplied prices with catalog prices, rejecting unknown products
+    pub fn apply_prices(&self, items: &mut [CartItem]) -> Result<(), String> {
+        for item in items.iter_mut() {
+            let product = self
+                .get(&item.name)
+                .ok_or_else(|| format!("Product not in catalog: {}", item.name))?;
+
+            match product.price {
+                Some(price) => item.extra.insert("price".to_string(), json!(price)),
+                None => item.extra.remove("price"),
+            };
+        }
+        Ok(())
+    }
+
+    /// Reports the stock left for each cart item, counting every line of the same product
+    pub fn availability(&self, items: &[CartItem]) -> Vec<ItemAvailability> {
+        let mut in_cart: HashMap<String, u32> = HashMap::new();
+        for item in items {
+            let quantity = in_cart.entry(normalize_name(&item.name)).or_default();
+            *quantity = quantity.saturating_add(item.quantity);
+        }
+
+        items
+            .iter()
+            .map(|item| ItemAvailability {
+                name: item.name.clone(),
+                remaining_stock: self
+                    .get(&item.name)
+                    .and_then(|p| p.stock)
+                    .map(|stock| stock.saturating_sub(in_cart[&normalize_name(&item.name)])),
+            })
+            .collect()
+    }
+
+    /// Checks cart items for unknown products, price mismatches, and stock shortages
+    pub fn validate(&self, items: &[CartItem]) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        for item in items {
+            let Some(product) = self.get(&item.name) else {
+                report.unknown_products.push(item.name.clone());
+                continue;
+            };
+
+            if let (Some(client_price), Some(catalog_price)) = (item.price(), product.price) {
+                if (client_price - catalog_price).abs() > f64::EPSILON {
+                    report.price_mismatches.push(PriceMismatch {
+                        name: item.name.clone(),
+                        client_price,
+                        catalog_price,
+                    });
+                }
+            }
+
+            if let Some(stock) = product.stock {
+                if item.quantity > stock {
+                    report.out_of_stock.push(StockShortage {
+                        name: item.name.clone(),
+                        requested: item.quantity,
+                        available: stock,
+                    });
+                }
+            }
+        }
+
+        report.valid = report.unknown_products.is_empty()
+            && report.price_mismatches.is_empty()
+            && report.out_of_stock.is_empty();
+        report
+    }
+}
+
+/// Normalizes a product name for lookups
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Levenshtein distance between two strings, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> Catalog {
+        Catalog::new(vec![
+            Product {
+                name: "Apple".into(),
+                price: Some(1.5),
+                stock: Some(10),
+                category: Some("fruit".into()),
+            },
+            Product {
+                name: "Bread".into(),
+                price: Some(3.0),
+                stock: Some(1),
+                category: None,
+            },
+        ])
+    }
+
+    fn item(value: serde_json::Value) -> CartItem {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_validate_valid_cart() {
+        let items = vec![
+            item(json!({ "name": "apple", "quantity": 2, "price": 1.5 })),
+            item(json!({ "name": "Bread" })),
+        ];
+        let report = catalog().validate(&items);
+        assert!(report.valid);
+        assert_eq!(
+            report,
+            ValidationReport {
+                valid: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_problems() {
+        let items = vec![
+            item(json!({ "name": "Durian" })),
+            item(json!({ "name": "Apple", "price": 0.5 })),
+            item(json!({ "name": "Bread", "quantity": 3 })),
+        ];
+        let report = catalog().validate(&items);
+        assert!(!report.valid);
+        assert_eq!(report.unknown_products, vec!["Durian".to_string()]);
+        assert_eq!(
+            report.price_mismatches,
+            vec![PriceMismatch {
+                name: "Apple".into(),
+                client_price: 0.5,
+                catalog_price: 1.5,
+            }]
+        );
+        assert_eq!(report.out_of_stock[0].available, 1);
+    }
+
+    #[test]
+    fn test_apply_prices_overrides_client_price() {
+        let mut items = vec![item(json!({ "name": "Apple", "price": 0.01 }))];
+        catalog().apply_prices(&mut items).unwrap();
+        assert_eq!(items[0].price(), Some(1.5));
+
+        let mut items = vec![item(json!({ "name": "Durian", "price": 9.0 }))];
+        let err = catalog().apply_prices(&mut items).unwrap_err();
+        assert_eq!(err, "Product not in catalog: Durian");
+    }
+
+    #[test]
+    fn test_fuzzy_name_correction() {
+        assert_eq!(edit_distance("aple", "apple"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        let mut items = vec![
+            item(json!({ "name": "aple" })),
+            item(json!({ "name": "Durian" })),
+        ];
+        let warnings = catalog().correct_names(&mut items, 2);
+        assert_eq!(items[0].name, "Apple");
+        assert_eq!(items[1].name, "Durian");
+        assert_eq!(
+            warnings,
+            vec!["Corrected \"aple\" to \"Apple\"".to_string()]
+        );
+    }
+}
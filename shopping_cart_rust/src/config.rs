@@ -0,0 +1,645 @@
+//! Runtime configuration for the shopping cart application
+//!
+//! Optional features are driven by environment variables read once at startup.
+//! Tests build a `Config` directly instead of mutating the process environment.
+
+use crate::catalog::Catalog;
+use crate::pricing::{currency_info, DEFAULT_CURRENCY};
+use serde::Serialize;
+use std::{path::PathBuf, time::Duration};
+
+/// Shortest `MAX_CART_ID_LEN` honoured: the length of a simple-format uuid
+const MIN_CART_ID_LEN: usize = 32;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// Where authoritative item prices come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PriceSource {
+    /// Trust `extra.price` as sent by the client
+    #[default]
+    Client,
+    /// Overwrite prices from the catalog and reject uncatalogued items
+    Catalog,
+}
+
+/// Key casing of tool structured content
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputCase {
+    /// `cartId`, as the widget expects
+    #[default]
+    Camel,
+    /// `cart_id`, for clients that expect snake_case
+    Snake,
+}
+
+/// What an add does when one batch repeats an item with different extras
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictingExtras {
+    /// Keep the first occurrence's extras, with a warning
+    #[default]
+    FirstWins,
+    /// Keep the last occurrence's extras, with a warning
+    LastWins,
+    /// Reject the batch
+    Error,
+}
+
+/// Settings resolved from the environment when the server starts
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Template for checkout payment links, containing an `{orderId}` placeholder
+    /// (env `PAYMENT_URL_TEMPLATE`)
+    pub payment_url_template: Option<String>,
+
+    /// Reject items carrying extra keys outside `ALLOWED_EXTRA_KEYS`
+    /// (env `STRICT_ITEM_SCHEMA=1`)
+    pub strict_item_schema: bool,
+
+    /// Reject `/sync_cart` calls with an empty `items` list instead of clearing the cart
+    /// (env `REJECT_EMPTY_SYNC=1`)
+    pub reject_empty_sync: bool,
+
+    /// Reject checkout of a cart that exists but holds no items, instead of issuing an
+    /// empty receipt (env `BLOCK_EMPTY_CHECKOUT=1`)
+    pub block_empty_checkout: bool,
+
+    /// Let `/sync_cart` keep the valid items of a batch and report the rejected ones,
+    /// instead of refusing the whole batch (env `LENIENT_SYNC=1`)
+    pub lenient_sync: bool,
+
+    /// Expose the `/admin/*` routes (env `ENABLE_ADMIN=1`)
+    pub enable_admin: bool,
+
+    /// Bearer token required by admin routes (env `ADMIN_TOKEN`)
+    pub admin_token: Option<String>,
+
+    /// ISO 4217 code used for prices (env `CURRENCY`, defaults to USD)
+    pub currency: Option<String>,
+
+    /// Directory holding the widget HTML, used instead of looking for `assets` next to
+    /// the working directory (env `CART_ASSETS_DIR`)
+    pub assets_dir: Option<PathBuf>,
+
+    /// Path to a JSON product catalog (env `CATALOG_PATH`)
+    pub catalog_path: Option<PathBuf>,
+
+    /// Path to a JSON list of coupon definitions (env `COUPONS_PATH`)
+    pub coupons_path: Option<PathBuf>,
+
+    /// Coupons a single cart may have applied at once (env `MAX_COUPONS_PER_CART`, defaults to 1)
+    pub max_coupons_per_cart: Option<usize>,
+
+    /// Most units a cart may hold across all its lines (env `MAX_TOTAL_QTY`, defaults to
+    /// `u32::MAX`)
+    pub max_total_qty: Option<u64>,
+
+    /// Maximum edit distance for correcting item names to catalog products
+    /// (env `FUZZY_MATCH_DISTANCE`; unset disables fuzzy matching)
+    pub fuzzy_match_distance: Option<usize>,
+
+    /// Source of item prices (env `PRICE_SOURCE=client|catalog`)
+    pub price_source: PriceSource,
+
+    /// Key casing of tool structured content (env `OUTPUT_CASE=camel|snake`)
+    pub output_case: OutputCase,
+
+    /// Handling of an item repeated in one add batch with different extras
+    /// (env `CONFLICTING_EXTRAS=first|last|error`)
+    pub conflicting_extras: ConflictingExtras,
+
+    /// Watch the assets directory and drop the widget cache on changes
+    /// (env `ASSET_HOT_RELOAD=1`)
+    pub asset_hot_reload: bool,
+
+    /// Check at startup that every advertised tool example parses as the tool's
+    /// input, logging any that don't (env `STARTUP_SELF_CHECK=1`)
+    pub startup_self_check: bool,
+
+    /// Maximum time to wait for widget HTML reads (env `ASSET_READ_TIMEOUT_MS`)
+    pub asset_read_timeout_ms: Option<u64>,
+
+    /// HTTP(S) URL to fetch the widget HTML from, e.g. a CDN origin
+    /// (env `WIDGET_URL`); local assets are used when the fetch fails
+    pub widget_url: Option<String>,
+
+    /// Reject requests whose JSON-RPC id is null or a fractional number
+    /// (env `STRICT_JSONRPC_IDS=1`)
+    pub strict_jsonrpc_ids: bool,
+
+    /// Base URL for shareable cart links (env `SHARE_BASE_URL`)
+    pub share_base_url: Option<String>,
+
+    /// HMAC secret used to sign and verify share links (env `SHARE_SECRET`)
+    pub share_secret: Option<String>,
+
+    /// Lifetime of signed share links, which become single-use when set
+    /// (env `SHARE_LINK_TTL_SECONDS`)
+    pub share_link_ttl_seconds: Option<u64>,
+
+    /// Cap on concurrent SSE sessions on `GET /mcp` (env `MAX_SSE_CONNECTIONS`)
+    pub max_sse_connections: Option<usize>,
+
+    /// Reject params sent to methods that take none, such as `tools/list`
+    /// (env `STRICT_PARAMS=1`)
+    pub strict_params: bool,
+
+    /// Tax rate charged after discounts, e.g. `0.08` (env `TAX_RATE`)
+    pub tax_rate: Option<f64>,
+
+    /// Items listed in text summaries before "…and N more" (env `SUMMARY_MAX_ITEMS`)
+    pub summary_max_items: Option<usize>,
+
+    /// Smallest response body, in bytes, worth gzip-compressing (env `COMPRESS_MIN_BYTES`)
+    pub compress_min_bytes: Option<u16>,
+
+    /// Requests allowed per client IP per minute (env `RATE_LIMIT_PER_MINUTE`)
+    pub rate_limit_per_minute: Option<u32>,
+
+    /// Most requests accepted in one JSON-RPC batch (env `MAX_BATCH_SIZE`, defaults to 50)
+    pub max_batch_size: Option<usize>,
+
+    /// Take the client IP from the last `X-Forwarded-For` hop, the one the proxy
+    /// appended (env `TRUST_PROXY=1`)
+    pub trust_proxy: bool,
+
+    /// Unit reported alongside `totalWeight` (env `WEIGHT_UNIT`, defaults to kg)
+    pub weight_unit: Option<String>,
+
+    /// Decimal places money totals are rounded to in responses (env `TOTAL_DECIMALS`,
+    /// defaults to the currency's minor unit digits)
+    pub total_decimals: Option<u32>,
+
+    /// How long add_to_cart idempotency keys are remembered
+    /// (env `IDEMPOTENCY_TTL_SECONDS`, defaults to ten minutes)
+    pub idempotency_ttl_seconds: Option<u64>,
+
+    /// Reject methods other than `initialize` and `ping` until the client's session has
+    /// initialized (env `STRICT_INIT=1`). A WebSocket connection is one session; HTTP
+    /// clients send back the `Mcp-Session-Id` their `initialize` returned.
+    pub strict_init: bool,
+
+    /// HTML-escape tool text content and the names in rendered widget HTML
+    /// (env `ESCAPE_ITEM_NAMES=1`). Names are stored as sent, so syncing them back
+    /// doesn't escape them again; control characters are always stripped
+    pub escape_item_names: bool,
+
+    /// Path to a JSON list of shipping tiers (env `SHIPPING_TIERS_PATH`)
+    pub shipping_tiers_path: Option<PathBuf>,
+
+    /// Path to a JSON object of tax rates by region code (env `TAX_TABLE_PATH`)
+    pub tax_table_path: Option<PathBuf>,
+
+    /// Path to a JSON object of tax rates by item `taxCategory`, e.g. a reduced food
+    /// rate (env `TAX_CATEGORIES_PATH`)
+    pub tax_categories_path: Option<PathBuf>,
+
+    /// Path to a JSON map of related products for `recommend`
+    /// (env `RECOMMENDATIONS_PATH`)
+    pub recommendations_path: Option<PathBuf>,
+
+    /// Path to a JSON object of exchange rates from the base currency, by currency
+    /// code, for `convert_total` (env `FX_RATES_PATH`)
+    pub fx_rates_path: Option<PathBuf>,
+
+    /// Path to a JSON table of deprecated method and tool names (env `ALIASES_PATH`)
+    pub aliases_path: Option<PathBuf>,
+
+    /// Longest cart id accepted from clients (env `MAX_CART_ID_LEN`, defaults to 128).
+    /// Never below 32, the length of the ids the server mints.
+    pub max_cart_id_len: Option<usize>,
+
+    /// Reserve added stock-tracked products for this long so other carts can't claim
+    /// them (env `STOCK_HOLD_SECONDS`)
+    pub stock_hold_seconds: Option<u64>,
+
+    /// Evict carts that haven't changed for this long (env `CART_TTL_SECONDS`)
+    pub cart_ttl_seconds: Option<u64>,
+
+    /// Keep items removed by a sync or patch for this long so `undo_remove` can
+    /// restore them (env `SOFT_DELETE_SECONDS`)
+    pub soft_delete_seconds: Option<u64>,
+
+    /// Record each cart's most recent mutations, up to this many, for `cart_history`
+    /// (env `CART_HISTORY_LEN`)
+    pub cart_history_len: Option<usize>,
+
+    /// Directory carts are saved to and restored from (env `CART_DATA_DIR`)
+    pub cart_data_dir: Option<PathBuf>,
+
+    /// Fail the request when a cart can't be saved, instead of logging and carrying
+    /// on from memory (env `STRICT_PERSISTENCE=1`)
+    pub strict_persistence: bool,
+
+    /// Delay every tool-call response by this long so widget loading states can be
+    /// tried out (env `SIMULATE_DELAY_MS`). Only honored in debug builds, or in
+    /// release with `ALLOW_SIMULATED_DELAY=1` as well.
+    pub simulate_delay_ms: Option<u64>,
+
+    /// Confirms `SIMULATE_DELAY_MS` in release builds (env `ALLOW_SIMULATED_DELAY=1`)
+    pub allow_simulated_delay: bool,
+
+    /// Copy the widget session id from a tool call's `_meta` into the result's `_meta`
+    /// (env `ECHO_WIDGET_SESSION_ID=1`)
+    pub echo_widget_session_id: bool,
+
+    /// Largest cart structured content, in bytes, before `items` are truncated
+    /// (env `MAX_RESPONSE_BYTES`)
+    pub max_response_bytes: Option<usize>,
+
+    /// Close HTTP connections that send and receive nothing for this long
+    /// (env `HTTP_IDLE_TIMEOUT_SECONDS`); SSE keep-alive comments count as activity
+    pub http_idle_timeout_seconds: Option<u64>,
+
+    /// Make `/ready` probe the persistence directory and `WIDGET_URL`
+    /// (env `READY_PROBES=1`)
+    pub ready_probes: bool,
+
+    /// Maximum time each `/ready` probe may take (env `READY_PROBE_TIMEOUT_MS`)
+    pub ready_probe_timeout_ms: Option<u64>,
+}
+
+impl Config {
+    /// Reads the configuration from environment variables
+    pub fn from_env() -> Self {
+        Self {
+            payment_url_template: env_string("PAYMENT_URL_TEMPLATE"),
+            strict_item_schema: env_flag("STRICT_ITEM_SCHEMA"),
+            reject_empty_sync: env_flag("REJECT_EMPTY_SYNC"),
+            block_empty_checkout: env_flag("BLOCK_EMPTY_CHECKOUT"),
+            lenient_sync: env_flag("LENIENT_SYNC"),
+            enable_admin: env_flag("ENABLE_ADMIN"),
+            admin_token: env_string("ADMIN_TOKEN"),
+            currency: env_string("CURRENCY"),
+            assets_dir: env_string("CART_ASSETS_DIR").map(PathBuf::from),
+            catalog_path: env_string("CATALOG_PATH").map(PathBuf::from),
+            coupons_path: env_string("COUPONS_PATH").map(PathBuf::from),
+            max_coupons_per_cart: env_parse("MAX_COUPONS_PER_CART"),
+            max_total_qty: env_parse("MAX_TOTAL_QTY"),
+            fuzzy_match_distance: env_parse("FUZZY_MATCH_DISTANCE"),
+            price_source: match env_string("PRICE_SOURCE").as_deref() {
+                Some("catalog") => PriceSource::Catalog,
+                Some("client") | None => PriceSource::Client,
+                Some(other) => {
+                    eprintln!("Unknown PRICE_SOURCE {:?}, using client prices", other);
+                    PriceSource::Client
+                }
+            },
+            output_case: match env_string("OUTPUT_CASE").as_deref() {
+                Some("snake") => OutputCase::Snake,
+                Some("camel") | None => OutputCase::Camel,
+                Some(other) => {
+                    eprintln!("Unknown OUTPUT_CASE {:?}, using camelCase", other);
+                    OutputCase::Camel
+                }
+            },
+            conflicting_extras: match env_string("CONFLICTING_EXTRAS").as_deref() {
+                Some("last") => ConflictingExtras::LastWins,
+                Some("error") => ConflictingExtras::Error,
+                Some("first") | None => ConflictingExtras::FirstWins,
+                Some(other) => {
+                    eprintln!(
+                        "Unknown CONFLICTING_EXTRAS {:?}, keeping the first extras",
+                        other
+                    );
+                    ConflictingExtras::FirstWins
+                }
+            },
+            asset_hot_reload: env_flag("ASSET_HOT_RELOAD"),
+            startup_self_check: env_flag("STARTUP_SELF_CHECK"),
+            asset_read_timeout_ms: env_parse("ASSET_READ_TIMEOUT_MS"),
+            widget_url: env_string("WIDGET_URL"),
+            strict_jsonrpc_ids: env_flag("STRICT_JSONRPC_IDS"),
+            share_base_url: env_string("SHARE_BASE_URL"),
+            share_secret: env_string("SHARE_SECRET"),
+            share_link_ttl_seconds: env_parse("SHARE_LINK_TTL_SECONDS"),
+            max_sse_connections: env_parse("MAX_SSE_CONNECTIONS"),
+            strict_params: env_flag("STRICT_PARAMS"),
+            tax_rate: env_parse("TAX_RATE"),
+            summary_max_items: env_parse("SUMMARY_MAX_ITEMS"),
+            compress_min_bytes: env_parse("COMPRESS_MIN_BYTES"),
+            rate_limit_per_minute: env_parse("RATE_LIMIT_PER_MINUTE"),
+            max_batch_size: env_parse("MAX_BATCH_SIZE"),
+            trust_proxy: env_flag("TRUST_PROXY"),
+            weight_unit: env_string("WEIGHT_UNIT"),
+            total_decimals: env_parse("TOTAL_DECIMALS"),
+            idempotency_ttl_seconds: env_parse("IDEMPOTENCY_TTL_SECONDS"),
+            strict_init: env_flag("STRICT_INIT"),
+            escape_item_names: env_flag("ESCAPE_ITEM_NAMES"),
+            shipping_tiers_path: env_string("SHIPPING_TIERS_PATH").map(PathBuf::from),
+            tax_table_path: env_string("TAX_TABLE_PATH").map(PathBuf::from),
+            tax_categories_path: env_string("TAX_CATEGORIES_PATH").map(PathBuf::from),
+            recommendations_path: env_string("RECOMMENDATIONS_PATH").map(PathBuf::from),
+            fx_rates_path: env_string("FX_RATES_PATH").map(PathBuf::from),
+            aliases_path: env_string("ALIASES_PATH").map(PathBuf::from),
+            max_cart_id_len: env_parse("MAX_CART_ID_LEN"),
+            stock_hold_seconds: env_parse("STOCK_HOLD_SECONDS"),
+            cart_ttl_seconds: env_parse("CART_TTL_SECONDS"),
+            soft_delete_seconds: env_parse("SOFT_DELETE_SECONDS"),
+            cart_history_len: env_parse("CART_HISTORY_LEN"),
+            cart_data_dir: env_string("CART_DATA_DIR").map(PathBuf::from),
+            strict_persistence: env_flag("STRICT_PERSISTENCE"),
+            simulate_delay_ms: env_parse("SIMULATE_DELAY_MS"),
+            allow_simulated_delay: env_flag("ALLOW_SIMULATED_DELAY"),
+            echo_widget_session_id: env_flag("ECHO_WIDGET_SESSION_ID"),
+            max_response_bytes: env_parse("MAX_RESPONSE_BYTES"),
+            http_idle_timeout_seconds: env_parse("HTTP_IDLE_TIMEOUT_SECONDS"),
+            ready_probes: env_flag("READY_PROBES"),
+            ready_probe_timeout_ms: env_parse("READY_PROBE_TIMEOUT_MS"),
+        }
+    }
+
+    /// Returns the widget HTML read timeout, defaulting to five seconds
+    pub fn asset_read_timeout(&self) -> Duration {
+        Duration::from_millis(self.asset_read_timeout_ms.unwrap_or(5_000))
+    }
+
+    /// Returns the `/ready` probe timeout, defaulting to one second
+    pub fn ready_probe_timeout(&self) -> Duration {
+        Duration::from_millis(self.ready_probe_timeout_ms.unwrap_or(1_000))
+    }
+
+    /// Returns how many items text summaries list, defaulting to ten
+    pub fn summary_max_items(&self) -> usize {
+        self.summary_max_items.unwrap_or(10)
+    }
+
+    /// Returns how many coupons may be stacked on one cart
+    pub fn max_coupons_per_cart(&self) -> usize {
+        self.max_coupons_per_cart.unwrap_or(1)
+    }
+
+    /// Returns the most units a cart may hold, defaulting to `u32::MAX`, the most a
+    /// single line can hold
+    pub fn max_total_quantity(&self) -> u64 {
+        self.max_total_qty.unwrap_or(u64::from(u32::MAX))
+    }
+
+    /// Returns the most requests accepted in one JSON-RPC batch
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size.unwrap_or(50)
+    }
+
+    /// Returns the longest cart id accepted from clients, at least long enough for the
+    /// uuid ids minted for new carts and sessions
+    pub fn max_cart_id_len(&self) -> usize {
+        self.max_cart_id_len.unwrap_or(128).max(MIN_CART_ID_LEN)
+    }
+
+    /// Returns how long signed share links stay valid, if they expire at all
+    pub fn share_link_ttl(&self) -> Option<Duration> {
+        self.share_link_ttl_seconds.map(Duration::from_secs)
+    }
+
+    /// Returns how long added products stay reserved, if holds are enabled
+    pub fn stock_hold(&self) -> Option<Duration> {
+        self.stock_hold_seconds.map(Duration::from_secs)
+    }
+
+    /// Returns how long an unchanged cart is kept, if carts expire
+    pub fn cart_ttl(&self) -> Option<Duration> {
+        self.cart_ttl_seconds.map(Duration::from_secs)
+    }
+
+    /// Returns how long removed items can be restored, if soft deletes are enabled
+    pub fn soft_delete_window(&self) -> Option<Duration> {
+        self.soft_delete_seconds.map(Duration::from_secs)
+    }
+
+    /// Returns the artificial tool-call delay, if one is set and allowed in this build
+    pub fn simulated_delay(&self) -> Option<Duration> {
+        let allowed = cfg!(debug_assertions) || self.allow_simulated_delay;
+        self.simulate_delay_ms
+            .filter(|ms| *ms > 0 && allowed)
+            .map(Duration::from_millis)
+    }
+
+    /// Returns how many mutations each cart's history keeps, if history is enabled
+    pub fn cart_history_limit(&self) -> Option<usize> {
+        self.cart_history_len.filter(|len| *len > 0)
+    }
+
+    /// Returns how long an HTTP connection may sit idle before it is closed
+    pub fn http_idle_timeout(&self) -> Option<Duration> {
+        self.http_idle_timeout_seconds.map(Duration::from_secs)
+    }
+
+    /// Returns how long idempotency keys dedup repeated calls
+    pub fn idempotency_ttl(&self) -> Duration {
+        Duration::from_secs(self.idempotency_ttl_seconds.unwrap_or(600))
+    }
+
+    /// Returns the compression threshold, defaulting to 1 KiB
+    pub fn compress_min_bytes(&self) -> u16 {
+        self.compress_min_bytes.unwrap_or(1024)
+    }
+
+    /// Returns the unit item weights are expressed in
+    pub fn weight_unit(&self) -> &str {
+        self.weight_unit.as_deref().unwrap_or("kg")
+    }
+
+    /// Returns the configured currency code, falling back to the default
+    pub fn currency(&self) -> &str {
+        self.currency.as_deref().unwrap_or(DEFAULT_CURRENCY)
+    }
+
+    /// Returns the decimal places for money totals, by default those of the currency
+    pub fn total_decimals(&self) -> u32 {
+        self.total_decimals
+            .unwrap_or_else(|| currency_info(self.currency()).decimal_places)
+    }
+}
+
+// =============================================================================
+// Feature Flags
+// =============================================================================
+
+/// How many entries each optional data file yielded. A file that is missing or
+/// invalid yields none, which leaves its feature off.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadedCounts {
+    /// Coupons from `COUPONS_PATH`
+    pub coupons: usize,
+    /// Tiers from `SHIPPING_TIERS_PATH`
+    pub shipping_tiers: usize,
+    /// Regions from `TAX_TABLE_PATH`
+    pub tax_regions: usize,
+    /// Categories from `TAX_CATEGORIES_PATH`
+    pub tax_categories: usize,
+    /// Currencies from `FX_RATES_PATH`
+    pub fx_rates: usize,
+    /// Products and categories from `RECOMMENDATIONS_PATH`
+    pub recommendations: usize,
+    /// Method and tool names from `ALIASES_PATH`
+    pub aliases: usize,
+}
+
+/// Optional features in effect, computed once at startup and reported by `GET /features`
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Features {
+    /// A product catalog was loaded
+    pub catalog: bool,
+    /// At least one catalog product tracks stock
+    pub inventory: bool,
+    /// Added stock-tracked products are held for the cart for a while
+    pub stock_holds: bool,
+    /// Carts left unchanged for a while are evicted
+    pub cart_expiry: bool,
+    /// Removed items can be restored with `undo_remove`
+    pub soft_delete: bool,
+    /// Cart mutations are recorded for `cart_history`
+    pub cart_history: bool,
+    /// Carts are saved to disk
+    pub persistence: bool,
+    /// Tool-call responses are artificially delayed
+    pub simulated_delay: bool,
+    /// The widget session id from a tool call's `_meta` is echoed in its result
+    pub widget_session_echo: bool,
+    /// Prices come from the catalog rather than the client
+    pub catalog_prices: bool,
+    /// Structured content keys are snake_case
+    pub snake_case_output: bool,
+    /// An add batch repeating an item with different extras is rejected
+    pub reject_conflicting_extras: bool,
+    /// Item names are corrected to catalog spellings
+    pub fuzzy_matching: bool,
+    /// Coupon definitions were loaded
+    pub coupons: bool,
+    /// A tax rate is charged
+    pub tax: bool,
+    /// Admin routes are exposed behind a bearer token
+    pub admin: bool,
+    /// Widget assets are watched and reloaded on change
+    pub asset_hot_reload: bool,
+    /// Widget HTML is fetched from a remote URL
+    pub remote_widget: bool,
+    /// `/ready` probes the server's dependencies
+    pub ready_probes: bool,
+    /// Checkout receipts carry a payment link
+    pub payment_links: bool,
+    /// `share_cart` can build links
+    pub cart_sharing: bool,
+    /// Share links are HMAC-signed
+    pub signed_share_links: bool,
+    /// Signed share links expire and can be opened once
+    pub expiring_share_links: bool,
+    /// Item extra keys are restricted
+    pub strict_item_schema: bool,
+    /// Empty syncs are rejected rather than clearing the cart
+    pub reject_empty_sync: bool,
+    /// Empty carts can't be checked out
+    pub block_empty_checkout: bool,
+    /// Syncs keep valid items and report the invalid ones
+    pub lenient_sync: bool,
+    /// Null and fractional JSON-RPC ids are rejected
+    pub strict_jsonrpc_ids: bool,
+    /// Params on parameterless methods are rejected
+    pub strict_params: bool,
+    /// Concurrent SSE sessions are capped
+    pub sse_connection_limit: bool,
+    /// Requests are rate limited per client IP
+    pub rate_limiting: bool,
+    /// Client IPs are taken from `X-Forwarded-For`
+    pub trust_proxy: bool,
+    /// Methods are refused until the client's session has been initialized
+    pub strict_init: bool,
+    /// Item names are HTML-escaped wherever they are displayed
+    pub escape_item_names: bool,
+    /// Shipping tiers were loaded for `shipping_estimate`
+    pub shipping: bool,
+    /// A regional tax table is available to `tax_estimate`
+    pub regional_tax: bool,
+    /// Items can be taxed at their category's rate
+    pub tax_categories: bool,
+    /// A recommendation map is available to `recommend`
+    pub recommendations: bool,
+    /// Exchange rates are available to `convert_total`
+    pub fx_conversion: bool,
+    /// Deprecated method and tool names are accepted
+    pub aliases: bool,
+}
+
+impl Features {
+    /// Derives the feature set from the configuration and the data loaded from it
+    pub fn new(config: &Config, catalog: &Catalog, loaded: LoadedCounts) -> Self {
+        Self {
+            catalog: !catalog.is_empty(),
+            inventory: catalog.tracks_stock(),
+            stock_holds: catalog.tracks_stock() && config.stock_hold_seconds.is_some(),
+            cart_expiry: config.cart_ttl_seconds.is_some(),
+            soft_delete: config.soft_delete_seconds.is_some(),
+            cart_history: config.cart_history_limit().is_some(),
+            persistence: config.cart_data_dir.is_some(),
+            simulated_delay: config.simulated_delay().is_some(),
+            widget_session_echo: config.echo_widget_session_id,
+            catalog_prices: config.price_source == PriceSource::Catalog,
+            snake_case_output: config.output_case == OutputCase::Snake,
+            reject_conflicting_extras: config.conflicting_extras == ConflictingExtras::Error,
+            fuzzy_matching: config.fuzzy_match_distance.is_some(),
+            coupons: loaded.coupons > 0,
+            tax: config.tax_rate.is_some_and(|rate| rate > 0.0),
+            admin: config.enable_admin && config.admin_token.is_some(),
+            asset_hot_reload: config.asset_hot_reload,
+            remote_widget: config.widget_url.is_some(),
+            ready_probes: config.ready_probes,
+            payment_links: config.payment_url_template.is_some(),
+            cart_sharing: config.share_base_url.is_some(),
+            signed_share_links: config.share_base_url.is_some() && config.share_secret.is_some(),
+            expiring_share_links: config.share_base_url.is_some()
+                && config.share_secret.is_some()
+                && config.share_link_ttl_seconds.is_some(),
+            strict_item_schema: config.strict_item_schema,
+            reject_empty_sync: config.reject_empty_sync,
+            block_empty_checkout: config.block_empty_checkout,
+            lenient_sync: config.lenient_sync,
+            strict_jsonrpc_ids: config.strict_jsonrpc_ids,
+            strict_params: config.strict_params,
+            sse_connection_limit: config.max_sse_connections.is_some(),
+            rate_limiting: config.rate_limit_per_minute.is_some(),
+            trust_proxy: config.trust_proxy,
+            strict_init: config.strict_init,
+            escape_item_names: config.escape_item_names,
+            shipping: loaded.shipping_tiers > 0,
+            regional_tax: loaded.tax_regions > 0,
+            tax_categories: loaded.tax_categories > 0,
+            recommendations: loaded.recommendations > 0,
+            fx_conversion: loaded.fx_rates > 0,
+            aliases: loaded.aliases > 0,
+        }
+    }
+}
+
+// =============================================================================
+// Helper Functions
+// =============================================================================
+
+/// Reads a non-empty string variable from the environment
+fn env_string(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Reads a boolean flag from the environment ("1", "true", "yes" or "on")
+fn env_flag(key: &str) -> bool {
+    env_string(key)
+        .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
+/// Parses a variable from the environment, warning when the value is malformed
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    let raw = env_string(key)?;
+    match raw.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            eprintln!("Ignoring invalid {}={:?}", key, raw);
+            None
+        }
+    }
+}
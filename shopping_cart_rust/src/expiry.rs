@@ -0,0 +1,111 @@
+//! Idle cart expiry
+//!
+//! With `CART_TTL_SECONDS` set, carts that nothing has changed for that long are
+//! evicted: their stock holds are released and their saved file is removed. A
+//! background sweeper runs the eviction periodically, and `POST /admin/sweep` runs
+//! the same sweep on demand.
+
+use crate::model::{AppState, SharedState};
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+/// When each cart was last changed
+#[derive(Debug, Default)]
+pub struct CartActivity {
+    /// How long a cart may sit unchanged; `None` disables expiry
+    ttl: Option<Duration>,
+
+    touched: DashMap<String, Instant>,
+}
+
+impl CartActivity {
+    /// Creates an activity log whose carts expire after `ttl`
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            touched: DashMap::new(),
+        }
+    }
+
+    /// Records that a cart changed just now; a no-op when expiry is disabled
+    pub fn touch(&self, cart_id: &str) {
+        if self.ttl.is_some() {
+            self.touched.insert(cart_id.to_string(), Instant::now());
+        }
+    }
+
+    /// Records that a cart last changed at `at`
+    #[cfg(test)]
+    pub fn touch_at(&self, cart_id: &str, at: Instant) {
+        self.touched.insert(cart_id.to_string(), at);
+    }
+
+    /// Returns the ids of carts idle for longer than the TTL
+    fn expired(&self) -> Vec<String> {
+        self.touched
+            .iter()
+            .filter(|entry| self.is_stale(entry.value()))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Returns true when the cart has been idle for longer than the TTL
+    fn is_expired(&self, cart_id: &str) -> bool {
+        self.touched
+            .get(cart_id)
+            .is_some_and(|touched| self.is_stale(&touched))
+    }
+
+    /// Forgets a cart unless it was touched since it expired
+    fn forget_expired(&self, cart_id: &str) {
+        self.touched
+            .remove_if(cart_id, |_, touched| self.is_stale(touched));
+    }
+
+    fn is_stale(&self, touched: &Instant) -> bool {
+        self.ttl
+            .is_some_and(|ttl| Instant::now().saturating_duration_since(*touched) > ttl)
+    }
+}
+
+/// Evicts every expired cart, returning how many carts were removed. Ids of carts
+/// already gone (checked out or deleted) are dropped without being counted.
+pub async fn sweep_expired_carts(state: &AppState) -> usize {
+    let mut evicted = 0;
+    for cart_id in state.cart_activity.expired() {
+        // Re-check under the cart's lock, so a cart touched since the scan keeps its writes
+        let removed = state.carts.remove_if(&cart_id, |cart_id, _| {
+            state.cart_activity.is_expired(cart_id)
+        });
+        state.cart_activity.forget_expired(&cart_id);
+        if removed.is_none() {
+            continue;
+        }
+        state.known_cart_ids.remove(&cart_id);
+        state.stock_holds.release(&cart_id);
+        // Best-effort: a stale file only means the cart comes back after a restart
+        let _ = state.persist_cart(&cart_id).await;
+        evicted += 1;
+    }
+    evicted
+}
+
+/// Sweeps expired carts in the background until shutdown
+pub fn spawn_sweeper(state: SharedState) -> Option<tokio::task::JoinHandle<()>> {
+    let ttl = state.config.cart_ttl()?;
+    let period = (ttl / 2).max(Duration::from_secs(1));
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let evicted = sweep_expired_carts(&state).await;
+                    if evicted > 0 {
+                        println!("Evicted {} expired carts", evicted);
+                    }
+                }
+                _ = state.shutdown_requested() => break,
+            }
+        }
+    }))
+}
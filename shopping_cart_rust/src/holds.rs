@@ -0,0 +1,141 @@
+//! Temporary stock holds
+//!
+//! With `STOCK_HOLD_SECONDS` set, adding a stock-tracked product reserves the
+//! cart's quantity of it for that long. Other carts see the reserved units as
+//! unavailable, so two carts can't both claim the last unit. Holds are released
+//! on checkout or when the cart is dropped, and otherwise expire; a background
+//! sweeper clears expired ones.
+
+use crate::model::SharedState;
+use dashmap::DashMap;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Units of a product reserved by a cart, and when the reservation lapses
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hold {
+    quantity: u32,
+    expires: Instant,
+}
+
+/// Stock reserved per (cart id, product name)
+#[derive(Debug, Default)]
+pub struct StockHolds {
+    /// How long a hold lasts; `None` disables holds
+    ttl: Option<Duration>,
+
+    holds: DashMap<(String, String), Hold>,
+
+    /// Held while checking free stock and placing holds, so two carts can't both
+    /// see the same units as free
+    reserving: Mutex<()>,
+}
+
+impl StockHolds {
+    /// Creates a hold store whose holds last `ttl`
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            holds: DashMap::new(),
+            reserving: Mutex::new(()),
+        }
+    }
+
+    /// Returns true when holds are placed at all
+    pub fn enabled(&self) -> bool {
+        self.ttl.is_some()
+    }
+
+    /// Makes a cart's holds exactly `wanted`, given as (product, quantity, stock):
+    /// each product is held afresh and holds on products not listed are released.
+    /// Fails without changing anything when a product lacks the free stock.
+    pub fn reserve(&self, cart_id: &str, wanted: &[(&str, u32, u32)]) -> Result<(), String> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let _reserving = self.reserving.lock().unwrap();
+        for (product, quantity, stock) in wanted {
+            let free = stock.saturating_sub(self.held_elsewhere(cart_id, product));
+            if *quantity > free {
+                return Err(format!(
+                    "Only {} of {} available; other carts are holding the rest",
+                    free, product
+                ));
+            }
+        }
+        self.holds.retain(|(holder, product), _| {
+            holder != cart_id || wanted.iter().any(|(wanted, _, _)| wanted == product)
+        });
+        for (product, quantity, _) in wanted {
+            self.place(cart_id, product, *quantity);
+        }
+        Ok(())
+    }
+
+    /// Reserves `quantity` units of `product` for a cart, replacing its previous hold
+    /// and restarting the clock. A zero quantity releases the hold.
+    fn place(&self, cart_id: &str, product: &str, quantity: u32) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        let key = (cart_id.to_string(), product.to_string());
+        if quantity == 0 {
+            self.holds.remove(&key);
+            return;
+        }
+        self.holds.insert(
+            key,
+            Hold {
+                quantity,
+                expires: Instant::now() + ttl,
+            },
+        );
+    }
+
+    /// Units of `product` reserved by carts other than `cart_id`
+    pub fn held_elsewhere(&self, cart_id: &str, product: &str) -> u32 {
+        let now = Instant::now();
+        self.holds
+            .iter()
+            .filter(|entry| {
+                let (holder, held) = entry.key();
+                holder != cart_id && held == product && entry.expires > now
+            })
+            .fold(0, |sum, entry| sum.saturating_add(entry.quantity))
+    }
+
+    /// Drops every hold a cart has placed
+    pub fn release(&self, cart_id: &str) {
+        self.holds.retain(|(holder, _), _| holder != cart_id);
+    }
+
+    /// Drops expired holds, returning how many were removed
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let before = self.holds.len();
+        self.holds.retain(|_, hold| hold.expires > now);
+        before.saturating_sub(self.holds.len())
+    }
+}
+
+/// Sweeps expired holds in the background until shutdown
+pub fn spawn_sweeper(state: SharedState) -> Option<tokio::task::JoinHandle<()>> {
+    let ttl = state.config.stock_hold()?;
+    let period = (ttl / 2).max(Duration::from_secs(1));
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let swept = state.stock_holds.sweep();
+                    if swept > 0 {
+                        println!("Released {} expired stock holds", swept);
+                    }
+                }
+                _ = state.shutdown_requested() => break,
+            }
+        }
+    }))
+}
@@ -1,20 +1,72 @@
 //! Shopping Cart Application Main Entry Point
 //! This application demonstrates a shopping cart widget integration with OpenAI
 
+mod aliases;
+mod catalog;
+mod config;
+mod expiry;
+mod holds;
 mod model;
+mod persist;
+mod pricing;
+mod qr;
+mod rate_limit;
+mod recommend;
+mod remote;
 mod router;
+mod server;
+mod share;
+mod shipping;
+mod tools;
+mod watcher;
 
-use model::AppState;
+use model::{AppState, SharedState};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use tools::ToolRegistry;
+
+/// Tools served alongside the built-ins; register your own here. A tool registered
+/// under a built-in's name replaces it.
+fn custom_tools() -> ToolRegistry {
+    ToolRegistry::default()
+}
 
 #[tokio::main]
 async fn main() {
     // Initialize application state
-    let state = Arc::new(AppState::new());
+    let state = Arc::new(AppState::new().with_tools(custom_tools()));
+
+    // Watch assets for changes during development (kept alive for the server's lifetime)
+    let _asset_watcher = if state.config.asset_hot_reload {
+        watcher::spawn_asset_watcher(state.clone())
+            .map_err(|e| eprintln!("Asset hot reload disabled: {}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    // Catch drift between advertised tool schemas and the input types
+    if state.config.startup_self_check {
+        let problems = router::mcp::self_check(&state);
+        for problem in &problems {
+            eprintln!("Self-check: {}", problem);
+        }
+        if problems.is_empty() {
+            println!("Self-check passed: tool examples match their inputs");
+        }
+    }
+
+    if state.config.simulate_delay_ms.is_some() && state.config.simulated_delay().is_none() {
+        eprintln!("SIMULATE_DELAY_MS ignored: release builds also need ALLOW_SIMULATED_DELAY=1");
+    }
+
+    // Release lapsed stock holds (kept alive for the server's lifetime)
+    let _hold_sweeper = holds::spawn_sweeper(state.clone());
+    // Evict carts idle for longer than CART_TTL_SECONDS
+    let _cart_sweeper = expiry::spawn_sweeper(state.clone());
 
     // Build application router with all routes and middleware
-    let app = router::create_app_router(state);
+    let app = router::create_app_router(state.clone());
 
     // Configure the server address
     let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
@@ -22,14 +74,53 @@ async fn main() {
 
     // Start the server
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let idle_timeout = state.config.http_idle_timeout();
+    server::serve(listener, app, idle_timeout, shutdown_signal(state)).await;
+
+    println!("Server stopped");
+}
+
+/// Resolves when the process receives Ctrl+C or an admin shutdown is requested
+async fn shutdown_signal(state: SharedState) {
+    let reason = tokio::select! {
+        _ = tokio::signal::ctrl_c() => "interrupt signal received".to_string(),
+        reason = state.shutdown_requested() => reason,
+    };
+    println!("Shutting down gracefully: {}", reason);
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::model::{AppState, CartItem, TOOL_NAME};
+    use crate::model::{AppState, Cart, CartItem, TOOL_NAME};
     use serde_json::json;
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_item_serialization_is_canonical() {
+        let mut forward = BTreeMap::new();
+        forward.insert("price".to_string(), json!(1.5));
+        forward.insert("category".to_string(), json!("fruit"));
+        forward.insert("description".to_string(), json!("Crisp"));
+        let reversed: Vec<(String, serde_json::Value)> =
+            forward.clone().into_iter().rev().collect();
+
+        let item = |extra| CartItem {
+            name: "Apple".into(),
+            quantity: 2,
+            extra,
+        };
+        let first = serde_json::to_string(&item(forward)).unwrap();
+        let second = serde_json::to_string(&item(reversed.into_iter().collect())).unwrap();
+        assert_eq!(first, second);
+
+        // Reordered input keys still produce the same bytes, and round-trip
+        let parsed: CartItem = serde_json::from_str(
+            r#"{"description":"Crisp","quantity":2,"category":"fruit","name":"Apple","price":1.5}"#,
+        )
+        .unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), first);
+        assert_eq!(serde_json::from_str::<CartItem>(&first).unwrap(), parsed);
+    }
 
     #[tokio::test]
     async fn test_state_manager_and_aggregation() {
@@ -40,9 +131,15 @@ mod tests {
         let initial_items = vec![CartItem {
             name: "Apple".into(),
             quantity: 2,
-            extra: HashMap::new(),
+            extra: BTreeMap::new(),
         }];
-        state.carts.insert(cart_id.into(), initial_items);
+        state.carts.insert(
+            cart_id.into(),
+            Cart {
+                items: initial_items,
+                ..Cart::default()
+            },
+        );
 
         // 2. Tool Call (Simulate Add)
         let args = json!({
@@ -55,10 +152,13 @@ mod tests {
 
         // Use the handler from the public MCP module
         use crate::router::mcp::handle_tool_call;
-        handle_tool_call(&state, TOOL_NAME, args).expect("Tool call failed");
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Tool call failed");
 
         // 3. Verify
-        let items = state.carts.get(cart_id).unwrap();
+        let cart = state.carts.get(cart_id).unwrap();
+        let items = &cart.items;
 
         let apple = items.iter().find(|i| i.name == "Apple").unwrap();
         assert_eq!(
@@ -79,5 +179,2119 @@ mod tests {
         let error = crate::model::rpc_error(json!(2), -1, "fail");
         assert_eq!(error["error"]["message"], "fail");
         assert_eq!(error["id"], 2);
+        assert!(error["error"].get("data").is_none());
+
+        let error = crate::model::rpc_error_with_data(json!(3), -1, "fail", Some(json!([1])));
+        assert_eq!(error["error"]["data"], json!([1]));
+    }
+
+    #[tokio::test]
+    async fn test_checkout_of_an_empty_cart() {
+        use crate::config::Config;
+        use crate::model::{Cart, CHECKOUT_TOOL_NAME, EMPTY_CHECKOUT_ERROR};
+        use crate::router::mcp::handle_tool_call;
+
+        let args = json!({ "cartId": "empty_cart" });
+
+        // By default an empty cart checks out with a zero-item receipt
+        let state = AppState::with_config(Config::default());
+        state.carts.insert("empty_cart".into(), Cart::default());
+        let result = handle_tool_call(&state, CHECKOUT_TOOL_NAME, args.clone())
+            .await
+            .expect("Checkout failed");
+        assert_eq!(result["structuredContent"]["receipt"]["items"], json!([]));
+        assert!(state.carts.get("empty_cart").is_none());
+
+        // With BLOCK_EMPTY_CHECKOUT it's refused and the cart is kept
+        let state = AppState::with_config(Config {
+            block_empty_checkout: true,
+            ..Config::default()
+        });
+        state.carts.insert("empty_cart".into(), Cart::default());
+        let err = handle_tool_call(&state, CHECKOUT_TOOL_NAME, args)
+            .await
+            .expect_err("Empty checkout should fail");
+        assert_eq!(err.message, EMPTY_CHECKOUT_ERROR);
+        assert!(state.carts.get("empty_cart").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_checkout_receipt_payment_url() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            payment_url_template: Some("https://pay.example.com/orders/{orderId}".into()),
+            ..Config::default()
+        });
+        let args = json!({ "cartId": "pay_cart", "items": [{ "name": "Apple" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(
+            &state,
+            crate::model::CHECKOUT_TOOL_NAME,
+            json!({ "cartId": "pay_cart" }),
+        )
+        .await
+        .expect("Checkout failed");
+
+        let receipt = &result["structuredContent"]["receipt"];
+        let order_id = receipt["orderId"].as_str().unwrap();
+        assert_eq!(
+            receipt["paymentUrl"],
+            format!("https://pay.example.com/orders/{}", order_id)
+        );
+        assert_eq!(receipt["items"][0]["name"], "Apple");
+
+        // Without a template the field is omitted
+        let state = AppState::with_config(Config::default());
+        let args = json!({ "cartId": "plain_cart", "items": [{ "name": "Apple" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        let result = handle_tool_call(
+            &state,
+            crate::model::CHECKOUT_TOOL_NAME,
+            json!({ "cartId": "plain_cart" }),
+        )
+        .await
+        .expect("Checkout failed");
+        assert!(result["structuredContent"]["receipt"]
+            .get("paymentUrl")
+            .is_none());
+
+        // Checkout never mints an id for a cart that cannot exist
+        let err = handle_tool_call(&state, crate::model::CHECKOUT_TOOL_NAME, json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "cartId required for checkout");
+
+        // Templates that don't resolve to an http(s) URL are rejected
+        assert!(crate::model::resolve_payment_url("not a url/{orderId}", "o1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_strict_item_schema() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            strict_item_schema: true,
+            ..Config::default()
+        });
+
+        // Only allowlisted extras are accepted
+        let args = json!({
+            "cartId": "strict_cart",
+            "items": [{ "name": "Apple", "price": 1.5, "category": "fruit" }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Allowed item rejected");
+
+        // Unknown extras are rejected and listed
+        let args = json!({
+            "cartId": "strict_cart",
+            "items": [{ "name": "Pear", "color": "green", "origin": "FR" }]
+        });
+        let err = handle_tool_call(&state, TOOL_NAME, args).await.unwrap_err();
+        assert!(
+            err.message.contains("color, origin"),
+            "unexpected error: {}",
+            err
+        );
+        assert_eq!(state.carts.get("strict_cart").unwrap().items.len(), 1);
+
+        // Permissive mode keeps accepting arbitrary extras
+        let state = AppState::with_config(Config::default());
+        let args = json!({ "items": [{ "name": "Pear", "color": "green" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Permissive mode rejected item");
+    }
+
+    #[tokio::test]
+    async fn test_validate_cart_tool() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::Config;
+        use crate::model::VALIDATE_CART_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let catalog = Catalog::new(vec![Product {
+            name: "Apple".into(),
+            price: Some(1.0),
+            stock: None,
+            category: None,
+        }]);
+        let state = AppState::with_config(Config::default()).with_catalog(catalog);
+        let args = json!({
+            "cartId": "check_cart",
+            "items": [{ "name": "Apple", "price": 2.0 }, { "name": "Kiwi" }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(
+            &state,
+            VALIDATE_CART_TOOL_NAME,
+            json!({ "cartId": "check_cart" }),
+        )
+        .await
+        .expect("Validation failed");
+
+        let report = &result["structuredContent"]["validation"];
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["unknownProducts"], json!(["Kiwi"]));
+        assert_eq!(report["priceMismatches"][0]["catalogPrice"], 1.0);
+        // Validation never mutates the cart
+        assert_eq!(state.carts.get("check_cart").unwrap().items.len(), 2);
+
+        let err = handle_tool_call(
+            &state,
+            VALIDATE_CART_TOOL_NAME,
+            json!({ "cartId": "no_such_cart" }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.message, "Cart not found: no_such_cart");
+    }
+
+    #[tokio::test]
+    async fn test_remaining_stock_decreases_as_items_are_added() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let catalog = Catalog::new(vec![Product {
+            name: "Apple".into(),
+            price: Some(1.0),
+            stock: Some(3),
+            category: None,
+        }]);
+        let state = AppState::with_config(Config::default()).with_catalog(catalog);
+
+        let add = |quantity: u32| {
+            json!({
+                "cartId": "stock_cart",
+                "items": [{ "name": "Apple", "quantity": quantity }, { "name": "Kiwi" }]
+            })
+        };
+
+        let result = handle_tool_call(&state, TOOL_NAME, add(1))
+            .await
+            .expect("Add failed");
+        let availability = &result["structuredContent"]["availability"];
+        assert_eq!(availability[0]["remainingStock"], 2);
+        // Products without tracked stock omit the field
+        assert!(availability[1].get("remainingStock").is_none());
+
+        let result = handle_tool_call(&state, TOOL_NAME, add(5))
+            .await
+            .expect("Add failed");
+        assert_eq!(
+            result["structuredContent"]["availability"][0]["remainingStock"],
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_share_and_load_shared_cart() {
+        use crate::config::Config;
+        use crate::model::{LOAD_SHARED_CART_TOOL_NAME, SHARE_CART_TOOL_NAME};
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            share_base_url: Some("https://shop.example/cart".into()),
+            share_secret: Some("secret".into()),
+            ..Config::default()
+        });
+        let args = json!({ "cartId": "shared_cart", "items": [{ "name": "Apple" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(
+            &state,
+            SHARE_CART_TOOL_NAME,
+            json!({ "cartId": "shared_cart" }),
+        )
+        .await
+        .expect("Share failed");
+        let url = result["structuredContent"]["shareUrl"].as_str().unwrap();
+        let signature = result["structuredContent"]["signature"].as_str().unwrap();
+        assert!(url.contains("cartId=shared_cart"));
+        assert!(url.ends_with(&format!("sig={}", signature)));
+        assert!(crate::share::verify(
+            "shared_cart",
+            None,
+            signature,
+            "secret"
+        ));
+
+        let result = handle_tool_call(
+            &state,
+            LOAD_SHARED_CART_TOOL_NAME,
+            json!({ "cartId": "shared_cart", "signature": signature }),
+        )
+        .await
+        .expect("Load failed");
+        assert_eq!(result["structuredContent"]["items"][0]["name"], "Apple");
+
+        let err = handle_tool_call(
+            &state,
+            LOAD_SHARED_CART_TOOL_NAME,
+            json!({ "cartId": "shared_cart", "signature": "00" }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.message, "Invalid share link signature");
+    }
+
+    #[tokio::test]
+    async fn test_expiring_share_links_are_single_use() {
+        use crate::config::Config;
+        use crate::model::{LOAD_SHARED_CART_TOOL_NAME, SHARE_CART_TOOL_NAME};
+        use crate::router::mcp::handle_tool_call;
+        use crate::share::{sign, unix_now, Claims};
+
+        let state = AppState::with_config(Config {
+            share_base_url: Some("https://shop.example/cart".into()),
+            share_secret: Some("secret".into()),
+            share_link_ttl_seconds: Some(300),
+            ..Config::default()
+        });
+        let args = json!({ "cartId": "once_cart", "items": [{ "name": "Apple" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(
+            &state,
+            SHARE_CART_TOOL_NAME,
+            json!({ "cartId": "once_cart" }),
+        )
+        .await
+        .expect("Share failed");
+        let shared = &result["structuredContent"];
+        let url = shared["shareUrl"].as_str().unwrap();
+        assert!(url.contains(&format!("exp={}", shared["exp"])));
+        let load = json!({
+            "cartId": "once_cart",
+            "signature": shared["signature"],
+            "exp": shared["exp"],
+            "nonce": shared["nonce"]
+        });
+
+        // A fresh link opens once; replaying its nonce is refused
+        handle_tool_call(&state, LOAD_SHARED_CART_TOOL_NAME, load.clone())
+            .await
+            .expect("Fresh link was refused");
+        let err = handle_tool_call(&state, LOAD_SHARED_CART_TOOL_NAME, load)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Share link has already been used");
+
+        let expired = Claims {
+            exp: unix_now() - 1,
+            nonce: "stale".into(),
+        };
+        let load = json!({
+            "cartId": "once_cart",
+            "signature": sign("once_cart", Some(&expired), "secret"),
+            "exp": expired.exp,
+            "nonce": expired.nonce
+        });
+        let err = handle_tool_call(&state, LOAD_SHARED_CART_TOOL_NAME, load)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Share link has expired");
+    }
+
+    #[tokio::test]
+    async fn test_simulated_delay() {
+        use crate::config::Config;
+        use crate::model::VALIDATE_CART_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+        use std::time::{Duration, Instant};
+
+        let delay = Duration::from_millis(300);
+        let timed_call = |state: AppState| async move {
+            let started = Instant::now();
+            // Even a failing call is delayed
+            let _ = handle_tool_call(&state, VALIDATE_CART_TOOL_NAME, json!({ "cartId": "slow" }))
+                .await;
+            started.elapsed()
+        };
+
+        // Tests are debug builds, so no confirmation is needed
+        let state = AppState::with_config(Config {
+            simulate_delay_ms: Some(300),
+            ..Config::default()
+        });
+        assert!(timed_call(state).await >= delay);
+
+        let state = AppState::with_config(Config::default());
+        assert!(timed_call(state).await < delay);
+    }
+
+    #[tokio::test]
+    async fn test_persistence_write_failures() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+        use std::sync::atomic::Ordering;
+
+        let dir = std::env::temp_dir().join(format!("carts_{}", uuid::Uuid::new_v4().simple()));
+        let args = json!({ "cartId": "saved_cart", "items": [{ "name": "Apple" }] });
+
+        // Saved carts are restored by the next process
+        let config = Config {
+            cart_data_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let state = AppState::with_config(config.clone());
+        handle_tool_call(&state, TOOL_NAME, args.clone())
+            .await
+            .expect("Add failed");
+        let restored = AppState::with_config(config);
+        assert_eq!(
+            restored.carts.get("saved_cart").unwrap().items[0].name,
+            "Apple"
+        );
+
+        // A data "directory" that is really a file makes every write fail
+        let blocked = dir.join("blocked");
+        std::fs::write(&blocked, "not a directory").unwrap();
+        let state = AppState::with_config(Config {
+            cart_data_dir: Some(blocked.clone()),
+            ..Config::default()
+        });
+        let result = handle_tool_call(&state, TOOL_NAME, args.clone())
+            .await
+            .expect("Best-effort add failed");
+        assert_eq!(result["structuredContent"]["items"][0]["name"], "Apple");
+        assert_eq!(state.persist_failures.load(Ordering::Relaxed), 1);
+
+        // Strict mode reports the failure, though the cart still changed in memory
+        let state = AppState::with_config(Config {
+            cart_data_dir: Some(blocked),
+            strict_persistence: true,
+            ..Config::default()
+        });
+        let err = handle_tool_call(&state, TOOL_NAME, args).await.unwrap_err();
+        assert!(err
+            .message
+            .starts_with("Cart saved_cart was changed but could not be saved"));
+        assert_eq!(state.persist_failures.load(Ordering::Relaxed), 1);
+        assert!(state.carts.contains_key("saved_cart"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves_of_one_cart() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+        use std::sync::atomic::Ordering;
+
+        let dir = std::env::temp_dir().join(format!("carts_{}", uuid::Uuid::new_v4().simple()));
+        let state = AppState::with_config(Config {
+            cart_data_dir: Some(dir.clone()),
+            strict_persistence: true,
+            ..Config::default()
+        });
+        let args = json!({ "cartId": "busy_cart", "items": [{ "name": "Apple" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        // Overlapping saves must not trip over each other's temporary files
+        let saves = (0..160).map(|_| state.persist_cart("busy_cart"));
+        for saved in futures_util::future::join_all(saves).await {
+            saved.expect("Save failed");
+        }
+        assert_eq!(state.persist_failures.load(Ordering::Relaxed), 0);
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cart_history_records_mutations_in_order() {
+        use crate::config::Config;
+        use crate::model::{APPLY_PATCH_TOOL_NAME, CART_HISTORY_TOOL_NAME, CHECKOUT_TOOL_NAME};
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            cart_history_len: Some(4),
+            ..Config::default()
+        });
+        let cart = json!({ "cartId": "log_cart" });
+        let add = |name: &str| json!({ "cartId": "log_cart", "items": [{ "name": name }] });
+        handle_tool_call(&state, TOOL_NAME, add("Apple"))
+            .await
+            .expect("Add failed");
+        handle_tool_call(&state, TOOL_NAME, add("Bread"))
+            .await
+            .expect("Add failed");
+        let patch = json!({
+            "cartId": "log_cart",
+            "patch": [{ "op": "remove", "path": "/0" }]
+        });
+        handle_tool_call(&state, APPLY_PATCH_TOOL_NAME, patch)
+            .await
+            .expect("Patch failed");
+
+        let result = handle_tool_call(&state, CART_HISTORY_TOOL_NAME, cart.clone())
+            .await
+            .expect("History failed");
+        let history = result["structuredContent"]["history"].as_array().unwrap();
+        let entries: Vec<(&str, &str)> = history
+            .iter()
+            .map(|e| (e["action"].as_str().unwrap(), e["detail"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("add", "1x Apple"),
+                ("add", "1x Bread"),
+                ("set", "1x Bread"),
+                ("remove", "Apple")
+            ]
+        );
+        assert!(history[0]["at"].as_u64().unwrap() <= history[3]["at"].as_u64().unwrap());
+
+        // The log is bounded, dropping the oldest entries, and checkout closes it
+        let result = handle_tool_call(&state, CHECKOUT_TOOL_NAME, cart)
+            .await
+            .expect("Checkout failed");
+        let history = result["structuredContent"]["receipt"]["history"]
+            .as_array()
+            .unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[0]["detail"], "1x Bread");
+        assert_eq!(history[3]["action"], "checkout");
+
+        // Off by default
+        let state = AppState::with_config(Config::default());
+        handle_tool_call(&state, TOOL_NAME, add("Apple"))
+            .await
+            .expect("Add failed");
+        let err = handle_tool_call(
+            &state,
+            CART_HISTORY_TOOL_NAME,
+            json!({ "cartId": "log_cart" }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Cart history is not enabled; set CART_HISTORY_LEN"
+        );
+        assert!(state.carts.get("log_cart").unwrap().history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete_and_undo_remove() {
+        use crate::config::Config;
+        use crate::model::{APPLY_PATCH_TOOL_NAME, UNDO_REMOVE_TOOL_NAME};
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            soft_delete_seconds: Some(300),
+            ..Config::default()
+        });
+        let args = json!({
+            "cartId": "undo_cart",
+            "items": [
+                { "name": "Apple", "quantity": 2, "price": 1.0 },
+                { "name": "Bread", "quantity": 1, "price": 3.0 },
+                { "name": "Cheese", "quantity": 1, "price": 5.0 }
+            ]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let patch = |ops: serde_json::Value| json!({ "cartId": "undo_cart", "patch": ops });
+        let undo = json!({ "cartId": "undo_cart" });
+
+        // Removing a line and zeroing another keeps both aside, out of the totals
+        handle_tool_call(
+            &state,
+            APPLY_PATCH_TOOL_NAME,
+            patch(json!([{ "op": "remove", "path": "/1" }])),
+        )
+        .await
+        .expect("Remove failed");
+        let result = handle_tool_call(
+            &state,
+            APPLY_PATCH_TOOL_NAME,
+            patch(json!([{ "op": "replace", "path": "/1/quantity", "value": 0 }])),
+        )
+        .await
+        .expect("Zeroing failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["items"].as_array().unwrap().len(), 1);
+        assert_eq!(content["subtotal"], 2.0);
+        assert_eq!(content["removedItems"][0]["name"], "Bread");
+        assert_eq!(content["removedItems"][0]["removed"], true);
+        assert_eq!(content["removedItems"][1]["name"], "Cheese");
+        assert_eq!(content["removedItems"][1]["quantity"], 1);
+
+        // Undo restores the most recent removal first, with its quantity
+        let result = handle_tool_call(&state, UNDO_REMOVE_TOOL_NAME, undo.clone())
+            .await
+            .expect("Undo failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["items"][1]["name"], "Cheese");
+        assert_eq!(content["items"][1]["quantity"], 1);
+        assert_eq!(content["subtotal"], 7.0);
+
+        handle_tool_call(&state, UNDO_REMOVE_TOOL_NAME, undo.clone())
+            .await
+            .expect("Second undo failed");
+        let err = handle_tool_call(&state, UNDO_REMOVE_TOOL_NAME, undo.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Nothing to undo in cart undo_cart");
+        assert_eq!(state.carts.get("undo_cart").unwrap().items.len(), 3);
+
+        // Without SOFT_DELETE_SECONDS removals are final and undo is unavailable
+        let state = AppState::with_config(Config::default());
+        let err = handle_tool_call(&state, UNDO_REMOVE_TOOL_NAME, undo)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Undo is not enabled; set SOFT_DELETE_SECONDS");
+    }
+
+    #[tokio::test]
+    async fn test_apply_patch_tool() {
+        use crate::config::Config;
+        use crate::model::APPLY_PATCH_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({ "cartId": "patch_cart", "items": [{ "name": "Apple" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let patch = |ops: serde_json::Value| json!({ "cartId": "patch_cart", "patch": ops });
+
+        // Replace a quantity
+        let result = handle_tool_call(
+            &state,
+            APPLY_PATCH_TOOL_NAME,
+            patch(json!([{ "op": "replace", "path": "/0/quantity", "value": 4 }])),
+        )
+        .await
+        .expect("Replace failed");
+        assert_eq!(result["structuredContent"]["items"][0]["quantity"], 4);
+
+        // Append an item
+        let result = handle_tool_call(
+            &state,
+            APPLY_PATCH_TOOL_NAME,
+            patch(json!([{ "op": "add", "path": "/-", "value": { "name": "Bread" } }])),
+        )
+        .await
+        .expect("Add op failed");
+        assert_eq!(result["structuredContent"]["items"][1]["name"], "Bread");
+        assert_eq!(result["structuredContent"]["items"][1]["quantity"], 1);
+
+        // Invalid results and unsupported ops are rejected without touching the cart
+        let err = handle_tool_call(
+            &state,
+            APPLY_PATCH_TOOL_NAME,
+            patch(json!([{ "op": "replace", "path": "/0/name", "value": 5 }])),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.message.starts_with("Patch produced invalid items"));
+
+        let err = handle_tool_call(
+            &state,
+            APPLY_PATCH_TOOL_NAME,
+            patch(json!([{ "op": "move", "from": "/0", "path": "/1" }])),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Only add, remove and replace operations are supported"
+        );
+        assert_eq!(state.carts.get("patch_cart").unwrap().items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys_expire() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let args = json!({
+            "cartId": "retry_cart",
+            "idempotencyKey": "req-1",
+            "items": [{ "name": "Apple" }]
+        });
+
+        // Within the window a retry is deduplicated
+        let state = AppState::with_config(Config::default());
+        for _ in 0..2 {
+            handle_tool_call(&state, TOOL_NAME, args.clone())
+                .await
+                .expect("Add failed");
+        }
+        assert_eq!(state.carts.get("retry_cart").unwrap().items[0].quantity, 1);
+
+        // The key is scoped to its cart, so another cart's add with it still runs
+        let other_cart = json!({
+            "cartId": "other_cart",
+            "idempotencyKey": "req-1",
+            "items": [{ "name": "Apple" }]
+        });
+        handle_tool_call(&state, TOOL_NAME, other_cart)
+            .await
+            .expect("Add failed");
+        assert!(state.carts.get("other_cart").is_some());
+
+        // Concurrent retries run the add once; the others replay or are told to wait
+        let race = json!({
+            "cartId": "race_cart",
+            "idempotencyKey": "req-2",
+            "items": [{ "name": "Apple" }]
+        });
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _ = runtime.block_on(handle_tool_call(&state, TOOL_NAME, race.clone()));
+                });
+            }
+        });
+        assert_eq!(state.carts.get("race_cart").unwrap().items[0].quantity, 1);
+
+        // Outside the window the key is forgotten and the repeat adds again
+        let state = AppState::with_config(Config {
+            idempotency_ttl_seconds: Some(0),
+            ..Config::default()
+        });
+        for _ in 0..2 {
+            handle_tool_call(&state, TOOL_NAME, args.clone())
+                .await
+                .expect("Add failed");
+        }
+        assert_eq!(state.carts.get("retry_cart").unwrap().items[0].quantity, 2);
+        assert!(state.idempotency_keys.len() <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_reports_line_and_unit_counts() {
+        use crate::config::Config;
+        use crate::model::CHECKOUT_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({
+            "cartId": "count_cart",
+            "items": [{ "name": "Apple", "quantity": 2 }, { "name": "Bread", "quantity": 3 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(
+            &state,
+            CHECKOUT_TOOL_NAME,
+            json!({ "cartId": "count_cart" }),
+        )
+        .await
+        .expect("Checkout failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["itemsCheckedOut"], 2);
+        assert_eq!(content["unitsCheckedOut"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_catalog_price_authority() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::{Config, PriceSource};
+        use crate::router::mcp::handle_tool_call;
+
+        let catalog = Catalog::new(vec![Product {
+            name: "Apple".into(),
+            price: Some(1.25),
+            stock: None,
+            category: None,
+        }]);
+        let state = AppState::with_config(Config {
+            price_source: PriceSource::Catalog,
+            ..Config::default()
+        })
+        .with_catalog(catalog);
+
+        let args = json!({ "cartId": "auth_cart", "items": [{ "name": "Apple", "price": 0.01 }] });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert_eq!(result["structuredContent"]["items"][0]["price"], 1.25);
+
+        let args = json!({ "cartId": "auth_cart", "items": [{ "name": "Mango", "price": 2.0 }] });
+        let err = handle_tool_call(&state, TOOL_NAME, args).await.unwrap_err();
+        assert_eq!(err.message, "Product not in catalog: Mango");
+        assert_eq!(state.carts.get("auth_cart").unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_render_cart_tool_embeds_escaped_cart() {
+        use crate::config::Config;
+        use crate::model::RENDER_CART_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let assets = std::env::temp_dir().join(format!("render_cart_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(
+            assets.join("shopping-cart.html"),
+            "<html><body><div id=\"root\"></div></body></html>",
+        )
+        .unwrap();
+
+        let mut state = AppState::with_config(Config::default());
+        state.assets_dir = assets.clone();
+
+        let args = json!({
+            "cartId": "html_cart",
+            "items": [{ "name": "</script><b>Apple</b>", "quantity": 2 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(
+            &state,
+            RENDER_CART_TOOL_NAME,
+            json!({ "cartId": "html_cart" }),
+        )
+        .await
+        .expect("Render failed");
+        let html = result["content"][0]["resource"]["text"].as_str().unwrap();
+        std::fs::remove_dir_all(&assets).unwrap();
+
+        // The data script sits inside the body and nothing escapes it
+        let open = "<script type=\"application/json\" id=\"cart-data\">";
+        let start = html.find(open).expect("cart data missing") + open.len();
+        let end = start + html[start..].find("</script>").unwrap();
+        assert!(html[end..].starts_with("</script></body></html>"));
+        assert_eq!(html.matches("</script>").count(), 1);
+
+        let embedded: serde_json::Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(embedded["cartId"], "html_cart");
+        assert_eq!(embedded["items"][0]["name"], "</script><b>Apple</b>");
+        assert_eq!(embedded["items"][0]["quantity"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_item_names_are_sanitized_for_display() {
+        use crate::config::Config;
+        use crate::model::RENDER_CART_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let assets = std::env::temp_dir().join(format!("sanitize_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(
+            assets.join("shopping-cart.html"),
+            "<html><body></body></html>",
+        )
+        .unwrap();
+
+        let mut state = AppState::with_config(Config {
+            escape_item_names: true,
+            ..Config::default()
+        });
+        state.assets_dir = assets.clone();
+
+        let args = json!({
+            "cartId": "xss_cart",
+            "items": [{ "name": "<script>alert(1)</script>Apple\u{7}\n" }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        let result = handle_tool_call(
+            &state,
+            RENDER_CART_TOOL_NAME,
+            json!({ "cartId": "xss_cart" }),
+        )
+        .await
+        .expect("Render failed");
+        std::fs::remove_dir_all(&assets).unwrap();
+
+        let html = result["content"][0]["resource"]["text"].as_str().unwrap();
+        assert!(!html.contains("<script>alert"));
+        // The embedded JSON escapes the `&` of each entity as well
+        assert!(html.contains("lt;script"));
+
+        // Names are stored raw and escaped for display, so round trips don't compound
+        let name = "<script>alert(1)</script>Apple";
+        assert_eq!(state.carts.get("xss_cart").unwrap().items[0].name, name);
+        let args = json!({ "cartId": "xss_cart", "items": [{ "name": name }] });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert_eq!(result["structuredContent"]["items"][0]["name"], name);
+        assert_eq!(result["structuredContent"]["items"][0]["quantity"], 2);
+        let text = result["content"][0]["text"].as_str().unwrap();
+        assert!(!text.contains('<'));
+    }
+
+    #[tokio::test]
+    async fn test_cart_metadata_round_trips() {
+        use crate::model::{MAX_CART_METADATA_BYTES, SET_CART_METADATA_TOOL_NAME};
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::new();
+        let args = json!({ "cartId": "meta_cart", "items": [{ "name": "Tea" }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let set = json!({
+            "cartId": "meta_cart",
+            "metadata": { "table": 12, "note": "No sugar" }
+        });
+        let result = handle_tool_call(&state, SET_CART_METADATA_TOOL_NAME, set)
+            .await
+            .expect("Set failed");
+        assert_eq!(
+            result["structuredContent"]["metadata"],
+            json!({ "table": 12, "note": "No sugar" })
+        );
+
+        // Null removes a key; the rest survives later tool calls
+        let unset = json!({ "cartId": "meta_cart", "metadata": { "note": null } });
+        handle_tool_call(&state, SET_CART_METADATA_TOOL_NAME, unset)
+            .await
+            .expect("Unset failed");
+        let args = json!({ "cartId": "meta_cart", "items": [{ "name": "Tea" }] });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert_eq!(
+            result["structuredContent"]["metadata"],
+            json!({ "table": 12 })
+        );
+
+        let huge = json!({
+            "cartId": "meta_cart",
+            "metadata": { "blob": "x".repeat(MAX_CART_METADATA_BYTES) }
+        });
+        assert!(handle_tool_call(&state, SET_CART_METADATA_TOOL_NAME, huge)
+            .await
+            .is_err());
+        assert_eq!(state.carts.get("meta_cart").unwrap().metadata.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shipping_estimate_tiers() {
+        use crate::config::Config;
+        use crate::model::SHIPPING_ESTIMATE_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+        use crate::shipping::ShippingTier;
+
+        let tier = |name: &str, cost: f64, min_subtotal: Option<f64>| ShippingTier {
+            name: name.into(),
+            cost,
+            min_subtotal,
+            max_weight: None,
+        };
+        let state = AppState::with_config(Config::default()).with_shipping_tiers(vec![
+            tier("Free", 0.0, Some(50.0)),
+            tier("Standard", 5.0, None),
+        ]);
+
+        let args = json!({
+            "cartId": "ship_cart",
+            "items": [{ "name": "Book", "quantity": 2, "price": 20.0 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        let estimate = json!({ "cartId": "ship_cart" });
+        let result = handle_tool_call(&state, SHIPPING_ESTIMATE_TOOL_NAME, estimate.clone())
+            .await
+            .expect("Estimate failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["tier"], "Standard");
+        assert_eq!(content["shippingCost"], 5.0);
+        assert_eq!(content["freeShipping"], false);
+
+        // Crossing the threshold qualifies for free shipping
+        let args = json!({
+            "cartId": "ship_cart",
+            "items": [{ "name": "Book", "quantity": 1, "price": 20.0 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        let result = handle_tool_call(&state, SHIPPING_ESTIMATE_TOOL_NAME, estimate)
+            .await
+            .expect("Estimate failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["tier"], "Free");
+        assert_eq!(content["shippingCost"], 0.0);
+        assert_eq!(content["freeShipping"], true);
+    }
+
+    #[tokio::test]
+    async fn test_tax_estimate_by_region() {
+        use crate::config::Config;
+        use crate::model::TAX_ESTIMATE_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default()).with_tax_table(&[("CA", 0.0725)]);
+        let args = json!({
+            "cartId": "tax_cart",
+            "items": [{ "name": "Lamp", "quantity": 2, "price": 50.0 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let known = json!({ "cartId": "tax_cart", "region": "ca" });
+        let result = handle_tool_call(&state, TAX_ESTIMATE_TOOL_NAME, known)
+            .await
+            .expect("Estimate failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["region"], "CA");
+        assert_eq!(content["taxRate"], 0.0725);
+        assert_eq!(content["tax"], 7.25);
+        assert_eq!(content["total"], 107.25);
+        assert!(content.get("warnings").is_none());
+
+        let unknown = json!({ "cartId": "tax_cart", "region": "ZZ" });
+        let result = handle_tool_call(&state, TAX_ESTIMATE_TOOL_NAME, unknown)
+            .await
+            .expect("Estimate failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["tax"], 0.0);
+        assert_eq!(content["total"], 100.0);
+        assert_eq!(
+            content["warnings"],
+            json!(["No tax rate for region ZZ; assuming 0"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_convert_total_to_display_currencies() {
+        use crate::config::Config;
+        use crate::model::CONVERT_TOTAL_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default())
+            .with_fx_rates(&[("EUR", 0.92), ("JPY", 151.37)]);
+        let args = json!({
+            "cartId": "fx_cart",
+            "items": [{ "name": "Lamp", "quantity": 2, "price": 12.5 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let args = json!({ "cartId": "fx_cart", "currencies": ["eur", "JPY", "XYZ"] });
+        let result = handle_tool_call(&state, CONVERT_TOTAL_TOOL_NAME, args)
+            .await
+            .expect("Convert failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["total"], 25.0);
+        assert_eq!(content["currencyInfo"]["code"], "USD");
+
+        let conversions = content["conversions"].as_array().unwrap();
+        assert_eq!(conversions.len(), 2);
+        assert_eq!(conversions[0]["currency"], "EUR");
+        assert_eq!(conversions[0]["total"], 23.0);
+        // Yen has no minor unit, so the amount is whole
+        assert_eq!(conversions[1]["currency"], "JPY");
+        assert_eq!(conversions[1]["total"], 3784.0);
+        assert_eq!(
+            content["warnings"],
+            json!(["No exchange rate for XYZ; skipped"])
+        );
+
+        // Totals in a currency without a minor unit are whole too
+        let state = AppState::with_config(Config {
+            currency: Some("JPY".into()),
+            ..Config::default()
+        });
+        let args = json!({ "cartId": "yen_cart", "items": [{ "name": "Tea", "price": 99.6 }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        let args = json!({ "cartId": "yen_cart", "currencies": [] });
+        let result = handle_tool_call(&state, CONVERT_TOTAL_TOOL_NAME, args)
+            .await
+            .expect("Convert failed");
+        assert_eq!(
+            result["structuredContent"]["currencyInfo"]["decimalPlaces"],
+            0
+        );
+        assert_eq!(result["structuredContent"]["total"], 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_tools_reject_invalid_cart_ids() {
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(crate::config::Config::default());
+        let add = |cart_id: String| json!({ "cartId": cart_id, "items": [{ "name": "Tea" }] });
+
+        let err = handle_tool_call(&state, TOOL_NAME, add("x".repeat(129)))
+            .await
+            .expect_err("Over-long id was accepted");
+        assert_eq!(err.message, "cartId is longer than 128 characters");
+
+        let err = handle_tool_call(&state, TOOL_NAME, add("../etc".into()))
+            .await
+            .expect_err("Illegal characters were accepted");
+        assert_eq!(
+            err.message,
+            "cartId may only contain letters, digits, '-' and '_'"
+        );
+        assert!(state.carts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_within_budget() {
+        use crate::config::Config;
+        use crate::model::ADD_WITHIN_BUDGET_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let add = |quantity: u32| {
+            json!({
+                "cartId": "budget_cart",
+                "budget": 30.0,
+                "items": [{ "name": "Mug", "quantity": quantity, "price": 8.0 }]
+            })
+        };
+
+        let result = handle_tool_call(&state, ADD_WITHIN_BUDGET_TOOL_NAME, add(2))
+            .await
+            .expect("Add within budget failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["subtotal"], 16.0);
+        assert_eq!(content["remainingBudget"], 14.0);
+
+        // Two more would reach 32, so nothing is added
+        let err = handle_tool_call(&state, ADD_WITHIN_BUDGET_TOOL_NAME, add(2))
+            .await
+            .expect_err("Over-budget add was applied");
+        assert_eq!(
+            err.message,
+            "Adding these items would exceed the budget by 2.00"
+        );
+        assert_eq!(err.data.unwrap()["overage"], 2.0);
+        assert_eq!(state.carts.get("budget_cart").unwrap().items[0].quantity, 2);
+
+        // A refused add to a new cart doesn't leave an empty one behind
+        let mut over = add(4);
+        over["cartId"] = json!("new_budget_cart");
+        handle_tool_call(&state, ADD_WITHIN_BUDGET_TOOL_NAME, over)
+            .await
+            .expect_err("Over-budget add was applied");
+        assert!(state.carts.get("new_budget_cart").is_none());
+        assert!(!state.known_cart_ids.contains("new_budget_cart"));
+    }
+
+    #[test]
+    fn test_cart_map_handles_many_inserts() {
+        use crate::model::{Cart, CartHasher, CartMap};
+
+        let carts = CartMap::with_hasher(CartHasher::default());
+        for i in 0..10_000 {
+            carts.insert(format!("cart_{}", i), Cart::default());
+        }
+        assert_eq!(carts.len(), 10_000);
+        assert!(carts.contains_key("cart_9999"));
+        assert!(carts.remove("cart_0").is_some());
+        assert_eq!(carts.len(), 9_999);
+    }
+
+    #[test]
+    fn test_with_cart_mut_is_atomic() {
+        use crate::config::Config;
+        use crate::model::{update_cart_with_new_items, CartItem};
+
+        let state = AppState::with_config(Config::default());
+        let tea = CartItem {
+            name: "Tea".into(),
+            quantity: 1,
+            extra: Default::default(),
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..500 {
+                        state.with_cart_mut("busy_cart", |cart| {
+                            update_cart_with_new_items(&mut cart.items, vec![tea.clone()]);
+                            cart.coupons.push(String::new());
+                        });
+                    }
+                });
+            }
+        });
+
+        let cart = state.carts.get("busy_cart").unwrap();
+        assert_eq!(cart.items.len(), 1);
+        assert_eq!(cart.items[0].quantity, 4_000);
+        assert_eq!(cart.coupons.len(), 4_000);
+        assert!(state
+            .with_existing_cart_mut("missing_cart", |_| ())
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_coupons_per_cart_are_capped() {
+        use crate::config::Config;
+        use crate::model::APPLY_COUPON_TOOL_NAME;
+        use crate::pricing::{Coupon, CouponKind};
+        use crate::router::mcp::handle_tool_call;
+
+        let coupon = |code: &str| Coupon {
+            code: code.into(),
+            kind: CouponKind::Percent,
+            value: 10.0,
+        };
+        let state = AppState::with_config(Config {
+            max_coupons_per_cart: Some(2),
+            ..Config::default()
+        })
+        .with_coupons(vec![coupon("A"), coupon("B"), coupon("C")]);
+        let args = json!({
+            "cartId": "stack_cart",
+            "items": [{ "name": "Apple", "price": 100.0 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let apply = |code: &str| json!({ "cartId": "stack_cart", "code": code });
+        handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, apply("A"))
+            .await
+            .expect("First coupon failed");
+        let result = handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, apply("B"))
+            .await
+            .expect("Second coupon failed");
+        assert_eq!(result["structuredContent"]["total"], 81.0);
+
+        let err = handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, apply("C"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Cart already has 2 coupons; remove one before applying C"
+        );
+        // Re-applying a coupon that is already on the cart is not a new one
+        handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, apply("A"))
+            .await
+            .expect("Re-applying failed");
+        assert_eq!(state.carts.get("stack_cart").unwrap().coupons, ["A", "B"]);
+    }
+
+    #[tokio::test]
+    async fn test_cart_qr_code_is_a_png() {
+        use crate::config::Config;
+        use crate::model::CART_QR_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({ "cartId": "qr_cart", "items": [{ "name": "Apple", "quantity": 2 }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let result = handle_tool_call(&state, CART_QR_TOOL_NAME, json!({ "cartId": "qr_cart" }))
+            .await
+            .expect("QR failed");
+        let image = &result["content"][0];
+        assert_eq!(image["type"], "image");
+        assert_eq!(image["mimeType"], "image/png");
+        assert_eq!(result["structuredContent"]["encoding"], "items");
+
+        let png = STANDARD.decode(image["data"].as_str().unwrap()).unwrap();
+        let reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.width, info.height);
+        assert!(info.width > 0);
+
+        // Without a share link to fall back on, oversized carts are refused
+        let big = json!({ "cartId": "qr_cart", "items": [{ "name": "x".repeat(2000) }] });
+        handle_tool_call(&state, TOOL_NAME, big)
+            .await
+            .expect("Add failed");
+        assert!(
+            handle_tool_call(&state, CART_QR_TOOL_NAME, json!({ "cartId": "qr_cart" }))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stock_holds_follow_patches_and_never_oversell() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::Config;
+        use crate::model::APPLY_PATCH_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let catalog = Catalog::new(vec![Product {
+            name: "Apple".into(),
+            price: Some(1.0),
+            stock: Some(5),
+            category: None,
+        }]);
+        let state = AppState::with_config(Config {
+            stock_hold_seconds: Some(60),
+            ..Config::default()
+        })
+        .with_catalog(catalog);
+        let add = |cart_id: &str, quantity: u32| json!({ "cartId": cart_id, "items": [{ "name": "Apple", "quantity": quantity }] });
+
+        handle_tool_call(&state, TOOL_NAME, add("patch_a", 5))
+            .await
+            .expect("Add failed");
+        assert!(handle_tool_call(&state, TOOL_NAME, add("patch_b", 1))
+            .await
+            .is_err());
+
+        // Lowering the line through a patch gives the units back straight away
+        let patch = json!({
+            "cartId": "patch_a",
+            "patch": [{ "op": "replace", "path": "/0/quantity", "value": 2 }]
+        });
+        handle_tool_call(&state, APPLY_PATCH_TOOL_NAME, patch)
+            .await
+            .expect("Patch failed");
+        handle_tool_call(&state, TOOL_NAME, add("patch_b", 3))
+            .await
+            .expect("Add of released units failed");
+
+        // Carts racing for the same units never hold more than the stock between them
+        let items = vec![CartItem {
+            name: "Apple".into(),
+            quantity: 1,
+            extra: BTreeMap::new(),
+        }];
+        state.stock_holds.release("patch_a");
+        state.stock_holds.release("patch_b");
+        let held = std::thread::scope(|scope| {
+            let racers: Vec<_> = (0..20)
+                .map(|n| {
+                    let (state, items) = (&state, &items);
+                    scope.spawn(move || state.hold_stock(&format!("race_{}", n), items).is_ok())
+                })
+                .collect();
+            racers
+                .into_iter()
+                .map(|racer| racer.join().unwrap())
+                .filter(|held| *held)
+                .count()
+        });
+        assert_eq!(held, 5);
+    }
+
+    #[tokio::test]
+    async fn test_stock_holds_reserve_and_expire() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let catalog = Catalog::new(vec![Product {
+            name: "Apple".into(),
+            price: Some(1.0),
+            stock: Some(5),
+            category: None,
+        }]);
+        let state = AppState::with_config(Config {
+            stock_hold_seconds: Some(1),
+            ..Config::default()
+        })
+        .with_catalog(catalog);
+        let add = |cart_id: &str, quantity: u32| json!({ "cartId": cart_id, "items": [{ "name": "Apple", "quantity": quantity }] });
+
+        handle_tool_call(&state, TOOL_NAME, add("hold_a", 3))
+            .await
+            .expect("Add failed");
+
+        // Cart A's hold leaves two apples for cart B
+        let result = handle_tool_call(&state, TOOL_NAME, add("hold_b", 1))
+            .await
+            .expect("Add failed");
+        assert_eq!(
+            result["structuredContent"]["availability"][0]["remainingStock"],
+            1
+        );
+        let err = handle_tool_call(&state, TOOL_NAME, add("hold_b", 2))
+            .await
+            .unwrap_err();
+        assert!(err.message.starts_with("Only 2 of Apple available"));
+        assert_eq!(state.carts.get("hold_b").unwrap().items[0].quantity, 1);
+
+        // Once cart A's hold lapses and is swept, its units are free again
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert_eq!(state.stock_holds.sweep(), 2);
+        let items = state.carts.get("hold_b").unwrap().items.clone();
+        assert_eq!(
+            state.availability("hold_b", &items)[0].remaining_stock,
+            Some(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recommendations_skip_cart_items() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::Config;
+        use crate::model::RECOMMEND_TOOL_NAME;
+        use crate::recommend::RecommendationMap;
+        use crate::router::mcp::handle_tool_call;
+
+        let product = |name: &str, category: Option<&str>| Product {
+            name: name.into(),
+            price: Some(1.0),
+            stock: None,
+            category: category.map(String::from),
+        };
+        let catalog = Catalog::new(vec![
+            product("Apple", Some("fruit")),
+            product("Pear", Some("fruit")),
+            product("Yogurt", None),
+            product("Granola", None),
+        ]);
+        let recommendations: RecommendationMap = serde_json::from_value(json!({
+            "categories": { "fruit": ["Pear", "Yogurt", "Unlisted", "Granola"] }
+        }))
+        .unwrap();
+
+        // Without a map there is nothing to suggest
+        let args =
+            json!({ "cartId": "rec_cart", "items": [{ "name": "Apple" }, { "name": "Pear" }] });
+        let state = AppState::with_config(Config::default()).with_catalog(catalog.clone());
+        handle_tool_call(&state, TOOL_NAME, args.clone())
+            .await
+            .expect("Add failed");
+        let result = handle_tool_call(&state, RECOMMEND_TOOL_NAME, json!({ "cartId": "rec_cart" }))
+            .await
+            .expect("Recommend failed");
+        assert_eq!(result["structuredContent"]["recommendations"], json!([]));
+
+        let state = AppState::with_config(Config::default())
+            .with_catalog(catalog)
+            .with_recommendations(recommendations);
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        let result = handle_tool_call(
+            &state,
+            RECOMMEND_TOOL_NAME,
+            json!({ "cartId": "rec_cart", "limit": 5 }),
+        )
+        .await
+        .expect("Recommend failed");
+
+        // Pear is already in the cart and Unlisted isn't a catalog product
+        assert_eq!(
+            result["structuredContent"]["recommendations"],
+            json!([
+                { "name": "Yogurt", "price": 1.0, "reason": "Goes with fruit" },
+                { "name": "Granola", "price": 1.0, "reason": "Goes with fruit" }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_large_responses_truncate_items() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            max_response_bytes: Some(4096),
+            ..Config::default()
+        });
+        let items: Vec<_> = (0..200)
+            .map(|i| json!({ "name": format!("Item {}", i), "price": 1.0 }))
+            .collect();
+        let args = json!({ "cartId": "huge_cart", "items": items });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let structured = &result["structuredContent"];
+        assert_eq!(structured["truncated"], true);
+        assert_eq!(structured["totalItems"], 200);
+        let kept = structured["items"].as_array().unwrap().len();
+        assert!(kept > 0 && kept < 200);
+        assert_eq!(structured["lines"].as_array().unwrap().len(), kept);
+        assert_eq!(structured["subtotal"], 200.0);
+        assert_eq!(state.carts.get("huge_cart").unwrap().items.len(), 200);
+
+        // Small carts are left alone
+        let args = json!({ "cartId": "small_cart", "items": [{ "name": "Apple" }] });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert!(result["structuredContent"].get("truncated").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_reports_created_and_aggregated_items() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args =
+            json!({ "cartId": "actions_cart", "items": [{ "name": "Apple", "quantity": 1 }] });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let args = json!({
+            "cartId": "actions_cart",
+            "items": [
+                { "name": "Pear", "quantity": 1 },
+                { "name": "Apple", "quantity": 2 },
+                { "name": "Pear", "quantity": 3 }
+            ]
+        });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert_eq!(
+            result["structuredContent"]["itemActions"],
+            json!([
+                { "name": "Pear", "action": "created" },
+                { "name": "Apple", "action": "aggregated" },
+                { "name": "Pear", "action": "aggregated" }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_savings_match_coupon_discount() {
+        use crate::config::Config;
+        use crate::model::{APPLY_COUPON_TOOL_NAME, CHECKOUT_TOOL_NAME};
+        use crate::pricing::{Coupon, CouponKind};
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default()).with_coupons(vec![Coupon {
+            code: "SAVE10".into(),
+            kind: CouponKind::Percent,
+            value: 10.0,
+        }]);
+        let args = json!({
+            "cartId": "savings_cart",
+            "items": [{ "name": "Apple", "quantity": 4, "price": 5.0 }]
+        });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert_eq!(result["structuredContent"]["savings"], 0.0);
+
+        let coupon = json!({ "cartId": "savings_cart", "code": "SAVE10" });
+        let result = handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, coupon)
+            .await
+            .expect("Apply failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["savings"], 2.0);
+        assert_eq!(content["savings"], content["appliedCoupons"][0]["discount"]);
+
+        let result = handle_tool_call(
+            &state,
+            CHECKOUT_TOOL_NAME,
+            json!({ "cartId": "savings_cart" }),
+        )
+        .await
+        .expect("Checkout failed");
+        assert_eq!(result["structuredContent"]["receipt"]["savings"], 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_coupon_breakdown_and_removal() {
+        use crate::config::Config;
+        use crate::model::{APPLY_COUPON_TOOL_NAME, REMOVE_COUPON_TOOL_NAME};
+        use crate::pricing::{Coupon, CouponKind};
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default()).with_coupons(vec![Coupon {
+            code: "SAVE10".into(),
+            kind: CouponKind::Percent,
+            value: 10.0,
+        }]);
+        let args = json!({
+            "cartId": "coupon_cart",
+            "items": [{ "name": "Apple", "quantity": 4, "price": 5.0 }]
+        });
+        handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let coupon = json!({ "cartId": "coupon_cart", "code": "save10" });
+        let result = handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, coupon.clone())
+            .await
+            .expect("Apply failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["subtotal"], 20.0);
+        assert_eq!(content["total"], 18.0);
+        assert_eq!(
+            content["appliedCoupons"],
+            json!([{ "code": "SAVE10", "type": "percent", "discount": 2.0 }])
+        );
+
+        let result = handle_tool_call(&state, REMOVE_COUPON_TOOL_NAME, coupon.clone())
+            .await
+            .expect("Remove failed");
+        let content = &result["structuredContent"];
+        assert_eq!(content["total"], 20.0);
+        assert_eq!(content["appliedCoupons"], json!([]));
+
+        // Removing again is an error, as is applying an unknown code
+        assert!(handle_tool_call(&state, REMOVE_COUPON_TOOL_NAME, coupon)
+            .await
+            .is_err());
+        let unknown = json!({ "cartId": "coupon_cart", "code": "BOGUS" });
+        assert!(handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, unknown)
+            .await
+            .is_err());
+
+        // A coupon never creates the cart it is applied to
+        let mistyped = json!({ "cartId": "coupon_crat", "code": "SAVE10" });
+        let err = handle_tool_call(&state, APPLY_COUPON_TOOL_NAME, mistyped)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Cart not found: coupon_crat");
+        assert!(!state.carts.contains_key("coupon_crat"));
+        assert!(!state.known_cart_ids.contains("coupon_crat"));
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_match_corrects_to_catalog() {
+        use crate::catalog::{Catalog, Product};
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let catalog = Catalog::new(vec![Product {
+            name: "Apple".into(),
+            price: Some(1.0),
+            stock: None,
+            category: None,
+        }]);
+        let state = AppState::with_config(Config {
+            fuzzy_match_distance: Some(2),
+            ..Config::default()
+        })
+        .with_catalog(catalog);
+
+        let args = json!({ "cartId": "fuzzy_cart", "items": [{ "name": "aple", "quantity": 2 }] });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let content = &result["structuredContent"];
+        assert_eq!(content["items"][0]["name"], "Apple");
+        assert_eq!(
+            content["warnings"],
+            json!(["Corrected \"aple\" to \"Apple\""])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_line_discount_in_cart_total() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({
+            "cartId": "line_cart",
+            "items": [
+                { "name": "Apple", "quantity": 2, "price": 5.0, "discount": { "type": "fixed", "value": 3 } },
+                { "name": "Bread", "quantity": 1, "price": 4.0 }
+            ]
+        });
+        let result = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let content = &result["structuredContent"];
+        assert_eq!(content["lines"][0]["discount"], 3.0);
+        assert_eq!(content["lines"][0]["total"], 7.0);
+        assert_eq!(content["lines"][1]["total"], 4.0);
+        assert_eq!(content["total"], 11.0);
+
+        let args = json!({
+            "cartId": "line_cart",
+            "items": [{ "name": "Pear", "discount": { "type": "percent", "value": -5 } }]
+        });
+        let err = handle_tool_call(&state, TOOL_NAME, args).await.unwrap_err();
+        assert_eq!(err.data.unwrap()["fields"][0]["field"], "discount");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_slow_asset_read_serves_fallback() {
+        use crate::config::Config;
+        use crate::model::FALLBACK_WIDGET_HTML;
+        use std::time::{Duration, Instant};
+
+        // A FIFO without a writer blocks readers indefinitely
+        let assets = std::env::temp_dir().join(format!("slow_assets_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        let fifo = assets.join("shopping-cart.html");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut state = AppState::with_config(Config {
+            asset_read_timeout_ms: Some(100),
+            ..Config::default()
+        });
+        state.assets_dir = assets.clone();
+
+        let started = Instant::now();
+        let html = state.load_widget_html().await.unwrap();
+        assert_eq!(html, FALLBACK_WIDGET_HTML);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(state.widget_cache.read().unwrap().is_none());
+
+        // Release the blocked reader thread before cleaning up
+        drop(std::fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_assets_dir_from_config() {
+        use crate::config::Config;
+
+        let assets = std::env::temp_dir().join(format!("assets_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(assets.join("shopping-cart.html"), "<p>custom widget</p>").unwrap();
+
+        let state = AppState::with_config(Config {
+            assets_dir: Some(assets.clone()),
+            ..Config::default()
+        });
+        assert_eq!(state.assets_dir, assets);
+        assert_eq!(
+            state.load_widget_html().await.unwrap(),
+            "<p>custom widget</p>"
+        );
+
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_widget_html_is_cached_until_invalidated() {
+        use crate::config::Config;
+
+        let assets = std::env::temp_dir().join(format!("cached_assets_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        let html_path = assets.join("shopping-cart.html");
+        std::fs::write(&html_path, "<p>v1</p>").unwrap();
+
+        let state = AppState::with_config(Config {
+            assets_dir: Some(assets.clone()),
+            ..Config::default()
+        });
+        assert_eq!(state.load_widget_html().await.unwrap(), "<p>v1</p>");
+
+        // Later reads come from memory, even once the file has changed
+        std::fs::write(&html_path, "<p>v2</p>").unwrap();
+        assert_eq!(state.load_widget_html().await.unwrap(), "<p>v1</p>");
+
+        state.invalidate_widget_cache();
+        assert_eq!(state.load_widget_html().await.unwrap(), "<p>v2</p>");
+
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_widget_fallback_is_reused_after_failed_fetch() {
+        use crate::config::Config;
+
+        let assets = std::env::temp_dir().join(format!("fallback_assets_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        let html_path = assets.join("shopping-cart.html");
+        std::fs::write(&html_path, "<p>v1</p>").unwrap();
+
+        let state = AppState::with_config(Config {
+            assets_dir: Some(assets.clone()),
+            widget_url: Some("http://127.0.0.1:1/widget.html".to_string()),
+            ..Config::default()
+        });
+        assert_eq!(state.load_widget_html().await.unwrap(), "<p>v1</p>");
+
+        // Within the retry window the local copy is served without refetching
+        std::fs::write(&html_path, "<p>v2</p>").unwrap();
+        assert_eq!(state.load_widget_html().await.unwrap(), "<p>v1</p>");
+        assert!(state.widget_cache.read().unwrap().is_none());
+
+        state.invalidate_widget_cache();
+        assert_eq!(state.load_widget_html().await.unwrap(), "<p>v2</p>");
+
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[test]
+    fn test_pluralization() {
+        use crate::model::{format_item_summary, pluralize};
+
+        assert_eq!(pluralize(1, "item"), "1 item");
+        assert_eq!(pluralize(2, "item"), "2 items");
+        assert_eq!(pluralize(0, "item"), "0 items");
+        assert_eq!(pluralize(2, "box"), "2 boxes");
+        assert_eq!(pluralize(3, "category"), "3 categories");
+
+        let items = vec![CartItem {
+            name: "Apple".into(),
+            quantity: 1,
+            extra: BTreeMap::new(),
+        }];
+        assert_eq!(format_item_summary(&items, 10), "1x Apple");
+        assert_eq!(format_item_summary(&[], 10), "no items");
+    }
+
+    #[test]
+    fn test_large_quantities_round_trip_and_saturate() {
+        use crate::model::update_cart_with_new_items;
+
+        let item: CartItem =
+            serde_json::from_value(json!({ "name": "Bolt", "quantity": u32::MAX })).unwrap();
+        assert_eq!(item.quantity, u32::MAX);
+        assert_eq!(
+            serde_json::to_value(&item).unwrap()["quantity"],
+            json!(u32::MAX)
+        );
+
+        // Aggregation saturates instead of wrapping
+        let mut items = vec![item.clone()];
+        update_cart_with_new_items(&mut items, vec![item]);
+        assert_eq!(items[0].quantity, u32::MAX);
+        let one = CartItem {
+            name: "Bolt".into(),
+            quantity: 1,
+            extra: BTreeMap::new(),
+        };
+        update_cart_with_new_items(&mut items, vec![one]);
+        assert_eq!(items[0].quantity, u32::MAX);
+
+        // Values beyond u32 are rejected rather than truncated
+        let too_big = json!({ "name": "Bolt", "quantity": u64::from(u32::MAX) + 1 });
+        assert!(serde_json::from_value::<CartItem>(too_big).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_totals_skip_unreadable_prices() {
+        use crate::config::Config;
+        use crate::model::CHECKOUT_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({
+            "cartId": "priced_cart",
+            "items": [
+                { "name": "Apple", "quantity": 2, "price": 2.5 },
+                { "name": "Bread" },
+                { "name": "Cheese", "price": "cheap" }
+            ]
+        });
+        let added = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+        assert_eq!(added["structuredContent"]["total"], 5.0);
+        assert_eq!(
+            added["structuredContent"]["warnings"],
+            json!(["Cheese has a non-numeric price; it counts as 0 in the total"])
+        );
+
+        let checked_out = handle_tool_call(
+            &state,
+            CHECKOUT_TOOL_NAME,
+            json!({ "cartId": "priced_cart" }),
+        )
+        .await
+        .expect("Checkout failed");
+        assert_eq!(checked_out["structuredContent"]["total"], 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_total_quantity_cap() {
+        use crate::config::Config;
+        use crate::model::APPLY_PATCH_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config {
+            max_total_qty: Some(5),
+            ..Config::default()
+        });
+        let add = |name: &str, quantity: u32| json!({ "cartId": "cap_cart", "items": [{ "name": name, "quantity": quantity }] });
+
+        // Aggregated to exactly the cap is fine
+        handle_tool_call(&state, TOOL_NAME, add("Apple", 3))
+            .await
+            .expect("Add failed");
+        handle_tool_call(&state, TOOL_NAME, add("Apple", 2))
+            .await
+            .expect("Add up to the cap failed");
+
+        // One more unit is refused, citing the cap and the would-be total
+        let err = handle_tool_call(&state, TOOL_NAME, add("Pear", 1))
+            .await
+            .expect_err("Add over the cap should fail");
+        assert_eq!(
+            err.message,
+            "Cart would hold 6 units, over the limit of 5 (MAX_TOTAL_QTY)"
+        );
+        // Patching a quantity past the cap is refused too
+        let patch = json!({ "cartId": "cap_cart", "patch": [
+            { "op": "replace", "path": "/0/quantity", "value": 6 }
+        ] });
+        let err = handle_tool_call(&state, APPLY_PATCH_TOOL_NAME, patch)
+            .await
+            .expect_err("Patch over the cap should fail");
+        assert!(err.message.contains("MAX_TOTAL_QTY"));
+
+        let cart = state.carts.get("cap_cart").unwrap();
+        assert_eq!(cart.items.len(), 1);
+        assert_eq!(cart.items[0].quantity, 5);
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_extras_in_one_batch() {
+        use crate::config::{Config, ConflictingExtras};
+        use crate::router::mcp::handle_tool_call;
+
+        let args = json!({ "cartId": "dup_cart", "items": [
+            { "name": "Apple", "quantity": 1, "price": 1.0 },
+            { "name": "Apple", "quantity": 2, "price": 1.5 }
+        ] });
+        let state_with = |conflicting_extras| {
+            AppState::with_config(Config {
+                conflicting_extras,
+                ..Config::default()
+            })
+        };
+
+        // Both non-error modes sum the quantities and warn about the dropped price
+        for (mode, price, warning) in [
+            (
+                ConflictingExtras::FirstWins,
+                1.0,
+                "Item 1 repeats Apple with different price; keeping item 0's",
+            ),
+            (
+                ConflictingExtras::LastWins,
+                1.5,
+                "Item 1 repeats Apple with different price; using item 1's",
+            ),
+        ] {
+            let state = state_with(mode);
+            let result = handle_tool_call(&state, TOOL_NAME, args.clone())
+                .await
+                .expect("Add failed");
+            assert_eq!(result["structuredContent"]["warnings"], json!([warning]));
+            let cart = state.carts.get("dup_cart").unwrap();
+            assert_eq!(cart.items.len(), 1);
+            assert_eq!(cart.items[0].quantity, 3);
+            assert_eq!(cart.items[0].extra["price"], json!(price), "{:?}", mode);
+        }
+
+        // Error mode rejects the batch and adds nothing
+        let state = state_with(ConflictingExtras::Error);
+        let err = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect_err("Conflicting batch should fail");
+        assert_eq!(err.code, -32602);
+        assert_eq!(
+            err.message,
+            "Item 1 repeats Apple from item 0 with different price"
+        );
+        assert!(state.carts.get("dup_cart").is_none());
+
+        // Repeats with matching extras aggregate quietly in every mode
+        let result = handle_tool_call(
+            &state,
+            TOOL_NAME,
+            json!({ "cartId": "dup_cart", "items": [
+                { "name": "Apple", "price": 1.0 },
+                { "name": "Apple", "price": 1.0 }
+            ] }),
+        )
+        .await
+        .expect("Matching repeats should be accepted");
+        assert!(result["structuredContent"].get("warnings").is_none());
+        assert_eq!(state.carts.get("dup_cart").unwrap().items[0].quantity, 2);
+    }
+
+    #[tokio::test]
+    async fn test_add_rejects_blank_names_and_zero_quantities() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let add = |items: serde_json::Value| json!({ "cartId": "blank_cart", "items": items });
+
+        let err = handle_tool_call(
+            &state,
+            TOOL_NAME,
+            add(json!([{ "name": "", "quantity": 2 }])),
+        )
+        .await
+        .expect_err("Empty name should fail");
+        assert_eq!(err.code, -32602);
+        assert_eq!(
+            err.message,
+            "Item 0 has an invalid name: name must not be empty"
+        );
+
+        // Whitespace-only names and zero quantities are each named by index
+        let err = handle_tool_call(
+            &state,
+            TOOL_NAME,
+            add(json!([
+                { "name": "Apple" },
+                { "name": "  \t", "quantity": 1 },
+                { "name": "Pear", "quantity": 0 }
+            ])),
+        )
+        .await
+        .expect_err("Blank name and zero quantity should fail");
+        assert_eq!(err.code, -32602);
+        assert_eq!(
+            err.message,
+            "Item 1 has an invalid name: name must not be empty; \
+             Item 2 has an invalid quantity: quantity must be at least 1"
+        );
+        assert_eq!(err.data.unwrap()["fields"][1]["field"], "quantity");
+        assert!(state.carts.get("blank_cart").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_cart_reads_without_changing() {
+        use crate::config::Config;
+        use crate::model::GET_CART_TOOL_NAME;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({ "cartId": "read_cart", "items": [{ "name": "Apple", "quantity": 2 }] });
+        let added = handle_tool_call(&state, TOOL_NAME, args)
+            .await
+            .expect("Add failed");
+
+        let read = handle_tool_call(&state, GET_CART_TOOL_NAME, json!({ "cartId": "read_cart" }))
+            .await
+            .expect("get_cart failed");
+        assert_eq!(
+            read["structuredContent"]["items"],
+            added["structuredContent"]["items"]
+        );
+        assert_eq!(
+            read["content"][0]["text"],
+            "Cart read_cart has 1 item: 2x Apple"
+        );
+        assert_eq!(state.carts.get("read_cart").unwrap().items[0].quantity, 2);
+
+        // An unknown cart reads as empty and isn't created
+        let read = handle_tool_call(&state, GET_CART_TOOL_NAME, json!({ "cartId": "no_cart" }))
+            .await
+            .expect("get_cart failed");
+        assert_eq!(read["structuredContent"]["items"], json!([]));
+        assert!(state.carts.get("no_cart").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_huge_adds_saturate() {
+        use crate::config::Config;
+        use crate::router::mcp::handle_tool_call;
+
+        let state = AppState::with_config(Config::default());
+        let args = json!({ "cartId": "bolt_cart", "items": [{ "name": "Bolt", "quantity": 4_000_000_000u32 }] });
+        for _ in 0..2 {
+            handle_tool_call(&state, TOOL_NAME, args.clone())
+                .await
+                .expect("Add failed");
+        }
+        assert_eq!(
+            state.carts.get("bolt_cart").unwrap().items[0].quantity,
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn test_quantity_accepts_numeric_strings() {
+        let parse = |quantity: serde_json::Value| {
+            serde_json::from_value::<CartItem>(json!({ "name": "Pear", "quantity": quantity }))
+        };
+
+        assert_eq!(parse(json!(3)).unwrap().quantity, 3);
+        assert_eq!(parse(json!("3")).unwrap().quantity, 3);
+        assert_eq!(parse(json!(" 12 ")).unwrap().quantity, 12);
+        // Coerced quantities serialize back as numbers
+        assert_eq!(
+            serde_json::to_value(parse(json!("3")).unwrap()).unwrap()["quantity"],
+            json!(3)
+        );
+
+        let err = parse(json!("three")).unwrap_err().to_string();
+        assert!(err.starts_with("invalid value: string \"three\", expected a whole number"));
+        assert!(parse(json!("-1")).is_err());
+        assert!(parse(json!("4294967296")).is_err());
+        assert!(parse(json!(-1)).is_err());
+        assert!(parse(json!(2.5)).is_err());
+    }
+
+    #[test]
+    fn test_total_weight_skips_unweighted_items() {
+        use crate::config::Config;
+        use crate::model::{total_weight, validate_items};
+
+        let items: Vec<CartItem> = serde_json::from_value(json!([
+            { "name": "Flour", "quantity": 3, "weight": 1.5 },
+            { "name": "Gift card" },
+            { "name": "Salt", "weight": 0.25 }
+        ]))
+        .unwrap();
+        assert_eq!(total_weight(&items), 4.75);
+        assert!(validate_items(&items, &Config::default()).is_ok());
+
+        let bad: Vec<CartItem> =
+            serde_json::from_value(json!([{ "name": "Rock", "weight": -1 }])).unwrap();
+        let err = validate_items(&bad, &Config::default()).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Item 0 has an invalid weight: weight must be a non-negative number"
+        );
+    }
+
+    #[test]
+    fn test_item_summary_truncates() {
+        use crate::model::format_item_summary;
+
+        let items: Vec<CartItem> = (1..=15)
+            .map(|n| CartItem {
+                name: format!("Item{}", n),
+                quantity: 1,
+                extra: BTreeMap::new(),
+            })
+            .collect();
+
+        let summary = format_item_summary(&items, 10);
+        assert!(summary.starts_with("1x Item1, "));
+        assert!(summary.contains("1x Item10"));
+        assert!(!summary.contains("Item11"));
+        assert!(summary.ends_with(" …and 5 more"));
+        assert!(!format_item_summary(&items[..10], 10).contains("more"));
     }
 }
@@ -1,7 +1,38 @@
-use dashmap::DashMap;
-use serde::{Deserialize, Serialize};
+use crate::aliases::{load_aliases, Aliases};
+use crate::catalog::{Catalog, ItemAvailability};
+use crate::config::{Config, ConflictingExtras, Features, LoadedCounts, PriceSource};
+use crate::expiry::CartActivity;
+use crate::holds::StockHolds;
+use crate::persist;
+use crate::pricing::{
+    compute_totals, load_coupons, load_fx_rates, load_tax_categories, load_tax_table,
+    parse_line_discount, Coupon, Money, PricingPolicy, Totals,
+};
+use crate::rate_limit::RateLimiter;
+use crate::recommend::{load_recommendations, RecommendationMap};
+use crate::share::{unix_now, Claims};
+use crate::shipping::{load_shipping_tiers, ShippingTier};
+use crate::tools::ToolRegistry;
+use dashmap::{mapref::entry::Entry, DashMap, DashSet};
+use schemars::JsonSchema;
+use serde::{
+    de::{self, Unexpected, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 use serde_json::{json, Value};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
 
 // =============================================================================
 // Constants
@@ -11,6 +42,42 @@ use std::{collections::HashMap, path::PathBuf, sync::Arc};
 pub const TOOL_NAME: &str = "add_to_cart";
 /// Name of the checkout tool
 pub const CHECKOUT_TOOL_NAME: &str = "checkout";
+/// Error for checking out an empty cart when `BLOCK_EMPTY_CHECKOUT` is set
+pub const EMPTY_CHECKOUT_ERROR: &str = "cart is empty, cannot checkout";
+/// Name of the inline widget rendering tool
+pub const RENDER_CART_TOOL_NAME: &str = "render_cart";
+/// Name of the coupon application tool
+pub const APPLY_COUPON_TOOL_NAME: &str = "apply_coupon";
+/// Name of the coupon removal tool
+pub const REMOVE_COUPON_TOOL_NAME: &str = "remove_coupon";
+/// Name of the catalog validation tool
+pub const VALIDATE_CART_TOOL_NAME: &str = "validate_cart";
+/// Name of the share link tool
+pub const SHARE_CART_TOOL_NAME: &str = "share_cart";
+/// Name of the tool opening a shared cart
+pub const LOAD_SHARED_CART_TOOL_NAME: &str = "load_shared_cart";
+/// Name of the JSON Patch tool
+pub const APPLY_PATCH_TOOL_NAME: &str = "apply_patch";
+/// Name of the cart metadata tool
+pub const SET_CART_METADATA_TOOL_NAME: &str = "set_cart_metadata";
+/// Name of the shipping cost tool
+pub const SHIPPING_ESTIMATE_TOOL_NAME: &str = "shipping_estimate";
+/// Name of the regional tax tool
+pub const TAX_ESTIMATE_TOOL_NAME: &str = "tax_estimate";
+/// Name of the QR code tool
+pub const CART_QR_TOOL_NAME: &str = "cart_qr_code";
+/// Name of the related-products tool
+pub const RECOMMEND_TOOL_NAME: &str = "recommend";
+/// Name of the tool restoring a soft-deleted item
+pub const UNDO_REMOVE_TOOL_NAME: &str = "undo_remove";
+/// Name of the cart mutation log tool
+pub const CART_HISTORY_TOOL_NAME: &str = "cart_history";
+/// Name of the currency conversion tool
+pub const CONVERT_TOTAL_TOOL_NAME: &str = "convert_total";
+/// Name of the budget-capped add tool
+pub const ADD_WITHIN_BUDGET_TOOL_NAME: &str = "add_within_budget";
+/// Name of the read-only cart lookup tool
+pub const GET_CART_TOOL_NAME: &str = "get_cart";
 /// URI for the widget template
 pub const WIDGET_TEMPLATE_URI: &str = "ui://widget/shopping-cart.html";
 /// MIME type for the widget
@@ -19,6 +86,21 @@ pub const WIDGET_MIME_TYPE: &str = "text/html+skybridge";
 pub const SERVER_NAME: &str = "shopping-cart-rust";
 /// Protocol version for MCP
 pub const PROTOCOL_VERSION: &str = "2024-11-05";
+/// Minimal widget served when the real asset cannot be read in time
+pub const FALLBACK_WIDGET_HTML: &str =
+    "<!doctype html><html><body><div id=\"shopping-cart-root\">Shopping cart is loading slowly. Please try again.</div></body></html>";
+/// Extra item keys accepted when strict item schema mode is enabled
+pub const ALLOWED_EXTRA_KEYS: &[&str] = &[
+    "price",
+    "description",
+    "category",
+    "imageUrl",
+    "discount",
+    "weight",
+    "taxCategory",
+];
+/// Largest serialized size, in bytes, of a cart's metadata map
+pub const MAX_CART_METADATA_BYTES: usize = 4096;
 
 // =============================================================================
 // Data Models
@@ -29,23 +111,268 @@ fn default_quantity() -> u32 {
     1
 }
 
+/// Reads a quantity given as a JSON integer or as a string of digits ("3"), which
+/// LLM clients sometimes send. Anything else, including values over `u32::MAX`, is
+/// rejected with a message naming the accepted forms.
+fn deserialize_quantity<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    struct QuantityVisitor;
+
+    impl Visitor<'_> for QuantityVisitor {
+        type Value = u32;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a whole number from 0 to 4294967295, or a string of one such as \"3\"")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<u32, E> {
+            u32::try_from(v).map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &self))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<u32, E> {
+            u32::try_from(v).map_err(|_| E::invalid_value(Unexpected::Signed(v), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<u32, E> {
+            let digits = v.trim();
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(E::invalid_value(Unexpected::Str(v), &self));
+            }
+            digits
+                .parse()
+                .map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+        }
+    }
+
+    deserializer.deserialize_any(QuantityVisitor)
+}
+
 /// Represents an item in the shopping cart
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct CartItem {
     /// Name of the product
     pub name: String,
 
-    /// Quantity of this item (defaults to 1)
-    #[serde(default = "default_quantity")]
+    /// Quantity of this item (defaults to 1). Kept as `u32`: aggregation saturates
+    /// at `u32::MAX` instead of overflowing, and larger JSON values are rejected.
+    /// Numeric strings are accepted and coerced.
+    #[serde(
+        default = "default_quantity",
+        deserialize_with = "deserialize_quantity"
+    )]
+    #[schemars(with = "u32")]
     pub quantity: u32,
 
-    /// Captures any extra fields (e.g., price, description) dynamically
+    /// Captures any extra fields (e.g., price, description) dynamically.
+    /// Ordered so an item always serializes to the same bytes.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl CartItem {
+    /// Returns the numeric unit price from the extra fields, if present
+    pub fn price(&self) -> Option<f64> {
+        self.extra.get("price").and_then(Value::as_f64)
+    }
+
+    /// Returns the `taxCategory` extra field, if it's a string
+    pub fn tax_category(&self) -> Option<&str> {
+        self.extra.get("taxCategory").and_then(Value::as_str)
+    }
+
+    /// Returns the unit weight from the extra fields, if present
+    pub fn weight(&self) -> Option<f64> {
+        self.extra.get("weight").and_then(Value::as_f64)
+    }
+}
+
+/// Sums `quantity * weight` over items; items without a weight are skipped
+pub fn total_weight(items: &[CartItem]) -> f64 {
+    items
+        .iter()
+        .filter_map(|item| item.weight().map(|w| w * item.quantity as f64))
+        .sum()
+}
+
+/// A cart held in server memory
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Cart {
+    /// Items currently in the cart
+    pub items: Vec<CartItem>,
+
+    /// Normalized codes of coupons applied to the cart, in application order
+    #[serde(default)]
+    pub coupons: Vec<String>,
+
+    /// Arbitrary client data attached to the cart, such as a table number or note
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, Value>,
+
+    /// Soft-deleted lines, oldest first; they don't count toward totals
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<RemovedLine>,
+
+    /// Recent mutations, oldest first. Server-side only, so it never changes the
+    /// fingerprint or travels in share links.
+    #[serde(skip)]
+    pub history: VecDeque<HistoryEntry>,
+}
+
+/// Kind of change made to a cart
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CartMutation {
+    /// Items were added or restored
+    Add,
+    /// A line left the cart
+    Remove,
+    /// The items were replaced by a sync or patch
+    Set,
+    /// The cart was checked out
+    Checkout,
+}
+
+/// One recorded cart mutation
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct HistoryEntry {
+    /// What kind of change was made
+    pub action: CartMutation,
+
+    /// Unix time of the change
+    pub at: u64,
+
+    /// Human-readable summary, e.g. "2x Apple"
+    pub detail: String,
+}
+
+/// How long an initialized MCP session is remembered without being used
+pub const MCP_SESSION_IDLE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long local assets stand in for `WIDGET_URL` after a failed fetch
+/// before the URL is tried again
+pub const WIDGET_FETCH_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Most ids of carts no longer in memory (e.g. checked out) remembered as known; past
+/// that they are all forgotten and read as not found again
+pub const MAX_DEPARTED_CART_IDS: usize = 10_000;
+
+/// Most orders the sales ledger keeps; older ones are dropped first
+pub const MAX_LEDGER_ORDERS: usize = 10_000;
+
+/// Most soft-deleted lines a cart keeps, however recent
+pub const MAX_REMOVED_LINES: usize = 20;
+
+/// A line taken out of the cart in soft-delete mode, kept for `undo_remove`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovedLine {
+    /// The line as it was before removal
     #[serde(flatten)]
-    pub extra: HashMap<String, Value>,
+    pub item: CartItem,
+
+    /// Always true, marking the line as removed for clients
+    pub removed: bool,
+
+    /// Unix time of the removal
+    pub removed_at: u64,
+}
+
+impl Cart {
+    /// Replaces the items, returning the names of lines that left the cart. With a
+    /// soft-delete window, lines that are dropped or set to zero quantity move to
+    /// `removed` instead of disappearing.
+    pub fn replace_items(
+        &mut self,
+        items: Vec<CartItem>,
+        soft_delete: Option<Duration>,
+    ) -> Vec<String> {
+        let dropped: Vec<CartItem> = std::mem::take(&mut self.items)
+            .into_iter()
+            .filter(|old| !items.iter().any(|i| i.name == old.name && i.quantity > 0))
+            .collect();
+        let names = dropped.iter().map(|item| item.name.clone()).collect();
+
+        match soft_delete {
+            None => self.items = items,
+            Some(window) => {
+                let now = unix_now();
+                self.items = items.into_iter().filter(|i| i.quantity > 0).collect();
+                self.removed
+                    .extend(dropped.into_iter().map(|item| RemovedLine {
+                        item,
+                        removed: true,
+                        removed_at: now,
+                    }));
+                self.prune_removed(window);
+            }
+        }
+        names
+    }
+
+    /// Appends to the mutation history, keeping at most `limit` entries;
+    /// does nothing when history is off
+    pub fn record(
+        &mut self,
+        limit: Option<usize>,
+        action: CartMutation,
+        detail: impl Into<String>,
+    ) {
+        let Some(limit) = limit.filter(|limit| *limit > 0) else {
+            return;
+        };
+        self.history.push_back(HistoryEntry {
+            action,
+            at: unix_now(),
+            detail: detail.into(),
+        });
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// Restores the most recently removed line still inside the window, merging it
+    /// with any line of the same name added since
+    pub fn undo_remove(&mut self, window: Duration) -> Option<CartItem> {
+        self.prune_removed(window);
+        let line = self.removed.pop()?;
+        update_cart_with_new_items(&mut self.items, vec![line.item.clone()]);
+        Some(line.item)
+    }
+
+    /// Forgets removed lines older than the window, and all but the newest few
+    fn prune_removed(&mut self, window: Duration) {
+        let cutoff = unix_now().saturating_sub(window.as_secs());
+        self.removed.retain(|line| line.removed_at >= cutoff);
+        let excess = self.removed.len().saturating_sub(MAX_REMOVED_LINES);
+        self.removed.drain(..excess);
+    }
+
+    /// Returns a short hash of the cart's contents, used as an optimistic concurrency version
+    pub fn fingerprint(&self) -> String {
+        // Value maps are ordered, so equal carts always serialize identically
+        let canonical = serde_json::to_value(self).unwrap_or_default().to_string();
+        hex::encode(&Sha256::digest(canonical.as_bytes())[..8])
+    }
+
+    /// Computes the cart's subtotal, discounts, tax, and amount due
+    pub fn total_value(&self, policy: &PricingPolicy) -> Totals {
+        compute_totals(&self.items, &self.coupons, policy)
+    }
+}
+
+/// Lifecycle state of a cart as reported to clients
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CartStatus {
+    /// The cart exists and has items
+    Active,
+    /// The cart was created but currently holds no items
+    Empty,
+    /// No cart with this id was ever created
+    NotFound,
 }
 
 /// Input for the add_to_cart tool
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AddToCartInput {
     /// List of items to add to the cart
@@ -53,18 +380,215 @@ pub struct AddToCartInput {
 
     /// Optional cart identifier
     pub cart_id: Option<String>,
+
+    /// Optional key making retries of the same add return the first result
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
 
-/// Input for the checkout tool
+/// What an idempotency key is scoped to: the tool, the cart id the caller sent (empty
+/// when the cart is minted), and the key itself
+pub type IdempotencyScope = (String, String, String);
+
+/// A call made with an idempotency key
+#[derive(Debug, Clone)]
+pub enum IdempotentCall {
+    /// Claimed by a call that hasn't finished
+    Running,
+
+    /// Finished, with the result retries get back
+    Done(Value),
+}
+
+impl AddToCartInput {
+    /// Rejects items with a blank name or a zero quantity, naming each by index
+    pub fn validate(&self) -> Result<(), ToolError> {
+        let mut messages = Vec::new();
+        let mut fields = Vec::new();
+        for (index, item) in self.items.iter().enumerate() {
+            if strip_control_chars(&item.name).trim().is_empty() {
+                let reason = "name must not be empty";
+                messages.push(format!("Item {} has an invalid name: {}", index, reason));
+                fields.push(json!({ "index": index, "field": "name", "reason": reason }));
+            }
+            if item.quantity == 0 {
+                let reason = "quantity must be at least 1";
+                messages.push(format!(
+                    "Item {} has an invalid quantity: {}",
+                    index, reason
+                ));
+                fields.push(json!({ "index": index, "field": "quantity", "reason": reason }));
+            }
+        }
+
+        if messages.is_empty() {
+            Ok(())
+        } else {
+            Err(ToolError::with_data(
+                messages.join("; "),
+                json!({ "fields": fields }),
+            ))
+        }
+    }
+}
+
+/// Input for the add_within_budget tool
+#[derive(Debug, Deserialize)]
+pub struct BudgetAddInput {
+    /// Items to add, all of which must be priced
+    pub items: Vec<CartItem>,
+
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// Largest subtotal the cart may reach
+    pub budget: f64,
+}
+
+/// Input for `POST /sync_cart`
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncCartInput {
+    /// Items replacing the cart's contents
+    pub items: Vec<CartItem>,
+
+    /// Optional cart identifier
+    pub cart_id: Option<String>,
+
+    /// Fingerprint from a prior read; the sync is refused if the cart changed since
+    #[serde(default)]
+    pub if_version: Option<String>,
+}
+
+/// Input for the checkout tool
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CheckoutInput {
     /// Optional cart identifier
     #[serde(rename = "cartId")]
     pub cart_id: Option<String>,
 }
 
+/// Input for tools that operate on an existing cart
+#[derive(Debug, Deserialize)]
+pub struct CartRefInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+}
+
+/// Input for the apply_coupon and remove_coupon tools
+#[derive(Debug, Deserialize)]
+pub struct CouponInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// Coupon code
+    pub code: String,
+}
+
+/// Input for the load_shared_cart tool
+#[derive(Debug, Deserialize)]
+pub struct SharedCartInput {
+    /// Cart identifier taken from the share link
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// Signature taken from the share link's `sig` parameter
+    #[serde(default)]
+    pub signature: Option<String>,
+
+    /// Expiry taken from the share link's `exp` parameter
+    #[serde(default)]
+    pub exp: Option<u64>,
+
+    /// One-time nonce taken from the share link's `nonce` parameter
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+/// Input for the apply_patch tool
+#[derive(Debug, Deserialize)]
+pub struct PatchInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// RFC 6902 operations against the cart's items array
+    pub patch: Value,
+}
+
+/// Input for the tax_estimate tool
+#[derive(Debug, Deserialize)]
+pub struct TaxEstimateInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// State or country code to look up in the tax table
+    pub region: String,
+}
+
+/// Input for the convert_total tool
+#[derive(Debug, Deserialize)]
+pub struct ConvertTotalInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// Currency codes to show the total in
+    pub currencies: Vec<String>,
+}
+
+/// Input for the recommend tool
+#[derive(Debug, Deserialize)]
+pub struct RecommendInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// Most suggestions to return (defaults to 3)
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Input for the set_cart_metadata tool
+#[derive(Debug, Deserialize)]
+pub struct MetadataInput {
+    /// Cart identifier
+    #[serde(rename = "cartId")]
+    pub cart_id: String,
+
+    /// Keys to set; a null value removes the key
+    pub metadata: HashMap<String, Value>,
+}
+
+/// An item field the server accepted without interpreting it
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct FieldWarning {
+    /// Index of the item in the request
+    pub index: usize,
+
+    /// Name of the field
+    pub field: String,
+
+    /// Why the field was flagged
+    pub reason: String,
+}
+
+/// What adding one item did to the cart
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemAction {
+    /// The item became a new cart line
+    Created,
+    /// The item's quantity was added to an existing line of the same name
+    Aggregated,
+}
+
 /// Response for cart synchronization operations
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct SyncResponse {
     /// Status of the operation
     pub status: String,
@@ -72,6 +596,24 @@ pub struct SyncResponse {
     /// Cart identifier
     #[serde(rename = "cartId")]
     pub cart_id: String,
+
+    /// Item fields that were stored but are not recognized
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<FieldWarning>,
+
+    /// Items left out of a lenient sync, by request index
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rejected: Vec<RejectedItem>,
+}
+
+/// An item a lenient sync refused, and why
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct RejectedItem {
+    /// Index of the item in the request
+    pub index: usize,
+
+    /// Why the item was refused
+    pub error: String,
 }
 
 /// Standard JSON-RPC 2.0 Request envelope
@@ -91,6 +633,94 @@ pub struct JsonRpcRequest {
     pub id: Option<Value>,
 }
 
+/// Params of a `tools/call` request
+#[derive(Debug, Deserialize)]
+pub struct ToolCallParams {
+    /// Name of the tool to run
+    pub name: String,
+
+    /// Tool arguments, validated by each tool's input type
+    #[serde(default)]
+    pub arguments: Option<Value>,
+
+    /// Request metadata sent by the host, such as the widget session id
+    #[serde(default, rename = "_meta")]
+    pub meta: Option<Value>,
+
+    /// Add a `diff` of the cart's lines against its state before the call
+    #[serde(default, rename = "includeDiff")]
+    pub include_diff: bool,
+}
+
+impl ToolCallParams {
+    /// Widget session id from `_meta`, under `openai/widgetSessionId` or plain
+    /// `widgetSessionId`; its value is passed through untouched
+    pub fn widget_session_id(&self) -> Option<Value> {
+        let meta = self.meta.as_ref()?;
+        meta.get("openai/widgetSessionId")
+            .or_else(|| meta.get("widgetSessionId"))
+            .filter(|id| !id.is_null())
+            .cloned()
+    }
+}
+
+/// JSON-RPC code for tool arguments a handler rejected
+pub const INVALID_PARAMS_CODE: i32 = -32602;
+
+/// JSON-RPC code for a `tools/call` naming a tool the server doesn't have.
+/// A missing tool is a missing operation rather than a bad argument, so it shares
+/// method-not-found's code; clients branch on it without parsing messages.
+pub const UNKNOWN_TOOL_CODE: i32 = -32601;
+
+/// Error returned by tool handlers, surfaced as a JSON-RPC error response
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolError {
+    /// JSON-RPC error code, [`INVALID_PARAMS_CODE`] unless stated otherwise
+    pub code: i32,
+
+    /// Human-readable error message
+    pub message: String,
+
+    /// Structured details for the JSON-RPC `data` member
+    pub data: Option<Value>,
+}
+
+impl ToolError {
+    /// Creates an error carrying structured details
+    pub fn with_data(message: impl Into<String>, data: Value) -> Self {
+        Self {
+            code: INVALID_PARAMS_CODE,
+            message: message.into(),
+            data: Some(data),
+        }
+    }
+
+    /// Creates the error for a call to a tool that doesn't exist
+    pub fn unknown_tool(name: &str) -> Self {
+        Self {
+            code: UNKNOWN_TOOL_CODE,
+            message: format!("Unknown tool: {}", name),
+            data: Some(json!({ "tool": name })),
+        }
+    }
+}
+
+impl From<String> for ToolError {
+    fn from(message: String) -> Self {
+        Self {
+            code: INVALID_PARAMS_CODE,
+            message,
+            data: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 // =============================================================================
 // Application State
 // =============================================================================
@@ -98,32 +728,737 @@ pub struct JsonRpcRequest {
 /// Shared application state that can be safely passed between threads
 pub type SharedState = Arc<AppState>;
 
+/// Hasher for the carts map: std SipHash, or aHash with the `fast-hash` feature
+#[cfg(not(feature = "fast-hash"))]
+pub type CartHasher = std::collections::hash_map::RandomState;
+/// Hasher for the carts map: std SipHash, or aHash with the `fast-hash` feature
+#[cfg(feature = "fast-hash")]
+pub type CartHasher = ahash::RandomState;
+
+/// Concurrent map of carts keyed by cart id
+pub type CartMap = DashMap<String, Cart, CartHasher>;
+
 /// Core application state containing carts and asset information
 pub struct AppState {
     /// In-memory storage for carts, keyed by cart_id.
     /// DashMap allows concurrent access without external Mutexes.
-    pub carts: DashMap<String, Vec<CartItem>>,
+    pub carts: CartMap,
+
+    /// Ids of live carts and of recently checked-out ones, so emptied carts can be told
+    /// apart from unknown ones. At most `MAX_DEPARTED_CART_IDS` departed ids are kept.
+    pub known_cart_ids: DashSet<String>,
 
     /// Path to the directory containing HTML assets.
     pub assets_dir: PathBuf,
+
+    /// Runtime configuration resolved at startup.
+    pub config: Config,
+
+    /// Optional features in effect, derived from the configuration at startup.
+    pub features: Features,
+
+    /// Product catalog (empty when none is configured).
+    pub catalog: Catalog,
+
+    /// Coupon definitions keyed by normalized code.
+    pub coupons: HashMap<String, Coupon>,
+
+    /// Shipping tiers in priority order (empty when none are configured).
+    pub shipping_tiers: Vec<ShippingTier>,
+
+    /// Tax rates keyed by normalized region code.
+    pub tax_table: HashMap<String, f64>,
+
+    /// Tax rates by item category, replacing `TAX_RATE` for items in a listed category.
+    pub tax_categories: HashMap<String, f64>,
+
+    /// Exchange rates from the base currency keyed by currency code.
+    pub fx_rates: HashMap<String, f64>,
+
+    /// Deprecated method and tool names still accepted.
+    pub aliases: Aliases,
+
+    /// Tools registered at startup, served alongside the built-in tools.
+    pub tools: ToolRegistry,
+
+    /// Related products and categories used by `recommend`.
+    pub recommendations: RecommendationMap,
+
+    /// Widget HTML cached after the first successful read.
+    pub widget_cache: RwLock<Option<String>>,
+
+    /// Local widget HTML served while `WIDGET_URL` is failing, with when it was read.
+    pub widget_fallback: RwLock<Option<(Instant, String)>>,
+
+    /// Shutdown signal carrying the reason once a graceful stop is requested.
+    pub shutdown: watch::Sender<Option<String>>,
+
+    /// Number of open SSE sessions.
+    pub sse_sessions: Arc<AtomicUsize>,
+
+    /// Per-client request counter enforcing `RATE_LIMIT_PER_MINUTE`.
+    pub rate_limiter: RateLimiter,
+
+    /// MCP sessions that completed a real (non-probe) `initialize`, with when they were
+    /// last used.
+    pub initialized_sessions: DashMap<String, Instant>,
+
+    /// Recent calls made with an idempotency key, with when they were claimed.
+    pub idempotency_keys: DashMap<IdempotencyScope, (Instant, IdempotentCall)>,
+
+    /// Share link nonces already redeemed, with the Unix time their links expire.
+    pub used_share_nonces: DashMap<String, u64>,
+
+    /// The latest `MAX_LEDGER_ORDERS` checkouts, oldest first, for sales stats.
+    pub orders: RwLock<VecDeque<OrderRecord>>,
+
+    /// Stock reserved by carts when `STOCK_HOLD_SECONDS` is set.
+    pub stock_holds: StockHolds,
+
+    /// When each cart last changed, for `CART_TTL_SECONDS` expiry.
+    pub cart_activity: CartActivity,
+
+    /// Cart writes to `CART_DATA_DIR` that failed.
+    pub persist_failures: AtomicU64,
+
+    /// Orders cart writes to `CART_DATA_DIR`.
+    pub cart_saves: persist::CartSaves,
+
+    /// Method and tool calls made through a deprecated alias.
+    pub deprecated_calls: AtomicU64,
+}
+
+/// A checked-out order as kept in the ledger
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRecord {
+    /// Order id from the receipt
+    pub order_id: String,
+
+    /// Unix time the order was placed
+    pub placed_at: u64,
+
+    /// Units across all lines
+    pub units: u64,
+
+    /// Amount due on the receipt
+    pub total: Money,
+}
+
+impl OrderRecord {
+    /// Describes a cart checked out just now as `order_id`, totalled as `totals`
+    pub fn for_cart(order_id: &str, cart: &Cart, totals: &Totals) -> Self {
+        Self {
+            order_id: order_id.to_string(),
+            placed_at: unix_now(),
+            units: cart.items.iter().map(|i| u64::from(i.quantity)).sum(),
+            total: Money::from_amount(totals.total, totals.decimals),
+        }
+    }
+}
+
+/// Sales aggregated over the ledger
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SalesStats {
+    /// Orders placed
+    pub orders: usize,
+
+    /// Units sold across all orders
+    pub units_sold: u64,
+
+    /// Revenue across all orders
+    pub revenue: f64,
+}
+
+/// An open SSE session; dropping it (on disconnect) frees its slot
+pub struct SseSession(Arc<AtomicUsize>);
+
+impl Drop for SseSession {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Loads an optional data file, logging a failure and falling back to empty data
+fn load_or_default<T: Default>(
+    path: &Option<PathBuf>,
+    load: impl FnOnce(&Path) -> Result<T, String>,
+) -> T {
+    match path {
+        Some(path) => load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            T::default()
+        }),
+        None => T::default(),
+    }
 }
 
 impl AppState {
     /// Creates a new AppState with empty carts and locates the assets directory
     pub fn new() -> Self {
-        let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-        let assets_dir = Self::locate_assets_directory(&current_dir);
+        Self::with_config(Config::from_env())
+    }
+
+    /// Creates a new AppState using an explicit configuration
+    pub fn with_config(config: Config) -> Self {
+        let assets_dir = config.assets_dir.clone().unwrap_or_else(|| {
+            let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+            Self::locate_assets_directory(&current_dir)
+        });
 
         println!("Using assets directory: {:?}", assets_dir);
 
-        Self {
-            carts: DashMap::new(),
+        let catalog = load_or_default(&config.catalog_path, Catalog::load);
+        let coupons = load_or_default(&config.coupons_path, load_coupons);
+        let shipping_tiers = load_or_default(&config.shipping_tiers_path, load_shipping_tiers);
+        let tax_table = load_or_default(&config.tax_table_path, load_tax_table);
+        let tax_categories = load_or_default(&config.tax_categories_path, load_tax_categories);
+        let fx_rates = load_or_default(&config.fx_rates_path, load_fx_rates);
+        let aliases = load_or_default(&config.aliases_path, load_aliases);
+        let recommendations = load_or_default(&config.recommendations_path, load_recommendations);
+
+        let rate_limiter = RateLimiter::new(config.rate_limit_per_minute);
+        let stock_holds = StockHolds::new(config.stock_hold());
+        let cart_activity = CartActivity::new(config.cart_ttl());
+
+        let mut state = Self {
+            carts: CartMap::with_hasher(CartHasher::default()),
+            known_cart_ids: DashSet::new(),
             assets_dir,
+            config,
+            features: Features::default(),
+            catalog,
+            coupons,
+            shipping_tiers,
+            tax_table,
+            tax_categories,
+            fx_rates,
+            aliases,
+            tools: ToolRegistry::default(),
+            recommendations,
+            widget_cache: RwLock::new(None),
+            widget_fallback: RwLock::new(None),
+            shutdown: watch::channel(None).0,
+            sse_sessions: Arc::new(AtomicUsize::new(0)),
+            rate_limiter,
+            initialized_sessions: DashMap::new(),
+            idempotency_keys: DashMap::new(),
+            used_share_nonces: DashMap::new(),
+            orders: RwLock::new(VecDeque::new()),
+            stock_holds,
+            cart_activity,
+            persist_failures: AtomicU64::new(0),
+            cart_saves: persist::CartSaves::default(),
+            deprecated_calls: AtomicU64::new(0),
+        };
+        state.refresh_features();
+        state.with_saved_carts()
+    }
+
+    /// Loads carts saved in `CART_DATA_DIR`, if any
+    fn with_saved_carts(self) -> Self {
+        let Some(dir) = &self.config.cart_data_dir else {
+            return self;
+        };
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Cannot create cart data directory {:?}: {}", dir, e);
+            return self;
+        }
+        match persist::load_carts(dir) {
+            Ok(carts) => {
+                println!("Restored {} saved carts from {:?}", carts.len(), dir);
+                for (cart_id, cart) in carts {
+                    self.remember_cart_id(&cart_id);
+                    self.cart_activity.touch(&cart_id);
+                    self.carts.insert(cart_id, cart);
+                }
+            }
+            Err(e) => eprintln!("Cannot read saved carts from {:?}: {}", dir, e),
+        }
+        self
+    }
+
+    /// Saves a cart's current state to `CART_DATA_DIR`. Failures are logged and
+    /// counted; they only become errors with `STRICT_PERSISTENCE`, and the change
+    /// stays in memory either way, which the error says so clients don't retry it.
+    pub async fn persist_cart(&self, cart_id: &str) -> Result<(), String> {
+        let Some(dir) = &self.config.cart_data_dir else {
+            return Ok(());
+        };
+        match self.cart_saves.save(dir, &self.carts, cart_id).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.persist_failures.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Saving cart {} failed: {}", cart_id, e);
+                if self.config.strict_persistence {
+                    Err(format!(
+                        "Cart {} was changed but could not be saved: {}",
+                        cart_id, e
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Stock left for each cart line after this cart's quantities and other carts' holds
+    pub fn availability(&self, cart_id: &str, items: &[CartItem]) -> Vec<ItemAvailability> {
+        let mut availability = self.catalog.availability(items);
+        if self.stock_holds.enabled() {
+            for entry in &mut availability {
+                if let (Some(remaining), Some(product)) = (
+                    entry.remaining_stock.as_mut(),
+                    self.catalog.get(&entry.name),
+                ) {
+                    let held = self.stock_holds.held_elsewhere(cart_id, &product.name);
+                    *remaining = remaining.saturating_sub(held);
+                }
+            }
+        }
+        availability
+    }
+
+    /// Rejects items whose combined quantity would exceed `MAX_TOTAL_QTY`; callers pass
+    /// the cart's items as they would be after the change
+    fn check_total_quantity(&self, items: &[CartItem]) -> Result<(), String> {
+        let cap = self.config.max_total_quantity();
+        let total: u64 = items.iter().map(|item| u64::from(item.quantity)).sum();
+        if total > cap {
+            return Err(format!(
+                "Cart would hold {} units, over the limit of {} (MAX_TOTAL_QTY)",
+                total, cap
+            ));
+        }
+        Ok(())
+    }
+
+    /// Admits a cart's items as they will be after a change: checks them against
+    /// `MAX_TOTAL_QTY`, then reserves their stock. Every path that replaces a cart's
+    /// items goes through here, under the cart's lock.
+    pub fn admit_items(&self, cart_id: &str, items: &[CartItem]) -> Result<(), String> {
+        self.check_total_quantity(items)?;
+        self.hold_stock(cart_id, items)
+    }
+
+    /// Reserves the cart's quantity of each stock-tracked product it holds, releasing
+    /// holds on products it no longer has. Fails without changing any hold when a
+    /// product lacks the free stock for this cart.
+    pub fn hold_stock(&self, cart_id: &str, items: &[CartItem]) -> Result<(), String> {
+        if !self.stock_holds.enabled() {
+            return Ok(());
+        }
+
+        let mut wanted: Vec<(&str, u32, u32)> = Vec::new();
+        for item in items {
+            let Some(product) = self.catalog.get(&item.name) else {
+                continue;
+            };
+            let Some(stock) = product.stock else {
+                continue;
+            };
+            match wanted.iter_mut().find(|(name, _, _)| *name == product.name) {
+                Some((_, quantity, _)) => *quantity = quantity.saturating_add(item.quantity),
+                None => wanted.push((&product.name, item.quantity, stock)),
+            }
+        }
+
+        self.stock_holds.reserve(cart_id, &wanted)
+    }
+
+    /// Adds a checked-out order to the ledger, dropping the oldest once it is full
+    pub fn record_order(&self, order: OrderRecord) {
+        let mut orders = self.orders.write().unwrap();
+        if orders.len() >= MAX_LEDGER_ORDERS {
+            orders.pop_front();
+        }
+        orders.push_back(order);
+    }
+
+    /// Aggregates orders placed within `[from, to]` (Unix seconds, both optional).
+    /// Revenue is summed in minor units so it matches the receipts to the cent.
+    pub fn sales_stats(&self, from: Option<u64>, to: Option<u64>) -> SalesStats {
+        let decimals = self.pricing_policy().decimals;
+        let orders = self.orders.read().unwrap();
+        let in_range = orders.iter().filter(|order| {
+            from.is_none_or(|from| order.placed_at >= from)
+                && to.is_none_or(|to| order.placed_at <= to)
+        });
+
+        let (mut count, mut units_sold) = (0, 0u64);
+        let mut revenue = Money::from_amount(0.0, decimals);
+        for order in in_range {
+            count += 1;
+            units_sold = units_sold.saturating_add(order.units);
+            revenue = revenue + order.total;
+        }
+        SalesStats {
+            orders: count,
+            units_sold,
+            revenue: revenue.amount(),
+        }
+    }
+
+    /// Returns the cart for `cart_id`, creating (and recording) it if missing
+    #[cfg(test)]
+    pub fn cart_entry(&self, cart_id: &str) -> dashmap::mapref::one::RefMut<'_, String, Cart> {
+        self.remember_cart_id(cart_id);
+        self.cart_activity.touch(cart_id);
+        self.carts.entry(cart_id.to_string()).or_default()
+    }
+
+    /// Records a cart id as known. Once more than `MAX_DEPARTED_CART_IDS` known ids have
+    /// no cart, those departed ids are dropped, so the set stays near the live cart count.
+    /// Callers must not hold a `carts` entry.
+    fn remember_cart_id(&self, cart_id: &str) {
+        if self.known_cart_ids.contains(cart_id) {
+            return;
+        }
+        self.known_cart_ids.insert(cart_id.to_string());
+        if self.known_cart_ids.len() > self.carts.len() + MAX_DEPARTED_CART_IDS {
+            self.known_cart_ids
+                .retain(|known| known == cart_id || self.carts.contains_key(known));
+        }
+    }
+
+    /// Returns true when the cart exists but holds no items
+    pub fn is_empty_cart(&self, cart_id: &str) -> bool {
+        self.carts
+            .get(cart_id)
+            .is_some_and(|cart| cart.items.is_empty())
+    }
+
+    /// Validates a client-supplied cart id against the configured limits
+    pub fn check_cart_id(&self, cart_id: &str) -> Result<(), String> {
+        validate_cart_id(cart_id, self.config.max_cart_id_len())
+    }
+
+    /// Runs `f` on the cart under its map lock, creating (and recording) the cart if missing.
+    /// Nothing else can read or write the cart until `f` returns, so read-modify-write
+    /// sequences inside it are atomic. `f` must not touch `self.carts` itself.
+    #[cfg(test)]
+    pub fn with_cart_mut<F, R>(&self, cart_id: &str, f: F) -> R
+    where
+        F: FnOnce(&mut Cart) -> R,
+    {
+        let mut cart = self.cart_entry(cart_id);
+        observe_cart(cart_id, &mut cart, f)
+    }
+
+    /// Like `with_cart_mut` for changes that can be refused: a missing cart is only
+    /// created (and recorded) when `f` succeeds, so a refused change leaves no empty cart
+    pub fn try_with_cart_mut<F, T, E>(&self, cart_id: &str, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Cart) -> Result<T, E>,
+    {
+        match self.carts.entry(cart_id.to_string()) {
+            Entry::Occupied(mut entry) => {
+                self.cart_activity.touch(cart_id);
+                observe_cart(cart_id, entry.get_mut(), f)
+            }
+            Entry::Vacant(entry) => {
+                let mut cart = Cart::default();
+                let output = observe_cart(cart_id, &mut cart, f)?;
+                self.cart_activity.touch(cart_id);
+                entry.insert(cart);
+                // After the entry is released, since pruning reads `carts`
+                self.remember_cart_id(cart_id);
+                Ok(output)
+            }
+        }
+    }
+
+    /// Like `with_cart_mut`, but leaves unknown carts alone and returns `None` for them
+    pub fn with_existing_cart_mut<F, R>(&self, cart_id: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Cart) -> R,
+    {
+        let mut cart = self.carts.get_mut(cart_id)?;
+        self.cart_activity.touch(cart_id);
+        Some(observe_cart(cart_id, &mut cart, f))
+    }
+
+    /// Reports whether a cart has items, is known but empty, or was never created
+    pub fn cart_status(&self, cart_id: &str) -> CartStatus {
+        match self.carts.get(cart_id) {
+            Some(cart) if !cart.items.is_empty() => CartStatus::Active,
+            Some(_) => CartStatus::Empty,
+            None if self.known_cart_ids.contains(cart_id) => CartStatus::Empty,
+            None => CartStatus::NotFound,
+        }
+    }
+
+    /// Corrects and validates incoming items and applies the configured price authority.
+    /// Returns warnings describing any adjustments made.
+    pub fn prepare_items(&self, items: &mut [CartItem]) -> Result<Vec<String>, ToolError> {
+        let mut warnings = Vec::new();
+
+        for item in items.iter_mut() {
+            item.name = strip_control_chars(&item.name);
+        }
+
+        if let Some(max_distance) = self.config.fuzzy_match_distance {
+            warnings.extend(self.catalog.correct_names(items, max_distance));
+        }
+
+        validate_items(items, &self.config)?;
+
+        if self.config.price_source == PriceSource::Catalog {
+            self.catalog.apply_prices(items)?;
+        }
+
+        warnings.extend(resolve_conflicting_extras(
+            items,
+            self.config.conflicting_extras,
+        )?);
+
+        // Totals skip prices they can't read, so say so rather than undercharge quietly
+        for item in items.iter() {
+            if item
+                .extra
+                .get("price")
+                .is_some_and(|price| !price.is_number())
+            {
+                warnings.push(format!(
+                    "{} has a non-numeric price; it counts as 0 in the total",
+                    item.name
+                ));
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Prepares each item on its own, keeping those that pass and reporting the rest
+    /// by their index in `items`
+    pub fn prepare_items_leniently(
+        &self,
+        items: Vec<CartItem>,
+    ) -> (Vec<CartItem>, Vec<RejectedItem>) {
+        let mut accepted = Vec::with_capacity(items.len());
+        let mut rejected = Vec::new();
+        for (index, mut item) in items.into_iter().enumerate() {
+            match self.prepare_items(std::slice::from_mut(&mut item)) {
+                Ok(_) => accepted.push(item),
+                Err(err) => rejected.push(RejectedItem {
+                    index,
+                    error: item_error_reason(&err),
+                }),
+            }
+        }
+        (accepted, rejected)
+    }
+
+    /// Returns the pricing policy carts are totalled with
+    pub fn pricing_policy(&self) -> PricingPolicy<'_> {
+        PricingPolicy {
+            coupons: &self.coupons,
+            tax_rate: self.config.tax_rate.unwrap_or(0.0),
+            category_rates: &self.tax_categories,
+            decimals: self.config.total_decimals(),
+        }
+    }
+
+    /// Replaces the coupon definitions
+    #[cfg(test)]
+    pub fn with_coupons(mut self, coupons: Vec<Coupon>) -> Self {
+        self.coupons = coupons
+            .into_iter()
+            .map(|c| (crate::pricing::normalize_coupon_code(&c.code), c))
+            .collect();
+        self.refresh_features();
+        self
+    }
+
+    /// Replaces the product catalog
+    #[cfg(test)]
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = catalog;
+        self.refresh_features();
+        self
+    }
+
+    /// Replaces the shipping tiers
+    #[cfg(test)]
+    pub fn with_shipping_tiers(mut self, tiers: Vec<ShippingTier>) -> Self {
+        self.shipping_tiers = tiers;
+        self.refresh_features();
+        self
+    }
+
+    /// Replaces the regional tax table
+    #[cfg(test)]
+    pub fn with_tax_table(mut self, rates: &[(&str, f64)]) -> Self {
+        self.tax_table = rates
+            .iter()
+            .map(|(region, rate)| (crate::pricing::normalize_region(region), *rate))
+            .collect();
+        self.refresh_features();
+        self
+    }
+
+    /// Replaces the exchange rates
+    #[cfg(test)]
+    pub fn with_fx_rates(mut self, rates: &[(&str, f64)]) -> Self {
+        self.fx_rates = rates
+            .iter()
+            .map(|(code, rate)| (code.to_ascii_uppercase(), *rate))
+            .collect();
+        self.refresh_features();
+        self
+    }
+
+    /// Serves `tools` alongside the built-in tools, replacing built-ins of the same name
+    pub fn with_tools(mut self, tools: ToolRegistry) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Replaces the alias table
+    #[cfg(test)]
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = aliases;
+        self.refresh_features();
+        self
+    }
+
+    /// Replaces the recommendation map
+    #[cfg(test)]
+    pub fn with_recommendations(mut self, recommendations: RecommendationMap) -> Self {
+        self.recommendations = recommendations;
+        self.refresh_features();
+        self
+    }
+
+    /// Derives the feature set from the configuration and the loaded data; test
+    /// builders call it again after swapping data
+    fn refresh_features(&mut self) {
+        let loaded = LoadedCounts {
+            coupons: self.coupons.len(),
+            shipping_tiers: self.shipping_tiers.len(),
+            tax_regions: self.tax_table.len(),
+            tax_categories: self.tax_categories.len(),
+            fx_rates: self.fx_rates.len(),
+            recommendations: self.recommendations.len(),
+            aliases: self.aliases.len(),
+        };
+        self.features = Features::new(&self.config, &self.catalog, loaded);
+    }
+
+    /// Logs and counts a call made through a deprecated alias, returning the current name
+    pub fn log_deprecation<'a>(&self, (name, warning): (&'a str, Option<String>)) -> &'a str {
+        if let Some(warning) = warning {
+            eprintln!("DEPRECATED: {}", warning);
+            self.deprecated_calls.fetch_add(1, Ordering::Relaxed);
+        }
+        name
+    }
+
+    /// Requests a graceful shutdown of the server with a logged reason
+    pub fn request_shutdown(&self, reason: impl Into<String>) {
+        self.shutdown.send_replace(Some(reason.into()));
+    }
+
+    /// Resolves with the shutdown reason once `request_shutdown` has been called
+    pub async fn shutdown_requested(&self) -> String {
+        let mut rx = self.shutdown.subscribe();
+        let reason = rx
+            .wait_for(Option::is_some)
+            .await
+            .map(|reason| reason.clone().unwrap_or_default());
+
+        match reason {
+            Ok(reason) => reason,
+            // The sender lives in AppState, so this only happens during teardown
+            Err(_) => std::future::pending().await,
+        }
+    }
+
+    /// Records that an MCP session completed `initialize`, forgetting idle sessions
+    pub fn mark_initialized(&self, session_id: &str) {
+        self.initialized_sessions
+            .retain(|_, used| used.elapsed() < MCP_SESSION_IDLE_TTL);
+        self.initialized_sessions
+            .insert(session_id.to_string(), Instant::now());
+    }
+
+    /// Returns true when the session completed `initialize`, refreshing its last use
+    pub fn is_initialized(&self, session_id: Option<&str>) -> bool {
+        let Some(mut used) = session_id.and_then(|id| self.initialized_sessions.get_mut(id)) else {
+            return false;
+        };
+        *used = Instant::now();
+        true
+    }
+
+    /// Forgets an MCP session, e.g. once its WebSocket closes
+    pub fn end_session(&self, session_id: &str) {
+        self.initialized_sessions.remove(session_id);
+    }
+
+    /// Claims an idempotency key for a call about to run, evicting keys older than the
+    /// TTL. Returns the stored result when the key already completed, and fails while
+    /// another call with the same key is still running.
+    pub fn claim_idempotency_key(&self, scope: &IdempotencyScope) -> Result<Option<Value>, String> {
+        let ttl = self.config.idempotency_ttl();
+        self.idempotency_keys
+            .retain(|_, (claimed, _)| claimed.elapsed() < ttl);
+        match self.idempotency_keys.entry(scope.clone()) {
+            Entry::Occupied(entry) => match &entry.get().1 {
+                IdempotentCall::Done(result) => Ok(Some(result.clone())),
+                IdempotentCall::Running => {
+                    Err("A call with this idempotencyKey is still running".to_string())
+                }
+            },
+            Entry::Vacant(entry) => {
+                entry.insert((Instant::now(), IdempotentCall::Running));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stores the result of a call that claimed an idempotency key, or releases the key
+    /// when the call failed so a retry runs again
+    pub fn settle_idempotency_key(&self, scope: &IdempotencyScope, result: Option<&Value>) {
+        match result {
+            Some(result) => {
+                if let Some(mut entry) = self.idempotency_keys.get_mut(scope) {
+                    entry.1 = IdempotentCall::Done(result.clone());
+                }
+            }
+            None => {
+                self.idempotency_keys.remove(scope);
+            }
         }
     }
 
+    /// Marks a share link's nonce as used, returning false if it already was.
+    /// Nonces are forgotten once their link expires, since it is refused from then on.
+    pub fn redeem_share_nonce(&self, claims: &Claims) -> bool {
+        let now = crate::share::unix_now();
+        self.used_share_nonces.retain(|_, exp| *exp > now);
+        match self.used_share_nonces.entry(claims.nonce.clone()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(claims.exp);
+                true
+            }
+        }
+    }
+
+    /// Opens an SSE session, or returns `None` when `max_sse_connections` are already open
+    pub fn open_sse_session(&self) -> Option<SseSession> {
+        let max = self.config.max_sse_connections.unwrap_or(usize::MAX);
+        self.sse_sessions
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .ok()?;
+        Some(SseSession(self.sse_sessions.clone()))
+    }
+
     /// Attempts to locate the assets directory using a multi-step strategy
-    fn locate_assets_directory(current_dir: &PathBuf) -> PathBuf {
+    fn locate_assets_directory(current_dir: &Path) -> PathBuf {
         // Strategy to locate assets:
         // 1. ./assets
         // 2. ../assets (if running from a subdir)
@@ -142,8 +1477,66 @@ impl AppState {
         PathBuf::from("assets") // Fallback
     }
 
-    /// Reads the shopping-cart.html file or a fallback version
+    /// Returns the widget HTML, fetching it from `WIDGET_URL` or reading it from
+    /// disk on first use
     pub async fn load_widget_html(&self) -> Result<String, axum::http::StatusCode> {
+        if let Some(html) = self.widget_cache.read().unwrap().as_ref() {
+            return Ok(html.clone());
+        }
+
+        let timeout = self.config.asset_read_timeout();
+        if let Some(url) = &self.config.widget_url {
+            if let Some((read_at, html)) = self.widget_fallback.read().unwrap().as_ref() {
+                if read_at.elapsed() < WIDGET_FETCH_RETRY_AFTER {
+                    return Ok(html.clone());
+                }
+            }
+            match crate::remote::fetch_text(url, timeout).await {
+                Ok(html) => {
+                    self.widget_fallback.write().unwrap().take();
+                    *self.widget_cache.write().unwrap() = Some(html.clone());
+                    return Ok(html);
+                }
+                Err(e) => {
+                    // Local files stand in for a short while, then the URL is retried
+                    eprintln!(
+                        "Warning: fetching widget from {} failed ({}), using local assets",
+                        url, e
+                    );
+                    let html = match tokio::time::timeout(timeout, self.read_widget_html()).await {
+                        Ok(html) => html?,
+                        Err(_) => FALLBACK_WIDGET_HTML.to_string(),
+                    };
+                    *self.widget_fallback.write().unwrap() = Some((Instant::now(), html.clone()));
+                    return Ok(html);
+                }
+            }
+        }
+
+        let html = match tokio::time::timeout(timeout, self.read_widget_html()).await {
+            Ok(html) => html?,
+            Err(_) => {
+                // Serve the built-in fallback without caching it so the next read retries
+                eprintln!(
+                    "Warning: reading widget HTML timed out after {:?}, serving fallback",
+                    timeout
+                );
+                return Ok(FALLBACK_WIDGET_HTML.to_string());
+            }
+        };
+
+        *self.widget_cache.write().unwrap() = Some(html.clone());
+        Ok(html)
+    }
+
+    /// Drops the cached widget HTML so the next read goes back to disk or `WIDGET_URL`
+    pub fn invalidate_widget_cache(&self) {
+        self.widget_cache.write().unwrap().take();
+        self.widget_fallback.write().unwrap().take();
+    }
+
+    /// Reads the shopping-cart.html file or a fallback version
+    async fn read_widget_html(&self) -> Result<String, axum::http::StatusCode> {
         // First try the primary HTML file
         let primary_html_path = self.assets_dir.join("shopping-cart.html");
         if primary_html_path.exists() {
@@ -210,40 +1603,481 @@ pub fn rpc_success(id: Value, result: Value) -> Value {
 
 /// Wraps an error in a JSON-RPC 2.0 Error Response.
 pub fn rpc_error(id: Value, code: i32, message: impl Into<String>) -> Value {
+    rpc_error_with_data(id, code, message, None)
+}
+
+/// Checks a request id against JSON-RPC 2.0: requests need a string or integer id.
+/// Notifications carry no id and are always accepted.
+pub fn validate_request_id(method: &str, id: Option<&Value>) -> Result<(), &'static str> {
+    if method.starts_with("notifications/") {
+        return Ok(());
+    }
+    match id {
+        None | Some(Value::Null) => Err("Request id must not be null"),
+        Some(Value::Number(n)) if !(n.is_i64() || n.is_u64()) => {
+            Err("Request id must be an integer")
+        }
+        Some(Value::String(_) | Value::Number(_)) => Ok(()),
+        Some(_) => Err("Request id must be a string or integer"),
+    }
+}
+
+/// Wraps an error in a JSON-RPC 2.0 Error Response with the optional `data` member.
+pub fn rpc_error_with_data(
+    id: Value,
+    code: i32,
+    message: impl Into<String>,
+    data: Option<Value>,
+) -> Value {
+    let mut error = json!({
+        "code": code,
+        "message": message.into()
+    });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+
     json!({
         "jsonrpc": "2.0",
         "id": id,
-        "error": {
-            "code": code,
-            "message": message.into()
-        }
+        "error": error
     })
 }
 
+/// Validates incoming items: line discount shapes always, and extra keys in strict schema mode
+pub fn validate_items(items: &[CartItem], config: &Config) -> Result<(), ToolError> {
+    let mut messages = Vec::new();
+    let mut fields = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        if let Some(discount) = item.extra.get("discount") {
+            if let Err(reason) = parse_line_discount(discount) {
+                messages.push(format!(
+                    "Item {} has an invalid discount: {}",
+                    index, reason
+                ));
+                fields.push(json!({ "index": index, "field": "discount", "reason": reason }));
+            }
+        }
+
+        if let Some(weight) = item.extra.get("weight") {
+            if !weight.as_f64().is_some_and(|w| w >= 0.0 && w.is_finite()) {
+                let reason = "weight must be a non-negative number";
+                messages.push(format!("Item {} has an invalid weight: {}", index, reason));
+                fields.push(json!({ "index": index, "field": "weight", "reason": reason }));
+            }
+        }
+
+        if !config.strict_item_schema {
+            continue;
+        }
+
+        let mut unknown: Vec<&str> = item
+            .extra
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !ALLOWED_EXTRA_KEYS.contains(key))
+            .collect();
+
+        if !unknown.is_empty() {
+            unknown.sort_unstable();
+            messages.push(format!(
+                "Item {} has unsupported fields: {}",
+                index,
+                unknown.join(", ")
+            ));
+            fields.extend(unknown.iter().map(
+                |field| json!({ "index": index, "field": field, "reason": "unsupported field" }),
+            ));
+        }
+    }
+
+    if messages.is_empty() {
+        Ok(())
+    } else {
+        Err(ToolError::with_data(
+            messages.join("; "),
+            json!({ "fields": fields }),
+        ))
+    }
+}
+
+/// Describes why a single item failed validation, without the batch index the
+/// message carries
+fn item_error_reason(err: &ToolError) -> String {
+    let fields = err
+        .data
+        .as_ref()
+        .and_then(|data| data["fields"].as_array())
+        .filter(|fields| !fields.is_empty());
+    match fields {
+        Some(fields) => fields
+            .iter()
+            .map(|f| {
+                format!(
+                    "{}: {}",
+                    f["field"].as_str().unwrap_or(""),
+                    f["reason"].as_str().unwrap_or("")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; "),
+        None => err.message.clone(),
+    }
+}
+
+/// Lists extra item fields outside `ALLOWED_EXTRA_KEYS`, which are kept but otherwise ignored
+pub fn unknown_extra_fields(items: &[CartItem]) -> Vec<FieldWarning> {
+    let mut warnings = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let mut unknown: Vec<&String> = item
+            .extra
+            .keys()
+            .filter(|key| !ALLOWED_EXTRA_KEYS.contains(&key.as_str()))
+            .collect();
+        unknown.sort_unstable();
+        warnings.extend(unknown.into_iter().map(|field| FieldWarning {
+            index,
+            field: field.clone(),
+            reason: "unrecognized field, stored but ignored".to_string(),
+        }));
+    }
+    warnings
+}
+
+/// Checks a client-supplied cart id: non-empty, at most `max_len` characters, and made only
+/// of ASCII letters, digits, `-` and `_`
+pub fn validate_cart_id(cart_id: &str, max_len: usize) -> Result<(), String> {
+    if cart_id.is_empty() {
+        return Err("cartId must not be empty".to_string());
+    }
+    if cart_id.len() > max_len {
+        return Err(format!("cartId is longer than {} characters", max_len));
+    }
+    if !cart_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("cartId may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
 /// Generates a new cart ID if none is provided
 pub fn get_or_create_cart_id(cart_id: Option<String>) -> String {
     cart_id.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string())
 }
 
-/// Updates the cart with new items, aggregating quantities for existing items
-pub fn update_cart_with_new_items(cart_items: &mut Vec<CartItem>, new_items: Vec<CartItem>) {
+/// Applies an RFC 6902 JSON Patch (add/remove/replace only) to a copy of the items
+pub fn apply_items_patch(items: &[CartItem], patch: Value) -> Result<Vec<CartItem>, String> {
+    let patch: json_patch::Patch =
+        serde_json::from_value(patch).map_err(|e| format!("Invalid patch: {}", e))?;
+
+    for op in &patch.0 {
+        if !matches!(
+            op,
+            json_patch::PatchOperation::Add(_)
+                | json_patch::PatchOperation::Remove(_)
+                | json_patch::PatchOperation::Replace(_)
+        ) {
+            return Err("Only add, remove and replace operations are supported".to_string());
+        }
+    }
+
+    let mut doc = serde_json::to_value(items).map_err(|e| e.to_string())?;
+    json_patch::patch(&mut doc, &patch).map_err(|e| format!("Patch failed: {}", e))?;
+    serde_json::from_value(doc).map_err(|e| format!("Patch produced invalid items: {}", e))
+}
+
+/// Applies metadata updates (null removes a key), refusing results over the size cap
+pub fn merge_cart_metadata(
+    metadata: &HashMap<String, Value>,
+    updates: HashMap<String, Value>,
+) -> Result<HashMap<String, Value>, String> {
+    let mut merged = metadata.clone();
+    for (key, value) in updates {
+        if value.is_null() {
+            merged.remove(&key);
+        } else {
+            merged.insert(key, value);
+        }
+    }
+
+    let size = serde_json::to_string(&merged).map_or(0, |json| json.len());
+    if size > MAX_CART_METADATA_BYTES {
+        return Err(format!(
+            "Cart metadata is {} bytes, over the {} byte limit",
+            size, MAX_CART_METADATA_BYTES
+        ));
+    }
+    Ok(merged)
+}
+
+/// Settles items repeated within one batch with different extras. Aggregation keeps
+/// only the extras of an item's first occurrence, so in last-wins mode the last
+/// occurrence's extras are copied onto it. Returns a warning per conflicting
+/// occurrence, or an error naming them all in error mode.
+pub fn resolve_conflicting_extras(
+    items: &mut [CartItem],
+    mode: ConflictingExtras,
+) -> Result<Vec<String>, ToolError> {
+    let mut warnings = Vec::new();
+    let mut messages = Vec::new();
+    let mut fields = Vec::new();
+    for index in 1..items.len() {
+        let Some(first) = items[..index]
+            .iter()
+            .position(|item| item.name == items[index].name)
+        else {
+            continue;
+        };
+        let (earlier, later) = items.split_at_mut(index);
+        let (kept, repeat) = (&mut earlier[first], &later[0]);
+        let mut conflicting: Vec<&str> = kept
+            .extra
+            .keys()
+            .chain(repeat.extra.keys())
+            .filter(|key| kept.extra.get(*key) != repeat.extra.get(*key))
+            .map(String::as_str)
+            .collect();
+        if conflicting.is_empty() {
+            continue;
+        }
+        conflicting.sort_unstable();
+        conflicting.dedup();
+        let conflicting = conflicting.join(", ");
+
+        match mode {
+            ConflictingExtras::Error => {
+                messages.push(format!(
+                    "Item {} repeats {} from item {} with different {}",
+                    index, repeat.name, first, conflicting
+                ));
+                fields.push(json!({
+                    "index": index,
+                    "field": conflicting,
+                    "reason": format!("conflicts with item {}", first)
+                }));
+            }
+            ConflictingExtras::FirstWins => warnings.push(format!(
+                "Item {} repeats {} with different {}; keeping item {}'s",
+                index, repeat.name, conflicting, first
+            )),
+            ConflictingExtras::LastWins => {
+                warnings.push(format!(
+                    "Item {} repeats {} with different {}; using item {}'s",
+                    index, repeat.name, conflicting, index
+                ));
+                kept.extra = repeat.extra.clone();
+            }
+        }
+    }
+
+    if messages.is_empty() {
+        Ok(warnings)
+    } else {
+        Err(ToolError::with_data(
+            messages.join("; "),
+            json!({ "fields": fields }),
+        ))
+    }
+}
+
+/// Updates the cart with new items, aggregating quantities for existing items.
+/// Returns what happened to each new item, in order.
+pub fn update_cart_with_new_items(
+    cart_items: &mut Vec<CartItem>,
+    new_items: Vec<CartItem>,
+) -> Vec<ItemAction> {
+    let mut actions = Vec::with_capacity(new_items.len());
     for incoming in new_items {
         if let Some(existing) = cart_items.iter_mut().find(|i| i.name == incoming.name) {
-            // Aggregate quantities for existing items
-            existing.quantity += incoming.quantity;
+            // Aggregate quantities for existing items, saturating rather than overflowing
+            existing.quantity = existing.quantity.saturating_add(incoming.quantity);
             // Note: The Python version doesn't merge extra fields, it just updates quantity
+            actions.push(ItemAction::Aggregated);
         } else {
             // Add new items to the cart
             cart_items.push(incoming);
+            actions.push(ItemAction::Created);
         }
     }
+    actions
 }
 
-/// Formats items into a readable summary string
-pub fn format_item_summary(items: &[CartItem]) -> String {
-    items
+tokio::task_local! {
+    /// Changes the cart helpers saw while a `tools/call` with `includeDiff` runs
+    static CART_CHANGES: RefCell<Vec<CartChange>>;
+}
+
+/// A cart's lines before and after a tool call, both read under the cart's lock
+#[derive(Debug)]
+pub struct CartChange {
+    pub cart_id: String,
+    pub before: Vec<CartItem>,
+    pub after: Vec<CartItem>,
+}
+
+/// Runs a tool call, collecting the changes it made to each cart as they happened
+pub async fn capture_cart_changes<F: Future>(call: F) -> (F::Output, Vec<CartChange>) {
+    CART_CHANGES
+        .scope(RefCell::new(Vec::new()), async {
+            let output = call.await;
+            (output, CART_CHANGES.with(RefCell::take))
+        })
+        .await
+}
+
+/// Runs `f` on a locked cart, recording its lines around the call when changes are
+/// being captured
+fn observe_cart<R>(cart_id: &str, cart: &mut Cart, f: impl FnOnce(&mut Cart) -> R) -> R {
+    let Ok(before) = CART_CHANGES.try_with(|_| cart.items.clone()) else {
+        return f(cart);
+    };
+    let output = f(cart);
+    record_cart_change(cart_id, &before, &cart.items);
+    output
+}
+
+/// Records a cart change when changes are being captured; a cart changed twice keeps
+/// its first `before` and last `after`
+pub fn record_cart_change(cart_id: &str, before: &[CartItem], after: &[CartItem]) {
+    let _ = CART_CHANGES.try_with(|changes| {
+        let mut changes = changes.borrow_mut();
+        match changes.iter_mut().find(|change| change.cart_id == cart_id) {
+            Some(change) => change.after = after.to_vec(),
+            None => changes.push(CartChange {
+                cart_id: cart_id.to_string(),
+                before: before.to_vec(),
+                after: after.to_vec(),
+            }),
+        }
+    });
+}
+
+/// Describes how a cart's lines changed, matching lines by name: `added` and
+/// `changed` follow the new order, `removed` the old one
+pub fn diff_cart_items(before: &[CartItem], after: &[CartItem]) -> Value {
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for item in after {
+        match before.iter().find(|old| old.name == item.name) {
+            None => added.push(json!(item)),
+            Some(old) if old != item => changed.push(json!({
+                "name": item.name,
+                "before": old,
+                "after": item
+            })),
+            Some(_) => {}
+        }
+    }
+    let removed: Vec<&CartItem> = before
         .iter()
+        .filter(|old| !after.iter().any(|item| item.name == old.name))
+        .collect();
+    json!({ "added": added, "removed": removed, "changed": changed })
+}
+
+/// Formats a count with its noun using simple English plural rules ("1 item", "2 items")
+pub fn pluralize(count: usize, noun: &str) -> String {
+    if count == 1 {
+        return format!("1 {}", noun);
+    }
+
+    let plural = match noun.strip_suffix('y') {
+        // "category" -> "categories", but "day" -> "days"
+        Some(stem) if !stem.ends_with(['a', 'e', 'i', 'o', 'u']) => format!("{}ies", stem),
+        _ if noun.ends_with(['s', 'x', 'z']) || noun.ends_with("ch") || noun.ends_with("sh") => {
+            format!("{}es", noun)
+        }
+        _ => format!("{}s", noun),
+    };
+    format!("{} {}", count, plural)
+}
+
+/// Formats items into a readable summary string, listing at most `max_items`
+pub fn format_item_summary(items: &[CartItem], max_items: usize) -> String {
+    if items.is_empty() {
+        return "no items".to_string();
+    }
+
+    let mut summary = items
+        .iter()
+        .take(max_items)
         .map(|i| format!("{}x {}", i.quantity, i.name))
         .collect::<Vec<_>>()
-        .join(", ")
+        .join(", ");
+    if items.len() > max_items {
+        summary.push_str(&format!(" …and {} more", items.len() - max_items));
+    }
+    summary
+}
+
+/// Generates a new order identifier for a checkout receipt
+pub fn new_order_id() -> String {
+    format!("order_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Resolves a payment link from a template containing an `{orderId}` placeholder.
+/// The result must be an absolute http(s) URL.
+pub fn resolve_payment_url(template: &str, order_id: &str) -> Result<String, String> {
+    let url = template.replace("{orderId}", order_id);
+    let uri: axum::http::Uri = url
+        .parse()
+        .map_err(|_| format!("Invalid payment URL: {}", url))?;
+
+    match (uri.scheme_str(), uri.host()) {
+        (Some("http") | Some("https"), Some(host)) if !host.is_empty() => Ok(url),
+        _ => Err(format!("Invalid payment URL: {}", url)),
+    }
+}
+
+/// Removes control characters (newlines, escapes, bells...) that break widget rendering
+pub fn strip_control_chars(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// Escapes the characters HTML treats as markup
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes serialized JSON so it can be embedded safely inside a `<script>` element.
+/// The `\uXXXX` escapes keep the payload valid JSON while preventing `</script>` breakouts.
+pub fn escape_json_for_html(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        match c {
+            '<' => escaped.push_str("\\u003c"),
+            '>' => escaped.push_str("\\u003e"),
+            '&' => escaped.push_str("\\u0026"),
+            '\u{2028}' => escaped.push_str("\\u2028"),
+            '\u{2029}' => escaped.push_str("\\u2029"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Injects cart data into the widget HTML as a JSON script tag before `</body>`
+pub fn inject_cart_data(html: &str, cart: &Value) -> String {
+    let script = format!(
+        "<script type=\"application/json\" id=\"cart-data\">{}</script>",
+        escape_json_for_html(&cart.to_string())
+    );
+
+    match html.rfind("</body>") {
+        Some(pos) => format!("{}{}{}", &html[..pos], script, &html[pos..]),
+        None => format!("{}{}", html, script),
+    }
 }
@@ -0,0 +1,211 @@
+//! Cart persistence on disk
+//!
+//! With `CART_DATA_DIR` set, each cart is written to `<dir>/<cart id>.json` after a
+//! tool or sync changes it, removed once the cart is gone, and loaded back at
+//! startup. Writes are best-effort: memory stays the source of truth, and a failed
+//! write is logged and counted rather than failing the request, unless
+//! `STRICT_PERSISTENCE` is set. Failures go to stderr like the rest of the
+//! server's logging; the server doesn't set up `tracing`.
+//!
+//! A save snapshots the cart under a brief read lock and writes it on the blocking
+//! pool, so a slow disk never holds up other carts or the runtime. Each snapshot is
+//! versioned; saves of one cart never overlap, and one whose snapshot is older than
+//! what was already written is dropped.
+
+use crate::model::Cart;
+use dashmap::DashMap;
+use std::{
+    fs,
+    hash::BuildHasher,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Orders the saves of each cart by snapshot version
+#[derive(Debug, Default)]
+pub struct CartSaves {
+    /// Last version handed out, across all carts
+    versions: AtomicU64,
+
+    /// Per cart, the newest version written, locked while that cart's file is written.
+    /// Kept while the cart exists or a save of it is pending.
+    written: DashMap<String, Arc<Mutex<u64>>>,
+}
+
+impl CartSaves {
+    /// Writes the current state of a cart in `carts`, or removes its file when the cart
+    /// is gone. Returns once the write is done or was dropped as stale.
+    pub async fn save<S: BuildHasher + Clone>(
+        &self,
+        dir: &Path,
+        carts: &DashMap<String, Cart, S>,
+        cart_id: &str,
+    ) -> io::Result<()> {
+        // Taken before the version, so a pending save always keeps the slot alive
+        let slot = self.written.entry(cart_id.to_string()).or_default().clone();
+        let (cart, version) = self.snapshot(carts, cart_id);
+        let removed = cart.is_none();
+
+        let (dir, id) = (dir.to_path_buf(), cart_id.to_string());
+        let saved = tokio::task::spawn_blocking(move || {
+            let mut written = slot.lock().unwrap();
+            if *written > version {
+                return Ok(());
+            }
+            *written = version;
+            save_cart(&dir, &id, cart.as_ref())
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        if removed {
+            self.written
+                .remove_if(cart_id, |_, slot| Arc::strong_count(slot) == 1);
+        }
+        saved
+    }
+
+    /// Clones a cart with a version that orders it against every other snapshot of it
+    fn snapshot<S: BuildHasher + Clone>(
+        &self,
+        carts: &DashMap<String, Cart, S>,
+        cart_id: &str,
+    ) -> (Option<Cart>, u64) {
+        loop {
+            // Under the read lock no write can land between the clone and the version
+            if let Some(cart) = carts.get(cart_id) {
+                return (Some(cart.clone()), self.next_version());
+            }
+            // Without an entry to lock, confirm the cart is still gone once versioned
+            let version = self.next_version();
+            if !carts.contains_key(cart_id) {
+                return (None, version);
+            }
+        }
+    }
+
+    fn next_version(&self) -> u64 {
+        self.versions.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+/// Path of a cart's file; cart ids are restricted to filename-safe characters
+fn cart_path(dir: &Path, cart_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", cart_id))
+}
+
+/// Writes a cart to its file, or removes the file when the cart no longer exists.
+/// The write goes through a uniquely named temporary file so a crash never leaves
+/// half a cart and no other save can move it away mid-write.
+pub fn save_cart(dir: &Path, cart_id: &str, cart: Option<&Cart>) -> io::Result<()> {
+    let path = cart_path(dir, cart_id);
+    let Some(cart) = cart else {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    };
+
+    let json = serde_json::to_vec(cart)?;
+    let tmp = path.with_extension(format!("json.{}.tmp", uuid::Uuid::new_v4().simple()));
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, &path)
+}
+
+/// Checks that carts can be saved by writing and removing a probe file, named uniquely
+/// so concurrent checks don't remove each other's probe
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(
+        ".ready-probe.{}.tmp",
+        uuid::Uuid::new_v4().simple()
+    ));
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)
+}
+
+/// Reads every saved cart, skipping (and logging) files that don't parse
+pub fn load_carts(dir: &Path) -> io::Result<Vec<(String, Cart)>> {
+    let mut carts = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(cart_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let parsed = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|raw| serde_json::from_slice::<Cart>(&raw).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(cart) => carts.push((cart_id.to_string(), cart)),
+            Err(e) => eprintln!("Skipping saved cart {:?}: {}", path, e),
+        }
+    }
+    Ok(carts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CartItem;
+
+    #[test]
+    fn test_save_load_and_remove() {
+        let dir = std::env::temp_dir().join(format!("carts_{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let cart = Cart {
+            items: vec![CartItem {
+                name: "Apple".into(),
+                quantity: 3,
+                extra: Default::default(),
+            }],
+            ..Cart::default()
+        };
+        save_cart(&dir, "saved_cart", Some(&cart)).unwrap();
+        assert_eq!(
+            load_carts(&dir).unwrap(),
+            vec![("saved_cart".to_string(), cart)]
+        );
+
+        save_cart(&dir, "saved_cart", None).unwrap();
+        assert!(load_carts(&dir).unwrap().is_empty());
+        // Removing a cart that was never saved is fine
+        save_cart(&dir, "saved_cart", None).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_saves_are_dropped() {
+        let dir = std::env::temp_dir().join(format!("carts_{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        let carts: DashMap<String, Cart> = DashMap::new();
+        carts.insert("stale_cart".into(), Cart::default());
+        let saves = CartSaves::default();
+
+        // A newer snapshot was already written, so this one isn't
+        saves
+            .written
+            .insert("stale_cart".into(), Arc::new(Mutex::new(u64::MAX)));
+        saves.save(&dir, &carts, "stale_cart").await.unwrap();
+        assert!(load_carts(&dir).unwrap().is_empty());
+
+        saves.written.clear();
+        saves.save(&dir, &carts, "stale_cart").await.unwrap();
+        assert_eq!(load_carts(&dir).unwrap().len(), 1);
+
+        // Once the cart and its file are gone, nothing is kept for it
+        carts.remove("stale_cart");
+        saves.save(&dir, &carts, "stale_cart").await.unwrap();
+        assert!(load_carts(&dir).unwrap().is_empty());
+        assert!(saves.written.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,720 @@
+//! Pricing helpers for the shopping cart application
+//!
+//! Money-related logic shared by the MCP tools and REST handlers lives here so
+//! that every response formats amounts the same way.
+
+use crate::model::CartItem;
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use std::{collections::HashMap, path::Path};
+
+// =============================================================================
+// Constants
+// =============================================================================
+
+/// Currency used when none is configured
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+// =============================================================================
+// Currency Metadata
+// =============================================================================
+
+/// Display metadata letting widgets format money without hardcoding rules
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CurrencyInfo {
+    /// ISO 4217 currency code
+    pub code: String,
+
+    /// Number of minor-unit digits shown after the decimal point
+    #[serde(rename = "decimalPlaces")]
+    pub decimal_places: u32,
+
+    /// Display symbol (falls back to the currency code)
+    pub symbol: String,
+}
+
+/// Builds the display metadata for an ISO 4217 currency code
+pub fn currency_info(code: &str) -> CurrencyInfo {
+    let code = code.to_ascii_uppercase();
+    CurrencyInfo {
+        decimal_places: currency_decimal_places(&code),
+        symbol: currency_symbol(&code).unwrap_or(&code).to_string(),
+        code,
+    }
+}
+
+/// Returns the number of minor-unit digits for a currency (ISO 4217 exponent)
+fn currency_decimal_places(code: &str) -> u32 {
+    match code {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// Returns the display symbol for well-known currencies
+fn currency_symbol(code: &str) -> Option<&'static str> {
+    match code {
+        "USD" | "AUD" | "CAD" | "NZD" | "SGD" | "HKD" | "MXN" => Some("$"),
+        "EUR" => Some("€"),
+        "GBP" => Some("£"),
+        "JPY" | "CNY" => Some("¥"),
+        "KRW" => Some("₩"),
+        "INR" => Some("₹"),
+        "CHF" => Some("CHF"),
+        _ => None,
+    }
+}
+
+// =============================================================================
+// Line Discounts
+// =============================================================================
+
+/// A per-line discount carried in an item's `discount` extra field,
+/// e.g. `{ "type": "percent", "value": 10 }`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum LineDiscount {
+    /// Percentage off the line subtotal (0-100)
+    Percent(f64),
+    /// Fixed amount off the line subtotal
+    Fixed(f64),
+}
+
+/// Pricing of a single cart line
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LinePricing {
+    pub name: String,
+    pub quantity: u32,
+    pub unit_price: Option<f64>,
+    /// `unit_price * quantity`
+    pub subtotal: f64,
+    /// Amount taken off by the line discount
+    pub discount: f64,
+    /// Line subtotal after the discount
+    pub total: f64,
+}
+
+/// Parses and validates a line discount value
+pub fn parse_line_discount(value: &serde_json::Value) -> Result<LineDiscount, String> {
+    let discount: LineDiscount = serde_json::from_value(value.clone()).map_err(|_| {
+        "discount must be { \"type\": \"percent\" | \"fixed\", \"value\": number }".to_string()
+    })?;
+
+    match discount {
+        LineDiscount::Percent(v) if !(0.0..=100.0).contains(&v) => {
+            Err("percent discount must be between 0 and 100".to_string())
+        }
+        LineDiscount::Fixed(v) if v < 0.0 || !v.is_finite() => {
+            Err("fixed discount must be a non-negative number".to_string())
+        }
+        _ => Ok(discount),
+    }
+}
+
+/// Computes the subtotal, discount, and total of a cart line
+pub fn line_pricing(item: &CartItem) -> LinePricing {
+    let unit_price = item.price();
+    let subtotal = unit_price.map_or(0.0, |p| p * item.quantity as f64);
+    let discount = item
+        .extra
+        .get("discount")
+        .and_then(|v| parse_line_discount(v).ok())
+        .map_or(0.0, |d| match d {
+            LineDiscount::Percent(pct) => subtotal * pct / 100.0,
+            LineDiscount::Fixed(amount) => amount,
+        })
+        .min(subtotal);
+
+    LinePricing {
+        name: item.name.clone(),
+        quantity: item.quantity,
+        unit_price,
+        subtotal,
+        discount,
+        total: subtotal - discount,
+    }
+}
+
+// =============================================================================
+// Coupons
+// =============================================================================
+
+/// How a coupon reduces the cart total
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CouponKind {
+    /// Percentage off the running total (value is 0-100)
+    Percent,
+    /// Fixed amount off the running total
+    Fixed,
+}
+
+/// A coupon definition loaded from configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Coupon {
+    /// Code entered by the shopper (matched case-insensitively)
+    pub code: String,
+
+    /// Discount type
+    #[serde(rename = "type")]
+    pub kind: CouponKind,
+
+    /// Percentage or fixed amount, depending on `kind`
+    pub value: f64,
+}
+
+/// A coupon applied to a cart along with the amount it took off
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AppliedCoupon {
+    pub code: String,
+    #[serde(rename = "type")]
+    pub kind: CouponKind,
+    pub discount: f64,
+}
+
+/// Price breakdown of a cart before and after coupons
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CouponBreakdown {
+    /// Sum of line prices before discounts
+    pub subtotal: f64,
+
+    /// Coupons in the order they were applied
+    pub applied_coupons: Vec<AppliedCoupon>,
+
+    /// Amount due after discounts
+    pub total: f64,
+}
+
+/// Normalizes a coupon code for lookups
+pub fn normalize_coupon_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+/// Loads coupon definitions from a JSON file containing an array of coupons
+pub fn load_coupons(path: &Path) -> Result<HashMap<String, Coupon>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read coupons {:?}: {}", path, e))?;
+    let coupons: Vec<Coupon> =
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid coupons {:?}: {}", path, e))?;
+    Ok(coupons
+        .into_iter()
+        .map(|c| (normalize_coupon_code(&c.code), c))
+        .collect())
+}
+
+/// Applies coupons in order to the subtotal. Each discount is taken from the
+/// running total and never pushes it below zero, so percentage coupons stack
+/// multiplicatively: 10% then 20% takes 28% off, not 30%.
+pub fn apply_coupons(subtotal: f64, coupons: &[&Coupon]) -> CouponBreakdown {
+    let mut total = subtotal;
+    let applied_coupons = coupons
+        .iter()
+        .map(|coupon| {
+            let discount = match coupon.kind {
+                CouponKind::Percent => total * coupon.value.clamp(0.0, 100.0) / 100.0,
+                CouponKind::Fixed => coupon.value.max(0.0),
+            }
+            .min(total);
+            total -= discount;
+
+            AppliedCoupon {
+                code: coupon.code.clone(),
+                kind: coupon.kind,
+                discount,
+            }
+        })
+        .collect();
+
+    CouponBreakdown {
+        subtotal,
+        applied_coupons,
+        total,
+    }
+}
+
+// =============================================================================
+// Regional Tax
+// =============================================================================
+
+/// Normalizes a region code (state or country) for tax table lookups
+pub fn normalize_region(region: &str) -> String {
+    region.trim().to_ascii_uppercase()
+}
+
+/// Loads a tax table from a JSON object mapping region codes to rates,
+/// e.g. `{ "CA": 0.0725, "DE": 0.19 }`
+pub fn load_tax_table(path: &Path) -> Result<HashMap<String, f64>, String> {
+    load_rate_map(path, "tax table", normalize_region, non_negative_rate)
+}
+
+/// Normalizes an item's tax category for rate lookups
+pub fn normalize_tax_category(category: &str) -> String {
+    category.trim().to_ascii_lowercase()
+}
+
+/// Loads tax rates by item category from a JSON object, e.g. `{ "food": 0.05 }`.
+/// Items in other categories, or none, pay the default `TAX_RATE`.
+pub fn load_tax_categories(path: &Path) -> Result<HashMap<String, f64>, String> {
+    load_rate_map(
+        path,
+        "tax categories",
+        normalize_tax_category,
+        non_negative_rate,
+    )
+}
+
+/// Tax rates may be zero but never negative, which totals would clamp to zero anyway
+fn non_negative_rate(rate: f64) -> Result<(), &'static str> {
+    if rate.is_finite() && rate >= 0.0 {
+        Ok(())
+    } else {
+        Err("must not be negative")
+    }
+}
+
+/// Loads exchange rates from a JSON object mapping currency codes to units per
+/// one unit of the base currency, e.g. `{ "EUR": 0.92, "JPY": 151.3 }`
+pub fn load_fx_rates(path: &Path) -> Result<HashMap<String, f64>, String> {
+    load_rate_map(
+        path,
+        "FX rates",
+        |code| code.trim().to_ascii_uppercase(),
+        |rate| {
+            if rate.is_finite() && rate > 0.0 {
+                Ok(())
+            } else {
+                Err("must be positive")
+            }
+        },
+    )
+}
+
+/// Loads a JSON object mapping keys to rates, normalizing each key. The whole file
+/// is refused if `validate` rejects any rate; its message names the requirement.
+fn load_rate_map(
+    path: &Path,
+    label: &str,
+    normalize: fn(&str) -> String,
+    validate: fn(f64) -> Result<(), &'static str>,
+) -> Result<HashMap<String, f64>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {} {:?}: {}", label, path, e))?;
+    let rates: HashMap<String, f64> =
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid {} {:?}: {}", label, path, e))?;
+    for (key, rate) in &rates {
+        validate(*rate).map_err(|requirement| {
+            format!("Invalid {} {:?}: {} {}", label, path, key, requirement)
+        })?;
+    }
+    Ok(rates
+        .into_iter()
+        .map(|(key, rate)| (normalize(&key), rate))
+        .collect())
+}
+
+// =============================================================================
+// Totals
+// =============================================================================
+
+/// Everything besides the items themselves that affects what a cart costs
+#[derive(Debug, Clone, Copy)]
+pub struct PricingPolicy<'a> {
+    /// Coupon definitions keyed by normalized code
+    pub coupons: &'a HashMap<String, Coupon>,
+
+    /// Tax rate applied after all discounts (0.08 = 8%)
+    pub tax_rate: f64,
+
+    /// Rates for items whose `taxCategory` is listed here, replacing `tax_rate`
+    pub category_rates: &'a HashMap<String, f64>,
+
+    /// Decimal places amounts are rounded to when serialized
+    pub decimals: u32,
+}
+
+impl PricingPolicy<'_> {
+    /// Tax rate for an item: its category's rate, or the default rate
+    pub fn tax_rate_for(&self, item: &CartItem) -> f64 {
+        item.tax_category()
+            .and_then(|category| {
+                self.category_rates
+                    .get(&normalize_tax_category(category))
+                    .copied()
+            })
+            .unwrap_or(self.tax_rate)
+            .max(0.0)
+    }
+}
+
+/// Money totals of a cart; the single source for responses and receipts.
+/// Amounts are kept exact and rounded to `decimals` only when serialized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totals {
+    /// Sum of line prices before any discount
+    pub subtotal: f64,
+
+    /// Line discounts plus coupon discounts
+    pub discount: f64,
+
+    /// Tax charged on the discounted amount
+    pub tax: f64,
+
+    /// Amount due
+    pub total: f64,
+
+    /// Coupons in the order they were applied
+    pub applied_coupons: Vec<AppliedCoupon>,
+
+    /// Decimal places amounts are rounded to when serialized
+    pub decimals: u32,
+}
+
+impl Totals {
+    /// Original subtotal minus the discounted pre-tax amount; zero without discounts
+    pub fn savings(&self) -> f64 {
+        self.discount.max(0.0)
+    }
+}
+
+impl Serialize for Totals {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let round = |amount| round_to(amount, self.decimals);
+        let applied_coupons: Vec<AppliedCoupon> = self
+            .applied_coupons
+            .iter()
+            .map(|c| AppliedCoupon {
+                discount: round(c.discount),
+                ..c.clone()
+            })
+            .collect();
+
+        let mut totals = serializer.serialize_struct("Totals", 6)?;
+        totals.serialize_field("subtotal", &round(self.subtotal))?;
+        totals.serialize_field("discount", &round(self.discount))?;
+        totals.serialize_field("savings", &round(self.savings()))?;
+        totals.serialize_field("tax", &round(self.tax))?;
+        totals.serialize_field("total", &round(self.total))?;
+        totals.serialize_field("appliedCoupons", &applied_coupons)?;
+        totals.end()
+    }
+}
+
+/// An exact amount of money held in integer minor units (cents at two decimals),
+/// so long sums such as sales totals don't pick up floating-point drift
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Money {
+    /// Amount in minor units
+    pub minor: i64,
+
+    /// Decimal places one major unit is split into
+    pub decimals: u32,
+}
+
+impl Money {
+    /// Rounds an amount to `decimals` places and stores it exactly
+    pub fn from_amount(amount: f64, decimals: u32) -> Self {
+        let decimals = decimals.min(12);
+        Self {
+            minor: (amount * 10f64.powi(decimals as i32)).round() as i64,
+            decimals,
+        }
+    }
+
+    /// Returns the amount in major units
+    pub fn amount(self) -> f64 {
+        self.minor as f64 / 10f64.powi(self.decimals as i32)
+    }
+}
+
+impl std::ops::Add for Money {
+    type Output = Money;
+
+    /// Adds two amounts; both sides must use the same number of decimals
+    fn add(self, other: Money) -> Money {
+        debug_assert_eq!(self.decimals, other.decimals);
+        Money {
+            minor: self.minor.saturating_add(other.minor),
+            decimals: self.decimals,
+        }
+    }
+}
+
+/// Rounds an amount half away from zero to `decimals` places
+pub fn round_to(amount: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals.min(12) as i32);
+    (amount * scale).round() / scale
+}
+
+/// Prices items: line discounts first, then coupons in order, then tax.
+/// Unpriced items contribute nothing and unknown coupon codes are skipped.
+pub fn compute_totals(
+    items: &[CartItem],
+    coupon_codes: &[String],
+    policy: &PricingPolicy,
+) -> Totals {
+    let lines: Vec<LinePricing> = items.iter().map(line_pricing).collect();
+    let subtotal: f64 = lines.iter().map(|l| l.subtotal).sum();
+    let line_discount: f64 = lines.iter().map(|l| l.discount).sum();
+
+    let coupons: Vec<&Coupon> = coupon_codes
+        .iter()
+        .filter_map(|code| policy.coupons.get(code))
+        .collect();
+    let discounted = subtotal - line_discount;
+    let breakdown = apply_coupons(discounted, &coupons);
+
+    let tax = if policy.category_rates.is_empty() {
+        breakdown.total * policy.tax_rate.max(0.0)
+    } else {
+        // Coupons cut every line by the same share, then each line pays its own rate
+        let coupon_share = if discounted > 0.0 {
+            breakdown.total / discounted
+        } else {
+            0.0
+        };
+        items
+            .iter()
+            .zip(&lines)
+            .map(|(item, line)| line.total * coupon_share * policy.tax_rate_for(item))
+            .sum()
+    };
+    Totals {
+        subtotal,
+        discount: subtotal - breakdown.total,
+        tax,
+        total: breakdown.total + tax,
+        applied_coupons: breakdown.applied_coupons,
+        decimals: policy.decimals,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currency_decimal_places() {
+        assert_eq!(currency_info("JPY").decimal_places, 0);
+        assert_eq!(currency_info("USD").decimal_places, 2);
+        assert_eq!(currency_info("usd").code, "USD");
+        assert_eq!(currency_info("USD").symbol, "$");
+        assert_eq!(currency_info("XYZ").symbol, "XYZ");
+    }
+
+    #[test]
+    fn test_percent_coupons_stack_multiplicatively() {
+        let percent = |code: &str, value: f64| Coupon {
+            code: code.into(),
+            kind: CouponKind::Percent,
+            value,
+        };
+        let (ten, twenty) = (percent("TEN", 10.0), percent("TWENTY", 20.0));
+
+        let breakdown = apply_coupons(100.0, &[&ten, &twenty]);
+        assert_eq!(breakdown.applied_coupons[1].discount, 18.0);
+        assert_eq!(breakdown.total, 72.0);
+    }
+
+    #[test]
+    fn test_apply_coupons_never_goes_negative() {
+        let ten_off = Coupon {
+            code: "TEN".into(),
+            kind: CouponKind::Percent,
+            value: 10.0,
+        };
+        let five = Coupon {
+            code: "FIVE".into(),
+            kind: CouponKind::Fixed,
+            value: 5.0,
+        };
+
+        let breakdown = apply_coupons(20.0, &[&ten_off, &five]);
+        assert_eq!(breakdown.applied_coupons[0].discount, 2.0);
+        assert_eq!(breakdown.applied_coupons[1].discount, 5.0);
+        assert_eq!(breakdown.total, 13.0);
+
+        let breakdown = apply_coupons(3.0, &[&five]);
+        assert_eq!(breakdown.applied_coupons[0].discount, 3.0);
+        assert_eq!(breakdown.total, 0.0);
+    }
+
+    #[test]
+    fn test_line_discounts() {
+        let discounted: CartItem = serde_json::from_value(serde_json::json!({
+            "name": "Apple",
+            "quantity": 2,
+            "price": 5.0,
+            "discount": { "type": "percent", "value": 50 }
+        }))
+        .unwrap();
+        let full_price: CartItem = serde_json::from_value(serde_json::json!({
+            "name": "Bread",
+            "price": 3.0,
+        }))
+        .unwrap();
+
+        let line = line_pricing(&discounted);
+        assert_eq!((line.subtotal, line.discount, line.total), (10.0, 5.0, 5.0));
+        let coupons = HashMap::new();
+        let category_rates = HashMap::new();
+        let policy = PricingPolicy {
+            coupons: &coupons,
+            tax_rate: 0.0,
+            category_rates: &category_rates,
+            decimals: 2,
+        };
+        assert_eq!(
+            compute_totals(&[discounted, full_price], &[], &policy).total,
+            8.0
+        );
+
+        assert!(
+            parse_line_discount(&serde_json::json!({ "type": "percent", "value": 150 })).is_err()
+        );
+        assert!(parse_line_discount(&serde_json::json!({ "type": "bogo" })).is_err());
+        assert_eq!(
+            parse_line_discount(&serde_json::json!({ "type": "fixed", "value": 1.5 })),
+            Ok(LineDiscount::Fixed(1.5))
+        );
+    }
+
+    #[test]
+    fn test_totals_combine_discounts_coupons_and_tax() {
+        let items: Vec<CartItem> = serde_json::from_value(serde_json::json!([
+            { "name": "Apple", "quantity": 2, "price": 10.0,
+              "discount": { "type": "percent", "value": 25 } },
+            { "name": "Bread", "price": 5.0 },
+            { "name": "Mystery" }
+        ]))
+        .unwrap();
+        let coupons: HashMap<String, Coupon> = [
+            Coupon {
+                code: "TEN".into(),
+                kind: CouponKind::Percent,
+                value: 10.0,
+            },
+            Coupon {
+                code: "FIVE".into(),
+                kind: CouponKind::Fixed,
+                value: 5.0,
+            },
+        ]
+        .into_iter()
+        .map(|c| (c.code.clone(), c))
+        .collect();
+        let category_rates = HashMap::new();
+        let policy = |tax_rate| PricingPolicy {
+            coupons: &coupons,
+            tax_rate,
+            category_rates: &category_rates,
+            decimals: 2,
+        };
+
+        // Line discount only: 25 - 5 = 20
+        let totals = compute_totals(&items, &[], &policy(0.0));
+        assert_eq!(
+            (totals.subtotal, totals.discount, totals.total),
+            (25.0, 5.0, 20.0)
+        );
+
+        // Line discount and coupons: 20 - 2 - 5 = 13; unknown codes are ignored
+        let codes = ["TEN".to_string(), "BOGUS".to_string(), "FIVE".to_string()];
+        let totals = compute_totals(&items, &codes, &policy(0.0));
+        assert_eq!((totals.discount, totals.total), (12.0, 13.0));
+        assert_eq!(totals.applied_coupons.len(), 2);
+
+        // Tax is charged on the discounted amount: 13 * 0.5
+        let totals = compute_totals(&items, &codes, &policy(0.5));
+        assert_eq!((totals.tax, totals.total), (6.5, 19.5));
+
+        // Tax alone
+        let totals = compute_totals(&items, &[], &policy(0.1));
+        assert_eq!(
+            (totals.discount, totals.tax, totals.total),
+            (5.0, 2.0, 22.0)
+        );
+
+        // An empty cart costs nothing
+        let totals = compute_totals(&[], &codes, &policy(0.5));
+        assert_eq!(totals.total, 0.0);
+    }
+
+    #[test]
+    fn test_tax_by_item_category() {
+        let items: Vec<CartItem> = serde_json::from_value(serde_json::json!([
+            { "name": "Bread", "quantity": 2, "price": 5.0, "taxCategory": "Food" },
+            { "name": "Lamp", "price": 20.0 }
+        ]))
+        .unwrap();
+        let coupons: HashMap<String, Coupon> = [Coupon {
+            code: "HALF".into(),
+            kind: CouponKind::Percent,
+            value: 50.0,
+        }]
+        .into_iter()
+        .map(|c| (c.code.clone(), c))
+        .collect();
+        let category_rates = HashMap::from([("food".to_string(), 0.05)]);
+        let policy = PricingPolicy {
+            coupons: &coupons,
+            tax_rate: 0.2,
+            category_rates: &category_rates,
+            decimals: 2,
+        };
+
+        // Food 10 * 0.05 + general 20 * 0.2
+        let totals = compute_totals(&items, &[], &policy);
+        assert_eq!((totals.tax, totals.total), (4.5, 34.5));
+
+        // A coupon halves every line, and so the tax on each
+        let totals = compute_totals(&items, &["HALF".to_string()], &policy);
+        assert_eq!((totals.tax, totals.total), (2.25, 17.25));
+    }
+
+    #[test]
+    fn test_tax_tables_refuse_negative_rates() {
+        let dir = std::env::temp_dir().join(format!("rates_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tax.json");
+
+        std::fs::write(&path, r#"{ "ca": 0.0725, "OR": 0 }"#).unwrap();
+        let table = load_tax_table(&path).unwrap();
+        assert_eq!(table["CA"], 0.0725);
+        assert_eq!(table["OR"], 0.0);
+
+        std::fs::write(&path, r#"{ "CA": 0.0725, "XX": -0.05 }"#).unwrap();
+        let err = load_tax_table(&path).unwrap_err();
+        assert!(err.ends_with("XX must not be negative"), "{}", err);
+        let err = load_tax_categories(&path).unwrap_err();
+        assert!(err.ends_with("XX must not be negative"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_totals_serialize_rounded() {
+        let items: Vec<CartItem> = serde_json::from_value(serde_json::json!([
+            { "name": "Gum", "quantity": 3, "price": 0.1 },
+            { "name": "Pen", "price": 9.999999 }
+        ]))
+        .unwrap();
+        let coupons = HashMap::new();
+        let category_rates = HashMap::new();
+        let policy = PricingPolicy {
+            coupons: &coupons,
+            tax_rate: 0.0725,
+            category_rates: &category_rates,
+            decimals: 2,
+        };
+
+        let totals = compute_totals(&items, &[], &policy);
+        assert_ne!(totals.subtotal, 10.3);
+        let json = serde_json::to_value(&totals).unwrap();
+        assert_eq!(json["subtotal"], 10.3);
+        assert_eq!(json["tax"], 0.75);
+        assert_eq!(serde_json::to_string(&json["total"]).unwrap(), "11.05");
+    }
+}
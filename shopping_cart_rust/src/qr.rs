@@ -0,0 +1,56 @@
+//! QR code rendering for scan-to-transfer
+//!
+//! Codes are rendered as black-on-white grayscale PNGs with the standard
+//! four-module quiet zone, and returned base64-encoded for MCP image content.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use qrcode::{Color, EcLevel, QrCode};
+
+/// Largest payload, in bytes, encoded into a QR code; denser codes scan poorly on phones
+pub const MAX_QR_PAYLOAD_BYTES: usize = 1024;
+
+/// Pixels per QR module
+const MODULE_PIXELS: usize = 4;
+
+/// Blank modules around the code, as required by the QR specification
+const QUIET_ZONE: usize = 4;
+
+/// Encodes a payload as a QR code and returns the PNG image, base64-encoded
+pub fn png_base64(payload: &str) -> Result<String, String> {
+    if payload.len() > MAX_QR_PAYLOAD_BYTES {
+        return Err(format!(
+            "QR payload is {} bytes, over the {} byte limit",
+            payload.len(),
+            MAX_QR_PAYLOAD_BYTES
+        ));
+    }
+
+    let code = QrCode::with_error_correction_level(payload, EcLevel::M)
+        .map_err(|e| format!("Cannot encode QR code: {}", e))?;
+    let modules = code.width();
+    let colors = code.to_colors();
+
+    let side = (modules + 2 * QUIET_ZONE) * MODULE_PIXELS;
+    let mut pixels = vec![255u8; side * side];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x0 = (index % modules + QUIET_ZONE) * MODULE_PIXELS;
+        let y0 = (index / modules + QUIET_ZONE) * MODULE_PIXELS;
+        for y in y0..y0 + MODULE_PIXELS {
+            pixels[y * side + x0..y * side + x0 + MODULE_PIXELS].fill(0);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| format!("Cannot write QR PNG: {}", e))?;
+
+    Ok(STANDARD.encode(png))
+}
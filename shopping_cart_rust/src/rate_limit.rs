@@ -0,0 +1,129 @@
+//! Per-client request rate limiting
+//!
+//! A fixed one-minute window per client IP. Behind a proxy the client IP comes
+//! from `X-Forwarded-For`, which is only trusted when `TRUST_PROXY` is set.
+//! Windows that have ended are pruned at most once a minute, so the map only holds
+//! clients seen recently.
+
+use axum::http::HeaderMap;
+use dashmap::DashMap;
+use std::{
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Length of a rate limiting window
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts requests per client IP within the current window
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// Requests allowed per window; `None` disables limiting
+    limit: Option<u32>,
+
+    /// Window start and request count, keyed by client IP
+    hits: DashMap<IpAddr, (Instant, u32)>,
+
+    /// When ended windows were last pruned from `hits`
+    pruned_at: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `limit` requests per minute per client
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limit,
+            hits: DashMap::new(),
+            pruned_at: Mutex::new(None),
+        }
+    }
+
+    /// Records a request from `ip`, returning false when it exceeds the limit
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+
+        let now = Instant::now();
+        self.prune(now);
+        let mut entry = self.hits.entry(ip).or_insert((now, 0));
+        let (started, count) = entry.value_mut();
+        if now.duration_since(*started) >= WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= limit
+    }
+
+    /// Drops ended windows, at most once per window length
+    fn prune(&self, now: Instant) {
+        {
+            let mut pruned_at = self.pruned_at.lock().unwrap();
+            if pruned_at.is_some_and(|at| now.duration_since(at) < WINDOW) {
+                return;
+            }
+            *pruned_at = Some(now);
+        }
+        self.hits
+            .retain(|_, (started, _)| now.duration_since(*started) < WINDOW);
+    }
+}
+
+/// The client IP resolved for a request, stored as a request extension so handlers
+/// can charge extra work (e.g. each request of a batch) to the same client
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+/// Resolves the client IP. With a trusted proxy that's the last `X-Forwarded-For`
+/// hop, the one the proxy appended; earlier hops come from the client and can be forged.
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trust_proxy: bool) -> Option<IpAddr> {
+    if trust_proxy {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    peer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_ip_trusts_forwarded_only_when_configured() {
+        let mut headers = HeaderMap::new();
+        // The client sent the first hop; the proxy appended the address it saw
+        headers.insert(
+            "x-forwarded-for",
+            "198.51.100.9, 203.0.113.7".parse().unwrap(),
+        );
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert_eq!(
+            client_ip(&headers, Some(peer), true),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(client_ip(&headers, Some(peer), false), Some(peer));
+        assert_eq!(client_ip(&HeaderMap::new(), Some(peer), true), Some(peer));
+    }
+
+    #[test]
+    fn test_ended_windows_are_pruned() {
+        let limiter = RateLimiter::new(Some(10));
+        let stale = Instant::now() - 2 * WINDOW;
+        limiter
+            .hits
+            .insert("203.0.113.1".parse().unwrap(), (stale, 3));
+
+        assert!(limiter.check("203.0.113.2".parse().unwrap()));
+        assert_eq!(limiter.hits.len(), 1);
+        assert!(limiter.hits.contains_key(&"203.0.113.2".parse().unwrap()));
+    }
+}
@@ -0,0 +1,120 @@
+//! Related-product recommendations
+//!
+//! Suggestions come from a JSON map (env `RECOMMENDATIONS_PATH`) of products
+//! bought together and of products that go with a category, e.g.
+//!
+//! ```json
+//! {
+//!   "related": { "Coffee": ["Milk", "Sugar"] },
+//!   "categories": { "fruit": ["Yogurt"] }
+//! }
+//! ```
+//!
+//! Only catalog products are suggested, and never ones already in the cart.
+
+use crate::catalog::Catalog;
+use crate::model::CartItem;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// Product and category associations used to suggest products
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct RecommendationMap {
+    /// Products often bought with the keyed product
+    #[serde(default)]
+    pub related: HashMap<String, Vec<String>>,
+
+    /// Products to suggest for carts holding the keyed category
+    #[serde(default)]
+    pub categories: HashMap<String, Vec<String>>,
+}
+
+impl RecommendationMap {
+    /// Number of products and categories with suggestions
+    pub fn len(&self) -> usize {
+        self.related.len() + self.categories.len()
+    }
+}
+
+/// A suggested product and why it was suggested
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Recommendation {
+    /// Catalog product name
+    pub name: String,
+
+    /// Catalog unit price, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+
+    /// Human-readable reason, e.g. "Often bought with Coffee"
+    pub reason: String,
+}
+
+/// Loads a recommendation map from a JSON file
+pub fn load_recommendations(path: &Path) -> Result<RecommendationMap, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read recommendations {:?}: {}", path, e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("Invalid recommendations {:?}: {}", path, e))
+}
+
+/// Suggests up to `limit` catalog products related to the cart's items.
+/// Product associations come before category ones, each in cart order.
+pub fn recommend(
+    map: &RecommendationMap,
+    catalog: &Catalog,
+    items: &[CartItem],
+    limit: usize,
+) -> Vec<Recommendation> {
+    let key = |name: &str| name.trim().to_lowercase();
+    let related: HashMap<String, &Vec<String>> =
+        map.related.iter().map(|(k, v)| (key(k), v)).collect();
+    let categories: HashMap<String, &Vec<String>> =
+        map.categories.iter().map(|(k, v)| (key(k), v)).collect();
+
+    // Anything in the cart, or already suggested, is skipped
+    let mut seen: HashSet<String> = items.iter().map(|item| key(&item.name)).collect();
+    let mut candidates: Vec<(&String, String)> = Vec::new();
+
+    for item in items {
+        for name in related.get(&key(&item.name)).copied().into_iter().flatten() {
+            candidates.push((name, format!("Often bought with {}", item.name)));
+        }
+    }
+    for item in items {
+        let category = catalog
+            .get(&item.name)
+            .and_then(|product| product.category.clone())
+            .or_else(|| {
+                item.extra
+                    .get("category")
+                    .and_then(|c| c.as_str())
+                    .map(String::from)
+            });
+        if let Some(category) = category {
+            for name in categories
+                .get(&key(&category))
+                .copied()
+                .into_iter()
+                .flatten()
+            {
+                candidates.push((name, format!("Goes with {}", category)));
+            }
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter_map(|(name, reason)| {
+            let product = catalog.get(name)?;
+            seen.insert(key(&product.name)).then(|| Recommendation {
+                name: product.name.clone(),
+                price: product.price,
+                reason,
+            })
+        })
+        .take(limit)
+        .collect()
+}
@@ -0,0 +1,40 @@
+//! Fetching assets over HTTP(S)
+//!
+//! Requests go through `reqwest` with rustls and the bundled webpki roots, so
+//! `WIDGET_URL` can point at an `https://` CDN without a system TLS library.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Shared client so connections and TLS sessions are reused across fetches
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// Fetches a URL and returns its body as text, failing on non-2xx statuses
+pub async fn fetch_text(url: &str, timeout: Duration) -> Result<String, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("only http:// and https:// URLs are supported".to_string());
+    }
+
+    let response = client()
+        .get(url)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| describe(e, timeout))?;
+    if !response.status().is_success() {
+        return Err(format!("server returned {}", response.status()));
+    }
+    response.text().await.map_err(|e| describe(e, timeout))
+}
+
+fn describe(e: reqwest::Error, timeout: Duration) -> String {
+    if e.is_timeout() {
+        format!("timed out after {:?}", timeout)
+    } else {
+        e.to_string()
+    }
+}
@@ -0,0 +1,392 @@
+//! Admin route handlers
+//!
+//! Operational endpoints for development and orchestration. Every route here is
+//! hidden unless `ENABLE_ADMIN` is set, and requires `Authorization: Bearer <ADMIN_TOKEN>`.
+
+use crate::expiry;
+use crate::model::SharedState;
+use crate::router::allow;
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::atomic::Ordering,
+};
+
+/// Creates routes for admin operations, guarded by `require_admin`
+pub fn routes(state: SharedState) -> Router<SharedState> {
+    Router::new()
+        .route(
+            "/admin/shutdown",
+            post(shutdown).options(allow("POST, OPTIONS")),
+        )
+        .route(
+            "/admin/metrics",
+            get(metrics).options(allow("GET, OPTIONS")),
+        )
+        .route("/admin/sweep", post(sweep).options(allow("POST, OPTIONS")))
+        .route(
+            "/stats/sales",
+            get(sales_stats).options(allow("GET, OPTIONS")),
+        )
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+/// Middleware: rejects admin requests unless admin mode is enabled and the token matches
+async fn require_admin(State(state): State<SharedState>, req: Request, next: Next) -> Response {
+    if !state.config.enable_admin {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let expected = match state.config.admin_token.as_deref() {
+        Some(token) => token,
+        None => {
+            eprintln!("Admin request refused: ADMIN_TOKEN is not configured");
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    };
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if provided != Some(expected) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Endpoint: POST /admin/shutdown
+/// Triggers a graceful shutdown; in-flight requests complete before the process exits.
+async fn shutdown(State(state): State<SharedState>) -> impl IntoResponse {
+    let reason = "admin shutdown requested";
+    println!("ADMIN: {}", reason);
+    state.request_shutdown(reason);
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "status": "shutting_down", "reason": reason })),
+    )
+}
+
+/// Endpoint: POST /admin/sweep
+/// Evicts expired carts now rather than waiting for the background sweeper.
+async fn sweep(State(state): State<SharedState>) -> impl IntoResponse {
+    let evicted = expiry::sweep_expired_carts(&state).await;
+    println!("ADMIN: swept {} expired carts", evicted);
+    Json(json!({ "evicted": evicted }))
+}
+
+/// Endpoint: GET /admin/metrics
+/// Reports cart counts and how carts spread over the map's shards, to spot hot-shard skew.
+async fn metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    Json(json!({
+        "carts": shard_stats(&state.carts),
+        "knownCartIds": state.known_cart_ids.len(),
+        "sseSessions": state.sse_sessions.load(Ordering::SeqCst),
+        "persistFailures": state.persist_failures.load(Ordering::Relaxed),
+        "deprecatedCalls": state.deprecated_calls.load(Ordering::Relaxed)
+    }))
+}
+
+/// Optional time range for sales stats, in Unix seconds (inclusive)
+#[derive(Debug, Deserialize)]
+struct SalesRange {
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+/// Endpoint: GET /stats/sales
+/// Totals orders, units, and revenue from checkouts over MCP and REST, optionally within a
+/// time range. Only the latest `MAX_LEDGER_ORDERS` orders are kept.
+async fn sales_stats(
+    State(state): State<SharedState>,
+    Query(range): Query<SalesRange>,
+) -> impl IntoResponse {
+    Json(state.sales_stats(range.from, range.to))
+}
+
+/// Summarizes entries per shard of a DashMap. Counts are read shard by shard, so under
+/// concurrent writes they are approximate.
+fn shard_stats<K: Eq + Hash, V, S: BuildHasher + Clone>(map: &DashMap<K, V, S>) -> Value {
+    let entries: Vec<usize> = map
+        .shards()
+        .iter()
+        .map(|shard| shard.read().len())
+        .collect();
+    json!({
+        "total": entries.iter().sum::<usize>(),
+        "shards": entries.len(),
+        "minShardEntries": entries.iter().min().copied().unwrap_or(0),
+        "maxShardEntries": entries.iter().max().copied().unwrap_or(0),
+        "shardEntries": entries
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::model::AppState;
+    use crate::router::create_app_router;
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use std::{sync::Arc, time::Duration};
+    use tower::ServiceExt;
+
+    fn admin_state(enable_admin: bool) -> Arc<AppState> {
+        Arc::new(AppState::with_config(Config {
+            enable_admin,
+            admin_token: Some("secret".into()),
+            ..Config::default()
+        }))
+    }
+
+    fn shutdown_request(token: Option<&str>) -> Request<Body> {
+        let mut builder = Request::post("/admin/shutdown");
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_triggers_signal() {
+        let state = admin_state(true);
+        let app = create_app_router(state.clone());
+
+        let res = app.oneshot(shutdown_request(Some("secret"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        let reason = tokio::time::timeout(Duration::from_secs(1), state.shutdown_requested())
+            .await
+            .expect("shutdown signal was not raised");
+        assert_eq!(reason, "admin shutdown requested");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_requires_admin_and_auth() {
+        let state = admin_state(true);
+        let res = create_app_router(state.clone())
+            .oneshot(shutdown_request(Some("wrong")))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let disabled = admin_state(false);
+        let res = create_app_router(disabled.clone())
+            .oneshot(shutdown_request(Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        assert!(state.shutdown.borrow().is_none());
+        assert!(disabled.shutdown.borrow().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_options_lists_allowed_methods() {
+        let state = admin_state(true);
+        for (uri, methods) in [
+            ("/admin/shutdown", "POST, OPTIONS"),
+            ("/admin/metrics", "GET, OPTIONS"),
+            ("/admin/sweep", "POST, OPTIONS"),
+            ("/stats/sales", "GET, OPTIONS"),
+        ] {
+            let res = create_app_router(state.clone())
+                .oneshot(
+                    Request::builder()
+                        .method("OPTIONS")
+                        .uri(uri)
+                        .header("authorization", "Bearer secret")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT, "{}", uri);
+            assert_eq!(res.headers()["allow"], methods);
+        }
+
+        // Without the token, OPTIONS is refused like the routes themselves
+        let res = create_app_router(state)
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/admin/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_evicts_expired_carts() {
+        use std::time::Instant;
+
+        let state = Arc::new(AppState::with_config(Config {
+            enable_admin: true,
+            admin_token: Some("secret".into()),
+            cart_ttl_seconds: Some(60),
+            ..Config::default()
+        }));
+        state.with_cart_mut("fresh_cart", |_| ());
+        state.with_cart_mut("stale_cart", |_| ());
+        let long_ago = Instant::now()
+            .checked_sub(Duration::from_secs(120))
+            .unwrap();
+        state.cart_activity.touch_at("stale_cart", long_ago);
+
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::post("/admin/sweep")
+                    .header("authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body, serde_json::json!({ "evicted": 1 }));
+        assert!(state.carts.get("stale_cart").is_none());
+        assert!(state.carts.get("fresh_cart").is_some());
+        assert!(!state.known_cart_ids.contains("stale_cart"));
+    }
+
+    async fn get_sales(state: &Arc<AppState>, query: &str) -> serde_json::Value {
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::get(format!("/stats/sales{}", query))
+                    .header("authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sales_stats_aggregate_checkouts() {
+        use crate::model::{CHECKOUT_TOOL_NAME, MAX_LEDGER_ORDERS, TOOL_NAME};
+        use crate::router::mcp::handle_tool_call;
+        use serde_json::json;
+
+        let state = admin_state(true);
+        for (cart_id, items) in [
+            (
+                "order_a",
+                json!([{ "name": "Apple", "price": 0.1, "quantity": 3 }]),
+            ),
+            (
+                "order_b",
+                json!([{ "name": "Pear", "price": 0.2, "quantity": 1 }]),
+            ),
+        ] {
+            handle_tool_call(
+                &state,
+                TOOL_NAME,
+                json!({ "cartId": cart_id, "items": items }),
+            )
+            .await
+            .expect("Add failed");
+            handle_tool_call(&state, CHECKOUT_TOOL_NAME, json!({ "cartId": cart_id }))
+                .await
+                .expect("Checkout failed");
+        }
+
+        // REST checkouts are recorded too
+        for (path, body) in [
+            (
+                "/sync_cart",
+                json!({ "cartId": "order_c", "items": [{ "name": "Fig", "price": 0.2 }] }),
+            ),
+            ("/checkout", json!({ "cartId": "order_c" })),
+        ] {
+            let res = create_app_router(state.clone())
+                .oneshot(
+                    Request::post(path)
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        let stats = get_sales(&state, "").await;
+        assert_eq!(stats["orders"], 3);
+        assert_eq!(stats["unitsSold"], 5);
+        // 0.1 * 3 + 0.2 + 0.2 sums to 0.7 exactly, not 0.7000000000000001
+        assert_eq!(stats["revenue"], 0.7);
+
+        // A range ending before the orders were placed excludes them
+        let stats = get_sales(&state, "?to=1").await;
+        assert_eq!(stats["orders"], 0);
+        assert_eq!(stats["revenue"], 0.0);
+
+        // The ledger keeps only the latest orders
+        let order = state.orders.read().unwrap()[0].clone();
+        for _ in 0..MAX_LEDGER_ORDERS {
+            state.record_order(order.clone());
+        }
+        assert_eq!(state.orders.read().unwrap().len(), MAX_LEDGER_ORDERS);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_report_cart_shards() {
+        let state = admin_state(true);
+        for i in 0..50 {
+            state.cart_entry(&format!("cart_{}", i));
+        }
+
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::get("/admin/metrics")
+                    .header("authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metrics: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let carts = &metrics["carts"];
+        assert_eq!(carts["total"], state.carts.len());
+        assert_eq!(carts["total"], 50);
+        let shard_sum: u64 = carts["shardEntries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n.as_u64().unwrap())
+            .sum();
+        assert_eq!(shard_sum, 50);
+        assert!(carts["shards"].as_u64().unwrap() > 0);
+    }
+}
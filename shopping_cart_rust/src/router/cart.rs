@@ -1,49 +1,666 @@
 //! Cart-related route handlers
 
 use crate::model::{
-    format_item_summary, get_or_create_cart_id, AddToCartInput, CheckoutInput, SharedState,
-    SyncResponse,
+    format_item_summary, new_order_id, unknown_extra_fields, CheckoutInput, OrderRecord,
+    SharedState, SyncCartInput, SyncResponse, EMPTY_CHECKOUT_ERROR,
 };
-use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
+use crate::router::{allow, mcp::record_replacement, session::Session};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde_json::json;
 
 /// Creates routes for cart-related operations
 pub fn routes() -> Router<SharedState> {
     Router::new()
-        .route("/sync_cart", post(sync_cart))
-        .route("/checkout", post(checkout))
+        .route(
+            "/sync_cart",
+            post(sync_cart).options(allow("POST, OPTIONS")),
+        )
+        .route("/checkout", post(checkout).options(allow("POST, OPTIONS")))
+        .route(
+            "/cart/:cart_id",
+            get(get_cart)
+                .delete(delete_cart)
+                .options(allow("GET, DELETE, OPTIONS")),
+        )
+}
+
+/// Endpoint: GET /cart/{cart_id}
+/// Returns the cart's items and whether it is active, empty, or unknown.
+async fn get_cart(State(state): State<SharedState>, Path(cart_id): Path<String>) -> Response {
+    if let Err(message) = state.check_cart_id(&cart_id) {
+        return bad_request(message);
+    }
+
+    let cart = state
+        .carts
+        .get(&cart_id)
+        .map(|cart| cart.clone())
+        .unwrap_or_default();
+
+    Json(json!({
+        "cartId": cart_id,
+        "state": state.cart_status(&cart_id),
+        "fingerprint": cart.fingerprint(),
+        "items": cart.items,
+        "metadata": cart.metadata
+    }))
+    .into_response()
+}
+
+/// Endpoint: DELETE /cart/{cart_id}
+/// Drops the cart and forgets its id, without any checkout side effects.
+async fn delete_cart(State(state): State<SharedState>, Path(cart_id): Path<String>) -> Response {
+    if let Err(message) = state.check_cart_id(&cart_id) {
+        return bad_request(message);
+    }
+
+    state.known_cart_ids.remove(&cart_id);
+    state.stock_holds.release(&cart_id);
+    let removed = state.carts.remove(&cart_id);
+    if let Err(message) = state.persist_cart(&cart_id).await {
+        return save_failed(message);
+    }
+    match removed {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
 }
 
 /// Endpoint: POST /sync_cart
 /// Updates the backend state to match the frontend (Widget) state exactly.
+/// With `ifVersion`, the update only applies if the cart still has that fingerprint.
 async fn sync_cart(
     State(state): State<SharedState>,
-    Json(payload): Json<AddToCartInput>,
+    Extension(session): Extension<Session>,
+    Json(mut payload): Json<SyncCartInput>,
 ) -> impl IntoResponse {
-    let cart_id = get_or_create_cart_id(payload.cart_id);
+    // An empty list usually means a widget bug rather than a deliberate clear
+    if state.config.reject_empty_sync && payload.items.is_empty() {
+        return bad_request("items must not be empty; REJECT_EMPTY_SYNC is set");
+    }
+
+    // Strict syncs are all-or-nothing; lenient ones drop the items that fail.
+    // Warnings are gathered first so they keep the request's item indexes.
+    let mut warnings = unknown_extra_fields(&payload.items);
+    let mut rejected = Vec::new();
+    if state.config.lenient_sync {
+        let (accepted, refused) = state.prepare_items_leniently(payload.items);
+        payload.items = accepted;
+        if payload.items.is_empty() && !refused.is_empty() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "every item was rejected", "rejected": refused })),
+            )
+                .into_response();
+        }
+        warnings.retain(|w| !refused.iter().any(|r| r.index == w.index));
+        rejected = refused;
+    } else if let Err(err) = state.prepare_items(&mut payload.items) {
+        return bad_request(err.message);
+    }
 
-    state.carts.insert(cart_id.clone(), payload.items);
+    let cart_id = session.cart_id(payload.cart_id);
+    if let Err(message) = state.check_cart_id(&cart_id) {
+        return bad_request(message);
+    }
+
+    // Replace the items while keeping cart-level state such as coupons
+    let synced = state.try_with_cart_mut(&cart_id, |cart| {
+        if let Some(expected) = payload.if_version {
+            let current = cart.fingerprint();
+            if expected != current {
+                let conflict = json!({
+                    "error": "Cart changed since ifVersion",
+                    "cartId": cart_id,
+                    "fingerprint": current,
+                    "items": cart.items
+                });
+                return Err((StatusCode::CONFLICT, conflict));
+            }
+        }
+        state
+            .admit_items(&cart_id, &payload.items)
+            .map_err(|message| (StatusCode::BAD_REQUEST, json!({ "error": message })))?;
+        let dropped = cart.replace_items(payload.items, state.config.soft_delete_window());
+        record_replacement(&state, cart, dropped);
+        Ok(())
+    });
+    if let Err(rejected) = synced {
+        return (rejected.0, Json(rejected.1)).into_response();
+    }
+    if let Err(message) = state.persist_cart(&cart_id).await {
+        return save_failed(message);
+    }
 
     Json(SyncResponse {
         status: "updated".to_string(),
         cart_id,
+        warnings,
+        rejected,
     })
+    .into_response()
 }
 
 /// Endpoint: POST /checkout
 /// Processes checkout from the cart
 async fn checkout(
     State(state): State<SharedState>,
+    Extension(session): Extension<Session>,
     Json(payload): Json<CheckoutInput>,
-) -> impl IntoResponse {
-    let cart_id = get_or_create_cart_id(payload.cart_id);
+) -> Response {
+    let cart_id = session.cart_id(payload.cart_id);
+    if let Err(message) = state.check_cart_id(&cart_id) {
+        return bad_request(message);
+    }
+    if state.config.block_empty_checkout && state.is_empty_cart(&cart_id) {
+        return bad_request(EMPTY_CHECKOUT_ERROR);
+    }
 
-    if let Some((_, items)) = state.carts.remove(&cart_id) {
-        let item_summary = format_item_summary(&items);
+    if let Some((_, cart)) = state.carts.remove(&cart_id) {
+        state.stock_holds.release(&cart_id);
+        let totals = cart.total_value(&state.pricing_policy());
+        state.record_order(OrderRecord::for_cart(&new_order_id(), &cart, &totals));
+        let item_summary = format_item_summary(&cart.items, state.config.summary_max_items());
         println!("REST API CHECKOUT: Cart {} - {}", cart_id, item_summary);
     }
+    if let Err(message) = state.persist_cart(&cart_id).await {
+        return save_failed(message);
+    }
 
     Json(SyncResponse {
         status: "checked_out".to_string(),
         cart_id,
+        warnings: Vec::new(),
+        rejected: Vec::new(),
     })
+    .into_response()
+}
+
+/// Builds a 400 response carrying an error message
+fn bad_request(message: impl Into<String>) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": message.into() })),
+    )
+        .into_response()
+}
+
+/// Builds a 500 response for a cart that couldn't be saved in strict persistence mode
+fn save_failed(message: String) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": message })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::model::{AppState, MAX_DEPARTED_CART_IDS};
+    use crate::router::create_app_router;
+    use axum::{body::Body, extract::ConnectInfo, http::Request, http::StatusCode};
+    use serde_json::{json, Value};
+    use std::{net::SocketAddr, sync::Arc};
+    use tower::ServiceExt;
+
+    async fn get_cart_state(state: &Arc<AppState>, cart_id: &str) -> Value {
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::get(format!("/cart/{}", cart_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<Value>(&body).unwrap()["state"].clone()
+    }
+
+    async fn sync(state: &Arc<AppState>, body: Value) -> StatusCode {
+        create_app_router(state.clone())
+            .oneshot(
+                Request::post("/sync_cart")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_get_cart_reports_state() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+
+        sync(
+            &state,
+            json!({ "cartId": "full", "items": [{ "name": "Apple" }] }),
+        )
+        .await;
+        sync(&state, json!({ "cartId": "empty", "items": [] })).await;
+
+        assert_eq!(get_cart_state(&state, "full").await, "active");
+        assert_eq!(get_cart_state(&state, "empty").await, "empty");
+        assert_eq!(get_cart_state(&state, "never").await, "not_found");
+
+        // A checked-out cart is known, so it reads as empty rather than not found
+        state.carts.remove("full");
+        assert_eq!(get_cart_state(&state, "full").await, "empty");
+
+        // Past the cap, departed ids are forgotten so the set stays bounded
+        for i in 0..MAX_DEPARTED_CART_IDS {
+            state.known_cart_ids.insert(format!("departed_{}", i));
+        }
+        sync(&state, json!({ "cartId": "fresh", "items": [] })).await;
+        assert_eq!(state.known_cart_ids.len(), state.carts.len());
+        assert_eq!(get_cart_state(&state, "fresh").await, "empty");
+        assert_eq!(get_cart_state(&state, "empty").await, "empty");
+        assert_eq!(get_cart_state(&state, "full").await, "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_delete_cart() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        sync(
+            &state,
+            json!({ "cartId": "doomed", "items": [{ "name": "Apple" }] }),
+        )
+        .await;
+
+        let delete = |cart_id: &str| {
+            create_app_router(state.clone()).oneshot(
+                Request::delete(format!("/cart/{}", cart_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let res = delete("doomed").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(!state.carts.contains_key("doomed"));
+        assert_eq!(get_cart_state(&state, "doomed").await, "not_found");
+
+        let res = delete("doomed").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_sync_if_version() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        sync(
+            &state,
+            json!({ "cartId": "versioned", "items": [{ "name": "Apple" }] }),
+        )
+        .await;
+
+        let read = |state: Arc<AppState>| async move {
+            let res = create_app_router(state)
+                .oneshot(Request::get("/cart/versioned").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<Value>(&body).unwrap()
+        };
+        let version = read(state.clone()).await["fingerprint"].clone();
+
+        let post = |body: Value| {
+            create_app_router(state.clone()).oneshot(
+                Request::post("/sync_cart")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+
+        // Matching version applies
+        let res = post(json!({
+            "cartId": "versioned",
+            "ifVersion": version,
+            "items": [{ "name": "Bread" }]
+        }))
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(read(state.clone()).await["items"][0]["name"], "Bread");
+
+        // The old version is now stale
+        let res = post(json!({
+            "cartId": "versioned",
+            "ifVersion": version,
+            "items": [{ "name": "Cherry" }]
+        }))
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let conflict: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(conflict["items"][0]["name"], "Bread");
+        assert_ne!(conflict["fingerprint"], version);
+
+        // A conflicting sync to an unknown cart doesn't create it
+        let res = post(json!({
+            "cartId": "never_synced",
+            "ifVersion": version,
+            "items": [{ "name": "Cherry" }]
+        }))
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert!(state.carts.get("never_synced").is_none());
+        assert!(!state.known_cart_ids.contains("never_synced"));
+    }
+
+    #[tokio::test]
+    async fn test_idless_syncs_reuse_session_cart() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+
+        // First contact, even a GET, establishes the session cookie
+        let res = create_app_router(state.clone())
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = res.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        assert!(cookie.starts_with("cart_session="));
+
+        let sync = |name: &str| {
+            create_app_router(state.clone()).oneshot(
+                Request::post("/sync_cart")
+                    .header("content-type", "application/json")
+                    .header("cookie", &cookie)
+                    .body(Body::from(
+                        json!({ "items": [{ "name": name }] }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+        };
+
+        let mut cart_ids = Vec::new();
+        for name in ["Apple", "Bread"] {
+            let res = sync(name).await.unwrap();
+            assert!(res.headers().get("set-cookie").is_none());
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            cart_ids.push(body["cartId"].as_str().unwrap().to_string());
+        }
+        assert_eq!(cart_ids[0], cart_ids[1]);
+        assert_eq!(state.carts.len(), 1);
+        assert_eq!(
+            state.carts.get(&cart_ids[0]).unwrap().items[0].name,
+            "Bread"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookieless_requests_get_their_own_sessions() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let sync = |name: &str| {
+            let mut req = Request::post("/sync_cart")
+                .header("content-type", "application/json")
+                .header("user-agent", "widget/1.0")
+                .body(Body::from(
+                    json!({ "items": [{ "name": name }] }).to_string(),
+                ))
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 4000))));
+            create_app_router(state.clone()).oneshot(req)
+        };
+
+        // Sharing an address and user agent doesn't make two requests one client
+        let (first, second) = tokio::join!(sync("Apple"), sync("Bread"));
+        let cookie = |res: &axum::response::Response| res.headers()["set-cookie"].clone();
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_ne!(cookie(&first), cookie(&second));
+        assert_eq!(state.carts.len(), 2);
+
+        // A request already carrying a session cookie is never issued another
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::get("/")
+                    .header("cookie", "cart_session=abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(res.headers().get("set-cookie").is_none());
+
+        // ...unless it can't be a cart id, in which case it is replaced
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::post("/sync_cart")
+                    .header("content-type", "application/json")
+                    .header("cookie", "cart_session=../etc")
+                    .body(Body::from(
+                        json!({ "items": [{ "name": "Cherry" }] }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let replaced = res.headers()["set-cookie"].to_str().unwrap();
+        assert!(replaced.starts_with("cart_session="));
+        assert!(!replaced.starts_with("cart_session=../etc"));
+        assert!(!state.carts.contains_key("../etc"));
+        assert_eq!(state.carts.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sync_warns_about_unknown_fields() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let payload = json!({
+            "cartId": "warn_cart",
+            "items": [
+                { "name": "Apple", "price": 1.0 },
+                { "name": "Bread", "colour": "brown", "price": 2.0 }
+            ]
+        });
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::post("/sync_cart")
+                    .header("content-type", "application/json")
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "updated");
+        assert_eq!(body["cartId"], "warn_cart");
+        assert_eq!(
+            body["warnings"],
+            json!([{
+                "index": 1,
+                "field": "colour",
+                "reason": "unrecognized field, stored but ignored"
+            }])
+        );
+        // The field is still kept on the item
+        assert_eq!(
+            state.carts.get("warn_cart").unwrap().items[1].extra["colour"],
+            "brown"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_respects_total_quantity_cap() {
+        let state = Arc::new(AppState::with_config(Config {
+            max_total_qty: Some(5),
+            ..Config::default()
+        }));
+        let at_cap = json!({
+            "cartId": "capped_cart",
+            "items": [{ "name": "Apple", "quantity": 3 }, { "name": "Pear", "quantity": 2 }]
+        });
+        let over_cap = json!({
+            "cartId": "capped_cart",
+            "items": [{ "name": "Apple", "quantity": 3 }, { "name": "Pear", "quantity": 3 }]
+        });
+
+        assert_eq!(sync(&state, at_cap).await, StatusCode::OK);
+        assert_eq!(sync(&state, over_cap).await, StatusCode::BAD_REQUEST);
+        assert_eq!(state.carts.get("capped_cart").unwrap().items[1].quantity, 2);
+    }
+
+    #[tokio::test]
+    async fn test_empty_sync_clears_or_is_rejected() {
+        let full = json!({ "cartId": "empty_cart", "items": [{ "name": "Apple" }] });
+        let empty = json!({ "cartId": "empty_cart", "items": [] });
+
+        // By default an empty sync clears the cart
+        let state = Arc::new(AppState::with_config(Config::default()));
+        sync(&state, full.clone()).await;
+        assert_eq!(sync(&state, empty.clone()).await, StatusCode::OK);
+        assert!(state.carts.get("empty_cart").unwrap().items.is_empty());
+
+        // With REJECT_EMPTY_SYNC the cart is left as it was
+        let state = Arc::new(AppState::with_config(Config {
+            reject_empty_sync: true,
+            ..Config::default()
+        }));
+        sync(&state, full).await;
+        assert_eq!(sync(&state, empty).await, StatusCode::BAD_REQUEST);
+        assert_eq!(state.carts.get("empty_cart").unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_an_invalid_item() {
+        let batch = json!({
+            "cartId": "batch_cart",
+            "items": [
+                { "name": "Apple" },
+                { "name": "Bread", "weight": -1 },
+                { "name": "Cherry", "colour": "red" }
+            ]
+        });
+        let post = |state: Arc<AppState>, body: Value| async move {
+            let res = create_app_router(state)
+                .oneshot(
+                    Request::post("/sync_cart")
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = res.status();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        };
+
+        // Strict by default: nothing is stored
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let (status, body) = post(state.clone(), batch.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("Item 1"));
+        assert!(state.carts.get("batch_cart").is_none());
+
+        // Lenient: the valid items are kept and the bad one is reported
+        let state = Arc::new(AppState::with_config(Config {
+            lenient_sync: true,
+            ..Config::default()
+        }));
+        let (status, body) = post(state.clone(), batch).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["rejected"],
+            json!([{ "index": 1, "error": "weight: weight must be a non-negative number" }])
+        );
+        assert_eq!(body["warnings"][0]["index"], 2);
+        let names: Vec<String> = state
+            .carts
+            .get("batch_cart")
+            .unwrap()
+            .items
+            .iter()
+            .map(|i| i.name.clone())
+            .collect();
+        assert_eq!(names, ["Apple", "Cherry"]);
+
+        // A batch with nothing valid leaves the cart alone
+        let (status, body) = post(
+            state.clone(),
+            json!({ "cartId": "batch_cart", "items": [{ "name": "Bread", "weight": -1 }] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["rejected"][0]["index"], 0);
+        assert_eq!(state.carts.get("batch_cart").unwrap().items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rest_rejects_invalid_cart_ids() {
+        let state = Arc::new(AppState::with_config(Config {
+            max_cart_id_len: Some(8),
+            ..Config::default()
+        }));
+        let get_cart = |cart_id: String| {
+            create_app_router(state.clone()).oneshot(
+                Request::get(format!("/cart/{}", cart_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        // A limit below the minted id length is raised to it, so minted ids still work
+        let minted = uuid::Uuid::new_v4().simple().to_string();
+        assert_eq!(get_cart(minted).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            get_cart("x".repeat(33)).await.unwrap().status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::post("/sync_cart")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        json!({ "cartId": "a b!", "items": [] }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["error"],
+            "cartId may only contain letters, digits, '-' and '_'"
+        );
+        assert!(state.carts.is_empty());
+    }
 }
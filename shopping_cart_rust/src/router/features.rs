@@ -0,0 +1,86 @@
+//! Feature flag route handlers
+//!
+//! Lets operators confirm at runtime which optional features the environment enabled.
+
+use crate::model::SharedState;
+use crate::router::allow;
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+
+/// Creates routes for feature reporting
+pub fn routes() -> Router<SharedState> {
+    Router::new().route(
+        "/features",
+        get(get_features).options(allow("GET, OPTIONS")),
+    )
+}
+
+/// Endpoint: GET /features
+/// Returns the optional features computed at startup.
+async fn get_features(State(state): State<SharedState>) -> impl IntoResponse {
+    Json(state.features.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::model::AppState;
+    use crate::router::create_app_router;
+    use axum::{body::Body, http::Request};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_enabled_feature_is_reported() {
+        // Same as running with ASSET_HOT_RELOAD=1 and TAX_RATE=0.08
+        let state = Arc::new(AppState::with_config(Config {
+            asset_hot_reload: true,
+            tax_rate: Some(0.08),
+            ..Config::default()
+        }));
+        let res = create_app_router(state)
+            .oneshot(Request::get("/features").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let features: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(features["assetHotReload"], true);
+        assert_eq!(features["tax"], true);
+        assert_eq!(features["coupons"], false);
+        assert_eq!(features["admin"], false);
+    }
+
+    #[tokio::test]
+    async fn test_unloadable_data_files_leave_features_off() {
+        let dir = std::env::temp_dir().join(format!("features-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.json");
+        std::fs::write(&empty, "{}").unwrap();
+
+        let state = Arc::new(AppState::with_config(Config {
+            tax_table_path: Some(dir.join("missing.json")),
+            tax_categories_path: Some(empty.clone()),
+            fx_rates_path: Some(empty.clone()),
+            recommendations_path: Some(empty.clone()),
+            aliases_path: Some(empty),
+            ..Config::default()
+        }));
+        let res = create_app_router(state)
+            .oneshot(Request::get("/features").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let features: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(features["regionalTax"], false);
+        assert_eq!(features["taxCategories"], false);
+        assert_eq!(features["fxConversion"], false);
+        assert_eq!(features["recommendations"], false);
+        assert_eq!(features["aliases"], false);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+//! Readiness route handler
+//!
+//! `GET /ready` answers 200 once the server can take traffic. With `READY_PROBES`
+//! set it also probes the dependencies enabled in the environment, each bounded by
+//! `READY_PROBE_TIMEOUT_MS`, and reports them as named checks. A failed critical
+//! check turns the response into a 503; the widget URL isn't critical because
+//! local assets stand in when it's down.
+
+use crate::model::SharedState;
+use crate::persist;
+use crate::remote;
+use crate::router::allow;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::{json, Map, Value};
+
+/// Creates routes for readiness reporting
+pub fn routes() -> Router<SharedState> {
+    Router::new().route("/ready", get(get_ready).options(allow("GET, OPTIONS")))
+}
+
+/// Outcome of probing one dependency
+struct Check {
+    name: &'static str,
+    critical: bool,
+    result: Result<(), String>,
+}
+
+/// Endpoint: GET /ready
+/// Returns `{ status, checks }`, with 503 when a critical dependency is down.
+async fn get_ready(State(state): State<SharedState>) -> Response {
+    let checks = if state.config.ready_probes {
+        probe_dependencies(&state).await
+    } else {
+        Vec::new()
+    };
+
+    let ready = checks
+        .iter()
+        .all(|check| !check.critical || check.result.is_ok());
+    let checks: Map<String, Value> = checks
+        .into_iter()
+        .map(|check| {
+            let mut report = json!({ "ok": check.result.is_ok(), "critical": check.critical });
+            if let Err(error) = check.result {
+                report["error"] = json!(error);
+            }
+            (check.name.to_string(), report)
+        })
+        .collect();
+
+    let (status, label) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    (status, Json(json!({ "status": label, "checks": checks }))).into_response()
+}
+
+/// Probes every enabled dependency concurrently
+async fn probe_dependencies(state: &SharedState) -> Vec<Check> {
+    let timeout = state.config.ready_probe_timeout();
+
+    let persistence = async {
+        let dir = state.config.cart_data_dir.clone()?;
+        let write = tokio::task::spawn_blocking(move || persist::check_writable(&dir));
+        let result = match tokio::time::timeout(timeout, write).await {
+            Ok(Ok(result)) => result.map_err(|e| e.to_string()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("timed out after {:?}", timeout)),
+        };
+        Some(Check {
+            name: "persistence",
+            critical: true,
+            result,
+        })
+    };
+
+    let widget = async {
+        let url = state.config.widget_url.as_deref()?;
+        Some(Check {
+            name: "widgetUrl",
+            critical: false,
+            result: remote::fetch_text(url, timeout).await.map(|_| ()),
+        })
+    };
+
+    let (persistence, widget) = tokio::join!(persistence, widget);
+    persistence.into_iter().chain(widget).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::model::AppState;
+    use crate::router::create_app_router;
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn get_ready(config: Config) -> (u16, Value) {
+        let state = Arc::new(AppState::with_config(config));
+        let res = create_app_router(state)
+            .oneshot(Request::get("/ready").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = res.status().as_u16();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ready_probes_dependencies() {
+        // Without probes, readiness doesn't depend on anything external
+        let (status, body) = get_ready(Config::default()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({ "status": "ready", "checks": {} }));
+
+        // Nothing listens on port 1, so the widget URL is unreachable
+        let dir = std::env::temp_dir().join(format!("ready_{}", uuid::Uuid::new_v4().simple()));
+        let config = Config {
+            ready_probes: true,
+            widget_url: Some("http://127.0.0.1:1/widget.html".to_string()),
+            cart_data_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let (status, body) = get_ready(config.clone()).await;
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["checks"]["widgetUrl"]["ok"], false);
+        assert!(body["checks"]["widgetUrl"]["error"].is_string());
+        assert_eq!(
+            body["checks"]["persistence"],
+            json!({ "ok": true, "critical": true })
+        );
+
+        // An unwritable data directory is critical
+        let blocked = dir.join("blocked");
+        std::fs::write(&blocked, "not a directory").unwrap();
+        let (status, body) = get_ready(Config {
+            cart_data_dir: Some(blocked),
+            ..config
+        })
+        .await;
+        assert_eq!(status, 503);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["checks"]["persistence"]["ok"], false);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_ready_probes_stay_ready() {
+        let dir = std::env::temp_dir().join(format!("ready_{}", uuid::Uuid::new_v4().simple()));
+        let config = Config {
+            ready_probes: true,
+            cart_data_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+
+        // Overlapping probes each write and remove their own file
+        let probes = (0..16).map(|_| get_ready(config.clone()));
+        for (status, body) in futures_util::future::join_all(probes).await {
+            assert_eq!(status, 200, "{}", body);
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,41 +3,207 @@
 //! This module implements the Model Context Protocol handlers for the shopping cart application.
 //! It exports `handle_tool_call` publicly to make it accessible for tests.
 
+use crate::config::OutputCase;
+use crate::model::SharedState;
 use crate::model::{
-    format_item_summary, get_or_create_cart_id, rpc_error, rpc_success, update_cart_with_new_items,
-    widget_meta, AddToCartInput, AppState, CheckoutInput, JsonRpcRequest, CHECKOUT_TOOL_NAME,
-    PROTOCOL_VERSION, SERVER_NAME, TOOL_NAME, WIDGET_MIME_TYPE, WIDGET_TEMPLATE_URI,
+    apply_items_patch, capture_cart_changes, diff_cart_items, escape_html, format_item_summary,
+    get_or_create_cart_id, inject_cart_data, merge_cart_metadata, new_order_id, pluralize,
+    record_cart_change, resolve_payment_url, rpc_error, rpc_error_with_data, rpc_success,
+    total_weight, update_cart_with_new_items, validate_request_id, widget_meta, AddToCartInput,
+    AppState, BudgetAddInput, Cart, CartChange, CartMutation, CartRefInput, CheckoutInput,
+    ConvertTotalInput, CouponInput, JsonRpcRequest, MetadataInput, OrderRecord, PatchInput,
+    RecommendInput, SharedCartInput, TaxEstimateInput, ToolCallParams, ToolError,
+    ADD_WITHIN_BUDGET_TOOL_NAME, APPLY_COUPON_TOOL_NAME, APPLY_PATCH_TOOL_NAME,
+    CART_HISTORY_TOOL_NAME, CART_QR_TOOL_NAME, CHECKOUT_TOOL_NAME, CONVERT_TOTAL_TOOL_NAME,
+    EMPTY_CHECKOUT_ERROR, GET_CART_TOOL_NAME, LOAD_SHARED_CART_TOOL_NAME, PROTOCOL_VERSION,
+    RECOMMEND_TOOL_NAME, REMOVE_COUPON_TOOL_NAME, RENDER_CART_TOOL_NAME, SERVER_NAME,
+    SET_CART_METADATA_TOOL_NAME, SHARE_CART_TOOL_NAME, SHIPPING_ESTIMATE_TOOL_NAME,
+    TAX_ESTIMATE_TOOL_NAME, TOOL_NAME, UNDO_REMOVE_TOOL_NAME, VALIDATE_CART_TOOL_NAME,
+    WIDGET_MIME_TYPE, WIDGET_TEMPLATE_URI,
 };
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use crate::pricing::{
+    currency_info, line_pricing, normalize_coupon_code, normalize_region, round_to, PricingPolicy,
+    Totals,
+};
+use crate::qr::{self, MAX_QR_PAYLOAD_BYTES};
+use crate::rate_limit::ClientIp;
+use crate::recommend::recommend;
+use crate::router::allow;
+use crate::share;
+use crate::shipping::select_tier;
+use crate::tools::{Tool, ToolFuture, ToolRegistry};
+use axum::{
+    body::Bytes,
+    extract::{
+        rejection::BytesRejection,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::{json, Value};
+use std::{convert::Infallible, marker::PhantomData, net::IpAddr, sync::OnceLock, time::Duration};
+
+/// Methods served by the MCP endpoints
+const MCP_METHODS: &str = "GET, POST, OPTIONS";
 
 /// Creates routes for MCP-related operations
 pub fn routes() -> Router<crate::model::SharedState> {
     Router::new()
-        .route("/", post(handle_mcp).get(handle_mcp_sse))
-        .route("/mcp", post(handle_mcp).get(handle_mcp_sse)) // Standard endpoint
-        .route("/mcp/", post(handle_mcp).get(handle_mcp_sse)) // Trailing slash safety
+        .route(
+            "/",
+            post(handle_mcp)
+                .get(handle_landing)
+                .options(allow(MCP_METHODS)),
+        )
+        .route(
+            "/mcp",
+            post(handle_mcp)
+                .get(handle_mcp_sse)
+                .options(allow(MCP_METHODS)),
+        ) // Standard endpoint
+        .route(
+            "/mcp/",
+            post(handle_mcp)
+                .get(handle_mcp_sse)
+                .options(allow(MCP_METHODS)),
+        ) // Trailing slash safety
+        .route("/mcp/ws", get(handle_mcp_ws).options(allow("GET, OPTIONS")))
+}
+
+/// Endpoint: GET /
+/// Describes the server for humans opening it in a browser.
+async fn handle_landing() -> impl IntoResponse {
+    Json(json!({
+        "name": SERVER_NAME,
+        "version": env!("CARGO_PKG_VERSION"),
+        "endpoints": [
+            { "method": "POST", "path": "/mcp", "description": "MCP JSON-RPC requests" },
+            { "method": "GET", "path": "/mcp", "description": "MCP SSE handshake" },
+            { "method": "GET", "path": "/mcp/ws", "description": "MCP JSON-RPC over WebSocket" },
+            { "method": "POST", "path": "/sync_cart", "description": "Replace a cart's items" },
+            { "method": "POST", "path": "/checkout", "description": "Check out a cart" },
+            { "method": "GET", "path": "/cart/{cartId}", "description": "Read a cart" },
+            { "method": "DELETE", "path": "/cart/{cartId}", "description": "Drop a cart" },
+            { "method": "GET", "path": "/features", "description": "Optional features in effect" }
+        ]
+    }))
 }
 
-/// Handle SSE (Server-Sent Events) handshake for GET requests
-async fn handle_mcp_sse() -> impl IntoResponse {
-    (
-        [("content-type", "text/event-stream")],
-        "event: endpoint\ndata: /mcp\n\n",
-    )
+/// Handle SSE (Server-Sent Events) handshake for GET requests.
+/// The stream stays open after the endpoint event until the client disconnects.
+async fn handle_mcp_sse(State(state): State<SharedState>) -> Response {
+    let Some(session) = state.open_sse_session() else {
+        eprintln!("SSE connection refused: MAX_SSE_CONNECTIONS reached");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    // Keep-alive comments must come more often than the idle timeout closes connections
+    let mut keep_alive_interval = Duration::from_secs(15);
+    if let Some(idle) = state.config.http_idle_timeout() {
+        keep_alive_interval = keep_alive_interval.min(idle / 2);
+    }
+
+    // The session guard lives in the stream state and is dropped on disconnect.
+    // Streams end on shutdown so they don't hold up the graceful stop.
+    let stream = futures_util::stream::unfold(
+        (true, state, session),
+        |(first, state, session)| async move {
+            if !first {
+                state.shutdown_requested().await;
+                return None;
+            }
+            let event = Event::default().event("endpoint").data("/mcp");
+            Some((Ok::<_, Infallible>(event), (false, state, session)))
+        },
+    );
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(keep_alive_interval))
+        .into_response()
 }
 
+/// Header carrying the MCP session id over HTTP
+const SESSION_HEADER: &str = "mcp-session-id";
+
+/// The MCP session a request belongs to, which strict init tracks `initialize` by. A
+/// WebSocket connection is one session; HTTP clients echo the `Mcp-Session-Id` header
+/// their `initialize` response carried. `None` until a session id is known.
+struct McpSession(Option<String>);
+
 /// Endpoint: POST /mcp
 /// Handles the Model Context Protocol communication for POST requests.
 async fn handle_mcp(
     State(state): State<crate::model::SharedState>,
-    body: Result<Json<JsonRpcRequest>, axum::extract::rejection::JsonRejection>,
-) -> impl IntoResponse {
+    headers: HeaderMap,
+    client: Option<Extension<ClientIp>>,
+    body: Result<Bytes, BytesRejection>,
+) -> Response {
+    let sent = headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let mut session = McpSession(sent.clone());
+    let mut response = respond_to_post(&state, &mut session, &headers, client, body).await;
+
+    // Hand a session id minted by `initialize` back to the client
+    if session.0 != sent {
+        if let Some(id) = session.0.and_then(|id| HeaderValue::from_str(&id).ok()) {
+            response.headers_mut().insert(SESSION_HEADER, id);
+        }
+    }
+    response
+}
+
+/// Answers a POST body: a single request, a batch, or a progress stream
+async fn respond_to_post(
+    state: &SharedState,
+    session: &mut McpSession,
+    headers: &HeaderMap,
+    client: Option<Extension<ClientIp>>,
+    body: Result<Bytes, BytesRejection>,
+) -> Response {
+    // A body cut off mid-read isn't malformed JSON; say so rather than "Parse error"
+    let body = match body {
+        Ok(body) => body,
+        Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            return rejection.into_response();
+        }
+        Err(rejection) => {
+            eprintln!("Request body read error: {}", rejection.body_text());
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(rpc_error(
+                    Value::Null,
+                    -32700,
+                    "Parse error: incomplete request body",
+                )),
+            )
+                .into_response();
+        }
+    };
+
     // Parse JSON-RPC Request (POST)
-    let req = match body {
-        Ok(Json(r)) => r,
+    let parsed = if is_json_content_type(headers) {
+        parse_rpc_body(&body).map_err(|e| e.to_string())
+    } else {
+        Err("Expected request with `Content-Type: application/json`".to_string())
+    };
+    let req = match parsed {
+        Ok(RpcBody::Single(r)) => r,
+        Ok(RpcBody::Batch(batch)) => {
+            let ip = client.and_then(|Extension(ClientIp(ip))| ip);
+            return handle_batch(state, session, ip, batch).await;
+        }
         Err(e) => {
-            eprintln!("JSON Parse Error: {}", e.body_text());
+            eprintln!("JSON Parse Error: {}", e);
             return (
                 StatusCode::BAD_REQUEST,
                 Json(rpc_error(Value::Null, -32700, "Parse error")),
@@ -46,36 +212,481 @@ async fn handle_mcp(
         }
     };
 
+    if let Err(invalid) = check_request_id(state, &req) {
+        return (StatusCode::BAD_REQUEST, Json(invalid)).into_response();
+    }
+
+    let id = req.id.unwrap_or(Value::Null);
+    let params = req.params.unwrap_or(Value::Null);
+
+    // Stream progress over SSE when the client asked for it and can receive it
+    if req.method == "tools/call" && accepts_event_stream(headers) {
+        if let Some(token) = progress_token(&params) {
+            if let Ok(call) = serde_json::from_value::<ToolCallParams>(params.clone()) {
+                if let Some(rejected) = precheck(state, session, &id, &req.method, &params) {
+                    return Json(rejected).into_response();
+                }
+                return stream_tool_call_with_progress(
+                    state.clone(),
+                    id,
+                    call,
+                    includes_meta(&params),
+                    token,
+                );
+            }
+        }
+    }
+
+    Json(dispatch(state, session, id, &req.method, params).await).into_response()
+}
+
+/// Parses a JSON-RPC request, tolerating a leading UTF-8 BOM and whitespace that
+/// some clients prepend. Anything else that isn't valid JSON is still an error.
+fn parse_rpc_request(body: &[u8]) -> serde_json::Result<JsonRpcRequest> {
+    serde_json::from_slice(trim_body(body))
+}
+
+/// Strips the leading UTF-8 BOM and whitespace some clients prepend
+fn trim_body(body: &[u8]) -> &[u8] {
+    let body = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+    body.trim_ascii_start()
+}
+
+/// A POST body: a single request, or a batch of them as an array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RpcBody {
+    /// Kept as raw values so one malformed request doesn't fail the whole batch
+    Batch(Vec<Value>),
+    Single(JsonRpcRequest),
+}
+
+/// Parses a POST body like `parse_rpc_request`, also accepting a batch
+fn parse_rpc_body(body: &[u8]) -> serde_json::Result<RpcBody> {
+    serde_json::from_slice(trim_body(body))
+}
+
+/// Runs each request of a batch in order and returns their responses as an array in
+/// the same order. Notifications (no `id`) get no entry, and a batch of nothing but
+/// notifications is acknowledged with an empty 202.
+///
+/// Batches over `max_batch_size` are refused outright. The HTTP request already paid
+/// for the first entry, and each further entry is charged to the client's rate limit;
+/// entries over the limit are answered with an error instead of being run.
+async fn handle_batch(
+    state: &AppState,
+    session: &mut McpSession,
+    ip: Option<IpAddr>,
+    batch: Vec<Value>,
+) -> Response {
+    let max = state.config.max_batch_size();
+    if batch.is_empty() || batch.len() > max {
+        let message = if batch.is_empty() {
+            "Invalid Request: empty batch".to_string()
+        } else {
+            format!("Invalid Request: batch exceeds {} requests", max)
+        };
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(rpc_error(Value::Null, -32600, &message)),
+        )
+            .into_response();
+    }
+
+    let mut responses = Vec::with_capacity(batch.len());
+    for (index, message) in batch.into_iter().enumerate() {
+        if index > 0 && ip.is_some_and(|ip| !state.rate_limiter.check(ip)) {
+            if let Some(id) = message.get("id") {
+                responses.push(rpc_error(id.clone(), -32000, "Rate limit exceeded"));
+            }
+            continue;
+        }
+        match serde_json::from_value::<JsonRpcRequest>(message) {
+            Ok(req) => {
+                let is_notification = req.id.is_none();
+                let response = handle_rpc(state, session, req).await;
+                if !is_notification {
+                    responses.push(response);
+                }
+            }
+            Err(e) => {
+                eprintln!("Invalid Request in batch: {}", e);
+                responses.push(rpc_error(Value::Null, -32600, "Invalid Request"));
+            }
+        }
+    }
+
+    if responses.is_empty() {
+        return StatusCode::ACCEPTED.into_response();
+    }
+    Json(responses).into_response()
+}
+
+/// Returns true for `application/json` and `+json` media types, as axum's `Json` accepts
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    media_type == "application/json"
+        || (media_type.starts_with("application/") && media_type.ends_with("+json"))
+}
+
+/// Endpoint: GET /mcp/ws
+/// Upgrades to a WebSocket carrying one JSON-RPC message per text frame.
+async fn handle_mcp_ws(State(state): State<SharedState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| serve_ws(socket, state))
+}
+
+/// Answers each text frame with a response frame until the client disconnects.
+/// Like SSE streams, sockets close on shutdown so they don't hold up the graceful stop.
+/// The connection is one MCP session, forgotten once it closes.
+async fn serve_ws(mut socket: WebSocket, state: SharedState) {
+    let mut session = McpSession(None);
+    loop {
+        let message = tokio::select! {
+            message = socket.recv() => message,
+            _ = state.shutdown_requested() => break,
+        };
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            // Pings are answered by axum; binary frames aren't part of the protocol
+            Some(Ok(_)) => continue,
+        };
+
+        let reply = match parse_rpc_request(text.as_bytes()) {
+            Ok(req) => handle_rpc(&state, &mut session, req).await,
+            Err(e) => {
+                eprintln!("JSON Parse Error: {}", e);
+                rpc_error(Value::Null, -32700, "Parse error")
+            }
+        };
+        if socket.send(Message::Text(reply.to_string())).await.is_err() {
+            break;
+        }
+    }
+    if let Some(id) = session.0 {
+        state.end_session(&id);
+    }
+}
+
+/// Validates and dispatches one JSON-RPC request, for transports without status codes
+async fn handle_rpc(state: &AppState, session: &mut McpSession, req: JsonRpcRequest) -> Value {
+    if let Err(invalid) = check_request_id(state, &req) {
+        return invalid;
+    }
     let id = req.id.unwrap_or(Value::Null);
-    let method_name = req.method.as_str();
     let params = req.params.unwrap_or(Value::Null);
+    dispatch(state, session, id, &req.method, params).await
+}
+
+/// Applies the strict id rules, returning the error response for an invalid request
+fn check_request_id(state: &AppState, req: &JsonRpcRequest) -> Result<(), Value> {
+    if !state.config.strict_jsonrpc_ids {
+        return Ok(());
+    }
+    validate_request_id(&req.method, req.id.as_ref()).map_err(|reason| {
+        eprintln!("Invalid Request: {}", reason);
+        rpc_error_with_data(
+            Value::Null,
+            -32600,
+            "Invalid Request",
+            Some(json!({ "reason": reason })),
+        )
+    })
+}
 
+/// Returns the error response for a request refused by strict params or strict init
+fn precheck(
+    state: &AppState,
+    session: &McpSession,
+    id: &Value,
+    method_name: &str,
+    params: &Value,
+) -> Option<Value> {
+    if state.config.strict_params && PARAMETERLESS_METHODS.contains(&method_name) {
+        let unexpected = unexpected_params(params);
+        if !unexpected.is_empty() {
+            return Some(rpc_error_with_data(
+                id.clone(),
+                -32602,
+                format!("{} takes no params", method_name),
+                Some(json!({ "unexpected": unexpected })),
+            ));
+        }
+    }
+
+    if state.config.strict_init
+        && !INIT_EXEMPT_METHODS.contains(&method_name)
+        && !state.is_initialized(session.0.as_deref())
+    {
+        return Some(rpc_error(id.clone(), -32002, "Server not initialized"));
+    }
+
+    None
+}
+
+/// Runs a JSON-RPC method and returns its response envelope; shared by every transport
+async fn dispatch(
+    state: &AppState,
+    session: &mut McpSession,
+    id: Value,
+    method_name: &str,
+    params: Value,
+) -> Value {
     println!("MCP Call: {} (id: {:?})", method_name, id);
+    let method_name = state.log_deprecation(state.aliases.method(method_name));
 
-    // Dispatch Method
-    let response_body = match method_name {
-        "initialize" => rpc_success(id, handle_initialize()),
-        "notifications/initialized" => rpc_success(id, json!({})),
-        "tools/list" => rpc_success(id, handle_tools_list()),
-        "resources/list" => rpc_success(id, handle_resources_list()),
-        "resources/read" => rpc_success(id, handle_resources_read(&state).await),
-        "tools/call" => {
-            let tool_name = params.get("name").and_then(|n| n.as_str()).unwrap_or("");
-            let args = params.get("arguments").cloned().unwrap_or(Value::Null);
+    if let Some(rejected) = precheck(state, session, &id, method_name, &params) {
+        return rejected;
+    }
 
-            match handle_tool_call(&state, tool_name, args) {
-                Ok(result) => rpc_success(id, result),
-                Err(msg) => rpc_error(id, -32602, msg), // Invalid params or internal error
+    let include_meta = includes_meta(&params);
+    let mut response = match method_name {
+        "initialize" => {
+            // A probe only reads capabilities and doesn't count as the handshake
+            if state.config.strict_init && !is_probe(&params) {
+                let session_id = session
+                    .0
+                    .get_or_insert_with(|| uuid::Uuid::new_v4().simple().to_string());
+                state.mark_initialized(session_id);
             }
+            rpc_success(id, handle_initialize())
         }
+        "notifications/initialized" => rpc_success(id, json!({})),
+        "tools/list" => rpc_success(id, handle_tools_list(state)),
+        "resources/list" => rpc_success(id, handle_resources_list()),
+        "resources/templates/list" => rpc_success(id, handle_resource_templates_list()),
+        "resources/read" => rpc_success(id, handle_resources_read(state).await),
+        "tools/call" => match serde_json::from_value::<ToolCallParams>(params) {
+            Ok(call) => tool_call_response(state, id, call).await,
+            Err(e) => rpc_error(id, -32602, format!("Invalid params: {}", e)),
+        },
         "ping" => rpc_success(id, json!({})), // Optional but good for health checks
+        "capabilities/get" => rpc_success(id, json!(state.features)),
         _ => {
             eprintln!("Unknown method: {}", method_name);
             rpc_error(id, -32601, "Method not found")
         }
     };
+    if !include_meta {
+        strip_meta(&mut response);
+    }
+    response
+}
+
+/// Methods that accept no params beyond `_meta`
+const PARAMETERLESS_METHODS: &[&str] = &[
+    "tools/list",
+    "resources/list",
+    "resources/templates/list",
+    "ping",
+    "capabilities/get",
+];
+
+/// Methods allowed before initialization in strict-init mode
+const INIT_EXEMPT_METHODS: &[&str] = &["initialize", "ping"];
+
+/// Returns true for an `initialize` sent with `_meta.probe: true`
+fn is_probe(params: &Value) -> bool {
+    params
+        .get("_meta")
+        .and_then(|meta| meta.get("probe"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Reads `includeMeta` from params; widget `_meta` is included unless it's `false`
+fn includes_meta(params: &Value) -> bool {
+    params
+        .get("includeMeta")
+        .and_then(Value::as_bool)
+        .unwrap_or(true)
+}
+
+/// Removes every `_meta` key from a response's result, for clients that don't render
+/// widgets. Error responses are left alone.
+fn strip_meta(response: &mut Value) {
+    fn strip(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                map.remove("_meta");
+                map.values_mut().for_each(strip);
+            }
+            Value::Array(values) => values.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+    if let Some(result) = response.get_mut("result") {
+        strip(result);
+    }
+}
+
+/// Lists the params keys a parameterless method would ignore (`_meta` and `includeMeta`
+/// are always allowed)
+fn unexpected_params(params: &Value) -> Vec<String> {
+    match params {
+        Value::Null => Vec::new(),
+        Value::Object(map) => map
+            .keys()
+            .filter(|k| *k != "_meta" && *k != "includeMeta")
+            .cloned()
+            .collect(),
+        _ => vec!["params".to_string()],
+    }
+}
+
+/// Runs a tool call and wraps the outcome in a JSON-RPC response envelope
+async fn tool_call_response(state: &AppState, id: Value, call: ToolCallParams) -> Value {
+    let widget_session = call.widget_session_id();
+    let args = call.arguments.unwrap_or(Value::Null);
+    let arg_cart_id = args.get("cartId").and_then(Value::as_str).map(String::from);
+
+    // The cart helpers record each change under the cart's lock, so concurrent calls
+    // don't leak into this call's diff
+    let (outcome, changes) = if call.include_diff {
+        let (outcome, changes) =
+            capture_cart_changes(handle_tool_call(state, &call.name, args)).await;
+        (outcome, Some(changes))
+    } else {
+        (handle_tool_call(state, &call.name, args).await, None)
+    };
+
+    match outcome {
+        Ok(mut result) => {
+            if let Some(changes) = changes {
+                attach_diff(state, &mut result, arg_cart_id, &changes);
+            }
+            if state.config.echo_widget_session_id {
+                echo_widget_session(&mut result, widget_session);
+            }
+            rpc_success(id, result)
+        }
+        Err(err) => rpc_error_with_data(id, err.code, err.message, err.data),
+    }
+}
+
+/// Adds `structuredContent.diff` describing the call's change to its cart; a cart the
+/// call didn't change gets an empty diff. add_to_cart may have minted the cart id, so
+/// the one the result reports wins.
+fn attach_diff(
+    state: &AppState,
+    result: &mut Value,
+    arg_cart_id: Option<String>,
+    changes: &[CartChange],
+) {
+    let Some(content) = result.get_mut("structuredContent") else {
+        return;
+    };
+    let cart_id = content
+        .get("cartId")
+        .or_else(|| content.get("cart_id"))
+        .and_then(Value::as_str)
+        .map(String::from)
+        .or(arg_cart_id);
+    let mut diff = match changes
+        .iter()
+        .find(|change| Some(&change.cart_id) == cart_id.as_ref())
+    {
+        Some(change) => diff_cart_items(&change.before, &change.after),
+        None => diff_cart_items(&[], &[]),
+    };
+    // The rest of the result was recased already
+    if state.config.output_case == OutputCase::Snake {
+        snake_case_keys(&mut diff);
+    }
+    content["diff"] = diff;
+}
+
+/// Copies the caller's widget session id into the result's `_meta` unchanged, so the
+/// widget can tell which of its calls a response belongs to
+fn echo_widget_session(result: &mut Value, widget_session: Option<Value>) {
+    let (Some(session), Some(result)) = (widget_session, result.as_object_mut()) else {
+        return;
+    };
+    let meta = result.entry("_meta").or_insert_with(|| json!({}));
+    if let Some(meta) = meta.as_object_mut() {
+        meta.insert("openai/widgetSessionId".to_string(), session);
+    }
+}
+
+// =============================================================================
+// Progress Notifications
+// =============================================================================
+
+/// Extracts the `progressToken` from a request's `params._meta`
+fn progress_token(params: &Value) -> Option<Value> {
+    params
+        .get("_meta")
+        .and_then(|meta| meta.get("progressToken"))
+        .filter(|token| token.is_string() || token.is_number())
+        .cloned()
+}
+
+/// Returns true when the client accepts a `text/event-stream` response
+fn accepts_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains("text/event-stream"))
+}
+
+/// Builds a `notifications/progress` message for the given token
+fn progress_notification(token: &Value, progress: u64, total: u64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/progress",
+        "params": {
+            "progressToken": token,
+            "progress": progress,
+            "total": total,
+            "message": message
+        }
+    })
+}
+
+/// Runs a tool call while streaming progress notifications, ending with the JSON-RPC response
+fn stream_tool_call_with_progress(
+    state: SharedState,
+    id: Value,
+    call: ToolCallParams,
+    include_meta: bool,
+    token: Value,
+) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Value>(8);
+    let tool_name = call.name.clone();
+
+    tokio::spawn(async move {
+        let started = progress_notification(&token, 0, 1, &format!("Running {}", tool_name));
+        let _ = tx.send(started).await;
+
+        let mut response = tool_call_response(&state, id, call).await;
+        if !include_meta {
+            strip_meta(&mut response);
+        }
+
+        let finished = progress_notification(&token, 1, 1, &format!("Finished {}", tool_name));
+        let _ = tx.send(finished).await;
+        let _ = tx.send(response).await;
+    });
 
-    Json(response_body).into_response()
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        let message = rx.recv().await?;
+        let event = Event::default().event("message").data(message.to_string());
+        Some((Ok::<_, Infallible>(event), rx))
+    });
+
+    Sse::new(stream).into_response()
 }
 
 // =============================================================================
@@ -98,53 +709,578 @@ fn handle_initialize() -> Value {
 }
 
 /// Handles `tools/list` request.
-fn handle_tools_list() -> Value {
+fn handle_tools_list(state: &AppState) -> Value {
+    let tools: Vec<Value> = all_tools(state).map(|tool| tool.schema()).collect();
     json!({
-        "tools": [
-            {
-                "name": TOOL_NAME,
-                "title": "Add items to cart",
-                "description": "Adds the provided items to the active cart and returns its state.",
-                "inputSchema": {
-                    "type": "object",
-                    "properties": {
-                        "items": {
-                            "type": "array",
+        "tools": tools,
+        "_meta": widget_meta()
+    })
+}
+
+/// Checks each tool's advertised examples against its input; see [`check_tool_examples`]
+pub fn self_check(state: &AppState) -> Vec<String> {
+    check_tool_examples(all_tools(state))
+}
+
+/// Validates each tool's advertised examples as that tool's input, returning one
+/// message per tool without examples or example that fails to validate
+fn check_tool_examples<'a>(tools: impl Iterator<Item = &'a dyn Tool>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for tool in tools {
+        let schema = tool.schema();
+        let examples = schema["examples"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        if examples.is_empty() {
+            problems.push(format!("{}: no examples advertised", tool.name()));
+        }
+        for (index, example) in examples.iter().enumerate() {
+            if let Err(e) = tool.validate_input(example) {
+                problems.push(format!(
+                    "{}: example {} doesn't match its input: {}",
+                    tool.name(),
+                    index,
+                    e
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Handler of a built-in tool that runs synchronously
+type SyncHandler = fn(&AppState, Value) -> Result<Value, ToolError>;
+
+/// A built-in tool: its `tools/list` descriptor, its handler, and the input type `I`
+/// its arguments are validated as
+struct BuiltinTool<I> {
+    descriptor: Value,
+    handler: SyncHandler,
+    mutating: bool,
+    input: PhantomData<fn() -> I>,
+}
+
+impl<I> BuiltinTool<I> {
+    /// Creates a read-only tool named by its descriptor's `name`
+    fn new(descriptor: Value, handler: SyncHandler) -> Self {
+        Self {
+            descriptor,
+            handler,
+            mutating: false,
+            input: PhantomData,
+        }
+    }
+
+    /// Marks the tool as changing the cart it's called on
+    fn mutating(mut self) -> Self {
+        self.mutating = true;
+        self
+    }
+}
+
+impl<I: DeserializeOwned> Tool for BuiltinTool<I> {
+    fn name(&self) -> &str {
+        self.descriptor["name"].as_str().unwrap_or_default()
+    }
+
+    fn schema(&self) -> Value {
+        self.descriptor.clone()
+    }
+
+    fn is_mutating(&self) -> bool {
+        self.mutating
+    }
+
+    fn validate_input(&self, args: &Value) -> Result<(), String> {
+        serde_json::from_value::<I>(args.clone())
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }
+
+    fn call<'a>(&'a self, state: &'a AppState, args: Value) -> ToolFuture<'a> {
+        Box::pin(std::future::ready((self.handler)(state, args)))
+    }
+}
+
+/// `render_cart`, the built-in tool whose handler awaits loading the widget HTML
+struct RenderCartTool {
+    descriptor: Value,
+}
+
+impl Tool for RenderCartTool {
+    fn name(&self) -> &str {
+        RENDER_CART_TOOL_NAME
+    }
+
+    fn schema(&self) -> Value {
+        self.descriptor.clone()
+    }
+
+    fn validate_input(&self, args: &Value) -> Result<(), String> {
+        serde_json::from_value::<CartRefInput>(args.clone())
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }
+
+    fn call<'a>(&'a self, state: &'a AppState, args: Value) -> ToolFuture<'a> {
+        Box::pin(handle_render_cart_tool(state, args))
+    }
+}
+
+/// The built-in tools, in the order they're listed
+fn builtin_tools() -> &'static ToolRegistry {
+    static BUILTIN_TOOLS: OnceLock<ToolRegistry> = OnceLock::new();
+    BUILTIN_TOOLS.get_or_init(|| {
+        let mut tools = ToolRegistry::default();
+        tools.register(
+            BuiltinTool::<AddToCartInput>::new(
+                json!({
+                    "name": TOOL_NAME,
+                    "title": "Add items to cart",
+                    "description": "Adds the provided items to the active cart and returns its state.",
+                    "examples": [{ "cartId": "cart_1", "items": [{ "name": "Apple", "quantity": 2 }, { "name": "Milk" }] }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "items": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "required": ["name"],
+                                    "properties": {
+                                        "name": { "type": "string", "minLength": 1 },
+                                        "quantity": { "type": "integer", "minimum": 1, "maximum": u32::MAX, "default": 1 }
+                                    },
+                                    "additionalProperties": true
+                                }
+                            },
+                            "cartId": { "type": "string" },
+                            "idempotencyKey": { "type": "string" }
+                        },
+                        "required": ["items"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_add_to_cart_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<CheckoutInput>::new(
+                json!({
+                    "name": CHECKOUT_TOOL_NAME,
+                    "title": "Checkout",
+                    "description": "Checks out the current cart, clearing it and returning a receipt.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_checkout_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": GET_CART_TOOL_NAME,
+                    "title": "Get cart",
+                    "description": "Returns the cart's current items and totals without changing it.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_get_cart_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<BudgetAddInput>::new(
+                json!({
+                    "name": ADD_WITHIN_BUDGET_TOOL_NAME,
+                    "title": "Add items within a budget",
+                    "description": "Adds priced items only if the cart subtotal stays within the budget; otherwise nothing is added and the overage is reported.",
+                    "examples": [{ "cartId": "cart_1", "budget": 20, "items": [{ "name": "Apple", "quantity": 2, "price": 1.5 }] }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "budget": { "type": "number", "minimum": 0 },
                             "items": {
-                                "type": "object",
-                                "required": ["name"],
-                                "properties": {
-                                    "name": { "type": "string" },
-                                    "quantity": { "type": "integer", "default": 1 }
-                                },
-                                "additionalProperties": true
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "name": { "type": "string" },
+                                        "quantity": { "type": "integer", "minimum": 1 }
+                                    },
+                                    "required": ["name"],
+                                    "additionalProperties": true
+                                }
                             }
                         },
-                        "cartId": { "type": "string" }
+                        "required": ["cartId", "budget", "items"],
+                        "additionalProperties": false
                     },
-                    "required": ["items"],
-                    "additionalProperties": false
-                },
-                "_meta": widget_meta()
-            },
-            {
-                "name": CHECKOUT_TOOL_NAME,
-                "title": "Checkout",
-                "description": "Checks out the current cart, clearing it and returning a receipt.",
+                    "_meta": widget_meta()
+                }),
+                handle_add_within_budget_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<CouponInput>::new(
+                json!({
+                    "name": APPLY_COUPON_TOOL_NAME,
+                    "title": "Apply coupon",
+                    "description": "Applies a coupon code to the cart and returns the discounted totals.",
+                    "examples": [{ "cartId": "cart_1", "code": "SAVE10" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "code": { "type": "string" }
+                        },
+                        "required": ["cartId", "code"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_apply_coupon_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<CouponInput>::new(
+                json!({
+                    "name": REMOVE_COUPON_TOOL_NAME,
+                    "title": "Remove coupon",
+                    "description": "Removes a previously applied coupon code from the cart.",
+                    "examples": [{ "cartId": "cart_1", "code": "SAVE10" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "code": { "type": "string" }
+                        },
+                        "required": ["cartId", "code"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_remove_coupon_tool,
+            )
+            .mutating(),
+        );
+        tools.register(RenderCartTool {
+            descriptor: json!({
+                "name": RENDER_CART_TOOL_NAME,
+                "title": "Render cart",
+                "description": "Returns the shopping cart widget HTML with the current cart embedded.",
+                "examples": [{ "cartId": "cart_1" }],
                 "inputSchema": {
                     "type": "object",
                     "properties": {
                         "cartId": { "type": "string" }
                     },
+                    "required": ["cartId"],
                     "additionalProperties": false
-                },
-                "_meta": widget_meta()
-            }
-        ],
-        "_meta": widget_meta()
+                }
+            }),
+        });
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": VALIDATE_CART_TOOL_NAME,
+                    "title": "Validate cart",
+                    "description": "Checks the cart against the catalog for unknown products, price mismatches, and stock shortages without modifying it.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_validate_cart_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": SHARE_CART_TOOL_NAME,
+                    "title": "Share cart",
+                    "description": "Returns a link that opens this cart for someone else.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_share_cart_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": CART_QR_TOOL_NAME,
+                    "title": "Cart QR code",
+                    "description": "Returns a QR code (PNG) carrying the cart's items, or a share link when the cart is too large, for scanning onto another device.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_cart_qr_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<SharedCartInput>::new(
+                json!({
+                    "name": LOAD_SHARED_CART_TOOL_NAME,
+                    "title": "Load shared cart",
+                    "description": "Opens a cart from a share link, verifying its signature when signing is enabled.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "signature": { "type": "string" },
+                            "exp": { "type": "integer" },
+                            "nonce": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_load_shared_cart_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<PatchInput>::new(
+                json!({
+                    "name": APPLY_PATCH_TOOL_NAME,
+                    "title": "Patch cart",
+                    "description": "Applies an RFC 6902 JSON Patch (add, remove, replace) to the cart's items array.",
+                    "examples": [{ "cartId": "cart_1", "patch": [{ "op": "replace", "path": "/0/quantity", "value": 3 }] }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "patch": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "required": ["op", "path"],
+                                    "properties": {
+                                        "op": { "type": "string", "enum": ["add", "remove", "replace"] },
+                                        "path": { "type": "string" },
+                                        "value": {}
+                                    }
+                                }
+                            }
+                        },
+                        "required": ["cartId", "patch"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_apply_patch_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<MetadataInput>::new(
+                json!({
+                    "name": SET_CART_METADATA_TOOL_NAME,
+                    "title": "Set cart metadata",
+                    "description": "Attaches client data such as a table number or customer note to the cart. A null value removes the key.",
+                    "examples": [{ "cartId": "cart_1", "metadata": { "tableNumber": 12 } }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "metadata": { "type": "object" }
+                        },
+                        "required": ["cartId", "metadata"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_set_cart_metadata_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": SHIPPING_ESTIMATE_TOOL_NAME,
+                    "title": "Estimate shipping",
+                    "description": "Estimates the cart's shipping cost from the configured tiers, based on its discounted subtotal and total weight.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_shipping_estimate_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<TaxEstimateInput>::new(
+                json!({
+                    "name": TAX_ESTIMATE_TOOL_NAME,
+                    "title": "Estimate tax",
+                    "description": "Estimates tax on the cart for a state or country code using the configured regional rates.",
+                    "examples": [{ "cartId": "cart_1", "region": "CA" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "region": { "type": "string" }
+                        },
+                        "required": ["cartId", "region"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_tax_estimate_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<RecommendInput>::new(
+                json!({
+                    "name": RECOMMEND_TOOL_NAME,
+                    "title": "Recommend products",
+                    "description": "Suggests catalog products related to the cart's items, each with a reason. Products already in the cart are never suggested.",
+                    "examples": [{ "cartId": "cart_1", "limit": 3 }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "limit": { "type": "integer", "minimum": 1 }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_recommend_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": UNDO_REMOVE_TOOL_NAME,
+                    "title": "Undo remove",
+                    "description": "Restores the item most recently removed from the cart, while it is still within the undo window.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    },
+                    "_meta": widget_meta()
+                }),
+                handle_undo_remove_tool,
+            )
+            .mutating(),
+        );
+        tools.register(
+            BuiltinTool::<CartRefInput>::new(
+                json!({
+                    "name": CART_HISTORY_TOOL_NAME,
+                    "title": "Cart history",
+                    "description": "Lists the cart's recent changes (adds, removals, syncs, checkout) with timestamps, oldest first.",
+                    "examples": [{ "cartId": "cart_1" }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" }
+                        },
+                        "required": ["cartId"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_cart_history_tool,
+            ),
+        );
+        tools.register(
+            BuiltinTool::<ConvertTotalInput>::new(
+                json!({
+                    "name": CONVERT_TOTAL_TOOL_NAME,
+                    "title": "Convert total",
+                    "description": "Shows the cart total in other currencies using the configured exchange rates. Currencies without a rate are skipped with a warning.",
+                    "examples": [{ "cartId": "cart_1", "currencies": ["EUR", "JPY"] }],
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "cartId": { "type": "string" },
+                            "currencies": { "type": "array", "items": { "type": "string" } }
+                        },
+                        "required": ["cartId", "currencies"],
+                        "additionalProperties": false
+                    }
+                }),
+                handle_convert_total_tool,
+            ),
+        );
+        tools
     })
 }
 
+/// Looks up a tool by name; a tool registered on the state replaces a built-in
+fn find_tool<'a>(state: &'a AppState, name: &str) -> Option<&'a dyn Tool> {
+    state.tools.get(name).or_else(|| builtin_tools().get(name))
+}
+
+/// Every tool in listing order: the built-ins, each replaced by a registered tool of
+/// the same name, then the other registered tools
+fn all_tools(state: &AppState) -> impl Iterator<Item = &dyn Tool> {
+    let builtins = builtin_tools();
+    builtins
+        .iter()
+        .map(|builtin| state.tools.get(builtin.name()).unwrap_or(builtin))
+        .chain(
+            state
+                .tools
+                .iter()
+                .filter(|tool| builtins.get(tool.name()).is_none()),
+        )
+}
+
 /// Handles `resources/list` request.
 fn handle_resources_list() -> Value {
     json!({
@@ -158,6 +1294,20 @@ fn handle_resources_list() -> Value {
     })
 }
 
+/// Handles `resources/templates/list` request.
+/// The widget URI has no variables yet, but is listed so template-aware clients can find it.
+fn handle_resource_templates_list() -> Value {
+    json!({
+        "resourceTemplates": [{
+            "name": "Shopping cart widget",
+            "uriTemplate": WIDGET_TEMPLATE_URI,
+            "mimeType": WIDGET_MIME_TYPE,
+            "_meta": widget_meta()
+        }],
+        "_meta": widget_meta()
+    })
+}
+
 /// Handles `resources/read` request.
 async fn handle_resources_read(state: &AppState) -> Value {
     let html = state.load_widget_html().await.unwrap_or_default();
@@ -173,59 +1323,434 @@ async fn handle_resources_read(state: &AppState) -> Value {
 }
 
 /// Handles `tools/call` request (Business Logic).
-pub fn handle_tool_call(state: &AppState, name: &str, args: Value) -> Result<Value, String> {
-    match name {
-        TOOL_NAME => handle_add_to_cart_tool(state, args),
-        CHECKOUT_TOOL_NAME => handle_checkout_tool(state, args),
-        _ => Err(format!("Unknown tool: {}", name)),
+pub async fn handle_tool_call(
+    state: &AppState,
+    name: &str,
+    args: Value,
+) -> Result<Value, ToolError> {
+    // Every tool takes its cart id under the same key, so check it once here
+    if let Some(cart_id) = args.get("cartId").and_then(Value::as_str) {
+        state.check_cart_id(cart_id)?;
     }
-}
 
-/// Handles the add_to_cart tool functionality
-fn handle_add_to_cart_tool(state: &AppState, args: Value) -> Result<Value, String> {
-    let input: AddToCartInput =
-        serde_json::from_value(args).map_err(|e| format!("Invalid arguments: {}", e))?;
+    let name = state.log_deprecation(state.aliases.tool(name));
 
-    let cart_id = get_or_create_cart_id(input.cart_id);
+    // Lets widget developers see their loading states; never on in release by accident
+    if let Some(delay) = state.config.simulated_delay() {
+        tokio::time::sleep(delay).await;
+    }
 
-    // Update or initialize cart
-    let mut cart_items = state.carts.entry(cart_id.clone()).or_insert(Vec::new());
+    let arg_cart_id = args.get("cartId").and_then(Value::as_str).map(String::from);
+    let tool = find_tool(state, name).ok_or_else(|| ToolError::unknown_tool(name))?;
+    let mut result = tool.call(state, args).await?;
 
-    // Update cart contents
-    update_cart_with_new_items(&mut cart_items, input.items);
+    // Save carts the tool changed; add_to_cart may have minted the id it reports
+    if tool.is_mutating() {
+        let cart_id = result["structuredContent"]["cartId"]
+            .as_str()
+            .map(String::from)
+            .or(arg_cart_id);
+        if let Some(cart_id) = cart_id {
+            state.persist_cart(&cart_id).await?;
+        }
+    }
 
-    let current_items = cart_items.clone();
-    let message = format!("Cart {} now has {} item(s).", cart_id, current_items.len());
+    // Names are stored raw, so escape the text that displays them
+    if state.config.escape_item_names {
+        escape_text_content(&mut result);
+    }
+
+    // Last, so the steps above can rely on the camelCase keys handlers produce
+    if state.config.output_case == OutputCase::Snake {
+        if let Some(content) = result.get_mut("structuredContent") {
+            snake_case_keys(content);
+        }
+    }
+    Ok(result)
+}
+
+/// HTML-escapes the text of a result's `text` content blocks
+fn escape_text_content(result: &mut Value) {
+    let Some(content) = result.get_mut("content").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for block in content {
+        if block["type"] == "text" {
+            if let Some(text) = block["text"].as_str() {
+                block["text"] = json!(escape_html(text));
+            }
+        }
+    }
+}
+
+/// Fields holding client data, whose keys are returned exactly as the client sent them
+const DATA_FIELDS: [&str; 1] = ["metadata"];
+
+/// Fields holding a cart item or list of items. Items mix a few server keys with the
+/// client's extras, so only the server's keys are recased.
+const ITEM_FIELDS: [&str; 6] = [
+    "items",
+    "removedItems",
+    "added",
+    "removed",
+    "before",
+    "after",
+];
+
+/// Keys the server itself adds to items
+const ITEM_SCHEMA_KEYS: [&str; 1] = ["removedAt"];
+
+/// Rewrites the server's object keys in `value` from camelCase to snake_case, leaving
+/// client data such as metadata and item extras untouched
+fn snake_case_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    if ITEM_FIELDS.contains(&key.as_str()) {
+                        snake_case_item_keys(&mut value);
+                    } else if !DATA_FIELDS.contains(&key.as_str()) {
+                        snake_case_keys(&mut value);
+                    }
+                    (to_snake_case(&key), value)
+                })
+                .collect();
+        }
+        Value::Array(values) => values.iter_mut().for_each(snake_case_keys),
+        _ => {}
+    }
+}
+
+/// Recases only [`ITEM_SCHEMA_KEYS`] in an item or list of items
+fn snake_case_item_keys(value: &mut Value) {
+    match value {
+        Value::Object(item) => {
+            for key in ITEM_SCHEMA_KEYS {
+                if let Some(field) = item.remove(key) {
+                    item.insert(to_snake_case(key), field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(snake_case_item_keys),
+        _ => {}
+    }
+}
+
+/// Converts a camelCase key to snake_case ("cartId" -> "cart_id")
+fn to_snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for (i, c) in key.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Parses a tool's arguments, naming in the error `data` where they went wrong,
+/// e.g. `{ "path": "items[0].quantity" }`
+fn parse_tool_args<T: DeserializeOwned>(args: Value) -> Result<T, ToolError> {
+    serde_path_to_error::deserialize(args).map_err(|e| {
+        ToolError::with_data(
+            format!("Invalid arguments: {}", e.inner()),
+            json!({ "path": e.path().to_string() }),
+        )
+    })
+}
+
+/// Handles the add_to_cart tool functionality
+fn handle_add_to_cart_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: AddToCartInput = parse_tool_args(args)?;
+    input.validate()?;
+
+    // A retried call with a remembered key returns the original result without adding
+    // again. The key is claimed before adding, so concurrent retries can't both run.
+    let scope = input.idempotency_key.clone().map(|key| {
+        let cart_id = input.cart_id.clone().unwrap_or_default();
+        (TOOL_NAME.to_string(), cart_id, key)
+    });
+    if let Some(scope) = &scope {
+        if let Some(result) = state.claim_idempotency_key(scope)? {
+            return Ok(result);
+        }
+    }
+
+    let outcome = add_items(state, input);
+    if let Some(scope) = &scope {
+        state.settle_idempotency_key(scope, outcome.as_ref().ok());
+    }
+    outcome
+}
+
+/// Adds validated items to the cart, creating it if needed
+fn add_items(state: &AppState, mut input: AddToCartInput) -> Result<Value, ToolError> {
+    let warnings = state.prepare_items(&mut input.items)?;
+
+    let cart_id = get_or_create_cart_id(input.cart_id);
+
+    // Update or initialize cart
+    let (message, mut structured) = state.try_with_cart_mut(&cart_id, |cart| {
+        let names: Vec<String> = input.items.iter().map(|i| i.name.clone()).collect();
+        let added = format_item_summary(&input.items, state.config.summary_max_items());
+        let mut items = cart.items.clone();
+        let actions = update_cart_with_new_items(&mut items, input.items);
+        state.admit_items(&cart_id, &items)?;
+        cart.items = items;
+        cart.record(state.config.cart_history_limit(), CartMutation::Add, added);
+        let message = format!(
+            "Cart {} now has {}.",
+            cart_id,
+            pluralize(cart.items.len(), "item")
+        );
+        let mut structured = cart_structured_content(state, &cart_id, cart);
+        // Tells the widget which lines are new and which grew, in request order
+        structured["itemActions"] = names
+            .into_iter()
+            .zip(actions)
+            .map(|(name, action)| json!({ "name": name, "action": action }))
+            .collect();
+        Ok::<_, String>((message, structured))
+    })?;
+    if !warnings.is_empty() {
+        structured["warnings"] = json!(warnings);
+    }
 
     Ok(json!({
         "content": [{ "type": "text", "text": message }],
-        "structuredContent": {
-            "cartId": cart_id,
-            "items": current_items
-        },
+        "structuredContent": structured,
         "_meta": widget_meta()
     }))
 }
 
+/// Handles the get_cart tool functionality. An unknown cart reads as empty and is
+/// not created.
+fn handle_get_cart_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+
+    // Copy the cart out so building the content doesn't hold its lock
+    let cart = state
+        .carts
+        .get(&input.cart_id)
+        .map(|cart| cart.clone())
+        .unwrap_or_default();
+    let message = format!(
+        "Cart {} has {}: {}",
+        input.cart_id,
+        pluralize(cart.items.len(), "item"),
+        format_item_summary(&cart.items, state.config.summary_max_items())
+    );
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": message }],
+        "structuredContent": cart_structured_content(state, &input.cart_id, &cart),
+        "_meta": widget_meta()
+    }))
+}
+
+/// Handles the add_within_budget tool functionality
+fn handle_add_within_budget_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let mut input: BudgetAddInput = parse_tool_args(args)?;
+
+    if !(input.budget.is_finite() && input.budget >= 0.0) {
+        return Err("budget must be a non-negative number".to_string().into());
+    }
+    let warnings = state.prepare_items(&mut input.items)?;
+    if let Some(item) = input.items.iter().find(|item| item.price().is_none()) {
+        return Err(format!(
+            "{} has no price; budgeted adds need priced items",
+            item.name
+        )
+        .into());
+    }
+
+    // Check and add under the cart's lock so concurrent adds can't both fit
+    let policy = state.pricing_policy();
+    state.try_with_cart_mut(&input.cart_id, |cart| {
+        let mut candidate = cart.clone();
+        let added = format_item_summary(&input.items, state.config.summary_max_items());
+        update_cart_with_new_items(&mut candidate.items, input.items);
+        let subtotal = candidate.total_value(&policy).subtotal;
+
+        if subtotal > input.budget {
+            let overage = round_to(subtotal - input.budget, policy.decimals);
+            return Err(ToolError::with_data(
+                format!(
+                    "Adding these items would exceed the budget by {:.*}",
+                    policy.decimals as usize,
+                    overage
+                ),
+                json!({
+                    "budget": input.budget,
+                    "subtotal": round_to(subtotal, policy.decimals),
+                    "overage": overage
+                }),
+            ));
+        }
+        state.admit_items(&input.cart_id, &candidate.items)?;
+        *cart = candidate;
+        cart.record(state.config.cart_history_limit(), CartMutation::Add, added);
+
+        let mut structured = cart_structured_content(state, &input.cart_id, cart);
+        structured["budget"] = json!(input.budget);
+        structured["remainingBudget"] = json!(round_to(input.budget - subtotal, policy.decimals));
+        if !warnings.is_empty() {
+            structured["warnings"] = json!(warnings);
+        }
+
+        Ok(json!({
+            "content": [{
+                "type": "text",
+                "text": format!("Cart {} now has {}.", input.cart_id, pluralize(cart.items.len(), "item"))
+            }],
+            "structuredContent": structured,
+            "_meta": widget_meta()
+        }))
+    })
+}
+
+/// Builds the structured content shared by tools that return a cart's state
+fn cart_structured_content(state: &AppState, cart_id: &str, cart: &Cart) -> Value {
+    let mut content = json!({
+        "cartId": cart_id,
+        "fingerprint": cart.fingerprint(),
+        "items": cart.items,
+        "lines": cart.items.iter().map(line_pricing).collect::<Vec<_>>(),
+        "availability": state.availability(cart_id, &cart.items),
+        "currencyInfo": currency_info(state.config.currency()),
+        "totalWeight": total_weight(&cart.items),
+        "weightUnit": state.config.weight_unit(),
+        "metadata": cart.metadata
+    });
+    if !cart.removed.is_empty() {
+        content["removedItems"] = json!(cart.removed);
+    }
+    merge_totals(&mut content, &cart.total_value(&state.pricing_policy()));
+    if let Some(max_bytes) = state.config.max_response_bytes {
+        cap_response_size(&mut content, max_bytes);
+    }
+    content
+}
+
+/// Per-item arrays in cart content, kept in step when truncating
+const PER_ITEM_FIELDS: [&str; 3] = ["items", "lines", "availability"];
+
+/// Drops trailing items until the content serializes within `max_bytes`, flagging it
+/// with `truncated` and `totalItems`. Totals still describe the whole cart.
+fn cap_response_size(content: &mut Value, max_bytes: usize) {
+    let size = |content: &Value| serde_json::to_vec(content).map_or(0, |bytes| bytes.len());
+    if size(content) <= max_bytes {
+        return;
+    }
+
+    let full: Vec<Vec<Value>> = PER_ITEM_FIELDS
+        .iter()
+        .map(|field| content[*field].as_array().cloned().unwrap_or_default())
+        .collect();
+    let total_items = full[0].len();
+    content["truncated"] = json!(true);
+    content["totalItems"] = json!(total_items);
+
+    let keep_first = |content: &mut Value, count: usize| {
+        for (field, values) in PER_ITEM_FIELDS.iter().zip(&full) {
+            content[*field] = json!(values[..count.min(values.len())]);
+        }
+    };
+
+    // Binary search for the most items that still fit
+    let (mut low, mut high) = (0, total_items);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        keep_first(content, mid);
+        if size(content) <= max_bytes {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    keep_first(content, low);
+}
+
+/// Adds the serialized (rounded) totals fields to a JSON object
+fn merge_totals(target: &mut Value, totals: &Totals) {
+    if let (Some(target), Value::Object(fields)) = (target.as_object_mut(), json!(totals)) {
+        target.extend(fields);
+    }
+}
+
 /// Handles the checkout tool functionality
-fn handle_checkout_tool(state: &AppState, args: Value) -> Result<Value, String> {
-    let input: CheckoutInput =
-        serde_json::from_value(args).map_err(|e| format!("Invalid arguments: {}", e))?;
+fn handle_checkout_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CheckoutInput = parse_tool_args(args)?;
 
-    let cart_id = get_or_create_cart_id(input.cart_id);
+    // A freshly minted id never has a cart, so checkout needs the caller's id
+    let cart_id = input
+        .cart_id
+        .ok_or_else(|| "cartId required for checkout".to_string())?;
+    if state.config.block_empty_checkout && state.is_empty_cart(&cart_id) {
+        return Err(EMPTY_CHECKOUT_ERROR.to_string().into());
+    }
+
+    // Resolve the payment link up front so a bad template never loses the cart
+    let order_id = new_order_id();
+    let payment_url = state
+        .config
+        .payment_url_template
+        .as_deref()
+        .map(|template| resolve_payment_url(template, &order_id))
+        .transpose()?;
 
     // Remove the cart from the state to clear it
-    if let Some((_, items)) = state.carts.remove(&cart_id) {
-        let item_summary = format_item_summary(&items);
-        let message = format!("Checked out now: {}", item_summary);
+    if let Some((_, mut cart)) = state.carts.remove(&cart_id) {
+        record_cart_change(&cart_id, &cart.items, &[]);
+        state.stock_holds.release(&cart_id);
+        let item_summary = format_item_summary(&cart.items, state.config.summary_max_items());
+        cart.record(
+            state.config.cart_history_limit(),
+            CartMutation::Checkout,
+            format!("order {}", order_id),
+        );
+        let message = format!(
+            "Checked out {}: {}",
+            pluralize(cart.items.len(), "item"),
+            item_summary
+        );
         println!("BACKEND CHECKOUT: {}", message);
 
+        // Counts for a toast, taken before the items move into the receipt
+        let items_checked_out = cart.items.len();
+        let units_checked_out: u64 = cart.items.iter().map(|i| u64::from(i.quantity)).sum();
+
+        let totals = cart.total_value(&state.pricing_policy());
+        state.record_order(OrderRecord::for_cart(&order_id, &cart, &totals));
+
+        let mut receipt = json!({
+            "orderId": order_id,
+            "items": cart.items
+        });
+        merge_totals(&mut receipt, &totals);
+        if !cart.history.is_empty() {
+            receipt["history"] = json!(cart.history);
+        }
+        if let Some(url) = payment_url {
+            receipt["paymentUrl"] = json!(url);
+        }
+
         Ok(json!({
             "content": [{ "type": "text", "text": message }],
             "structuredContent": {
                 "cartId": cart_id,
                 "items": [],
-                "checkout": true
+                "checkout": true,
+                "itemsCheckedOut": items_checked_out,
+                "unitsCheckedOut": units_checked_out,
+                "total": receipt["total"].clone(),
+                "receipt": receipt,
+                "currencyInfo": currency_info(state.config.currency())
             },
             "_meta": widget_meta()
         }))
@@ -236,9 +1761,1413 @@ fn handle_checkout_tool(state: &AppState, args: Value) -> Result<Value, String>
             "structuredContent": {
                 "cartId": cart_id,
                 "items": [],
-                "checkout": true
+                "checkout": true,
+                "itemsCheckedOut": 0,
+                "unitsCheckedOut": 0,
+                "total": 0.0,
+                "currencyInfo": currency_info(state.config.currency())
             },
             "_meta": widget_meta()
         }))
     }
 }
+
+/// Handles the apply_coupon tool functionality
+fn handle_apply_coupon_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CouponInput = parse_tool_args(args)?;
+
+    let code = normalize_coupon_code(&input.code);
+    if !state.coupons.contains_key(&code) {
+        return Err(format!("Unknown coupon: {}", input.code).into());
+    }
+
+    let max_coupons = state.config.max_coupons_per_cart();
+    let structured = state
+        .with_existing_cart_mut(&input.cart_id, |cart| {
+            if !cart.coupons.contains(&code) {
+                if cart.coupons.len() >= max_coupons {
+                    return Err(format!(
+                        "Cart already has {}; remove one before applying {}",
+                        pluralize(cart.coupons.len(), "coupon"),
+                        code
+                    ));
+                }
+                cart.coupons.push(code.clone());
+            }
+            Ok(cart_structured_content(state, &input.cart_id, cart))
+        })
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))??;
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": format!("Coupon {} applied.", code) }],
+        "structuredContent": structured,
+        "_meta": widget_meta()
+    }))
+}
+
+/// Handles the remove_coupon tool functionality
+fn handle_remove_coupon_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CouponInput = parse_tool_args(args)?;
+
+    let code = normalize_coupon_code(&input.code);
+    let structured = state
+        .with_existing_cart_mut(&input.cart_id, |cart| {
+            if !cart.coupons.contains(&code) {
+                return None;
+            }
+            cart.coupons.retain(|c| c != &code);
+            Some(cart_structured_content(state, &input.cart_id, cart))
+        })
+        .flatten()
+        .ok_or_else(|| format!("Coupon {} is not applied", code))?;
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": format!("Coupon {} removed.", code) }],
+        "structuredContent": structured,
+        "_meta": widget_meta()
+    }))
+}
+
+/// Handles the render_cart tool functionality, returning the widget HTML inline
+async fn handle_render_cart_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+
+    let html = state
+        .load_widget_html()
+        .await
+        .map_err(|_| "Widget HTML is unavailable".to_string())?;
+
+    let mut items = state
+        .carts
+        .get(&input.cart_id)
+        .map(|cart| cart.items.clone())
+        .unwrap_or_default();
+    if state.config.escape_item_names {
+        for item in &mut items {
+            item.name = escape_html(&item.name);
+        }
+    }
+    let cart = json!({ "cartId": input.cart_id, "items": items });
+
+    Ok(json!({
+        "content": [{
+            "type": "resource",
+            "resource": {
+                "uri": WIDGET_TEMPLATE_URI,
+                "mimeType": WIDGET_MIME_TYPE,
+                "text": inject_cart_data(&html, &cart)
+            }
+        }],
+        "structuredContent": cart
+    }))
+}
+
+/// Handles the validate_cart tool functionality (read-only)
+fn handle_validate_cart_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+
+    if state.catalog.is_empty() {
+        return Err("No catalog configured".to_string().into());
+    }
+
+    let items = state
+        .carts
+        .get(&input.cart_id)
+        .map(|cart| cart.items.clone())
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?;
+    let report = state.catalog.validate(&items);
+
+    let message = if report.valid {
+        format!("Cart {} is valid.", input.cart_id)
+    } else {
+        format!("Cart {} has problems.", input.cart_id)
+    };
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": message }],
+        "structuredContent": {
+            "cartId": input.cart_id,
+            "validation": report
+        }
+    }))
+}
+
+/// Handles the share_cart tool functionality
+fn handle_share_cart_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+
+    let base_url = state
+        .config
+        .share_base_url
+        .as_deref()
+        .ok_or_else(|| "Cart sharing is not configured".to_string())?;
+    if !state.carts.contains_key(&input.cart_id) {
+        return Err(format!("Cart not found: {}", input.cart_id).into());
+    }
+
+    let secret = state.config.share_secret.as_deref();
+    let claims = state.config.share_link_ttl().map(share::Claims::new);
+    let url = share::share_link(base_url, &input.cart_id, secret, claims.as_ref());
+
+    let mut structured = json!({ "cartId": input.cart_id, "shareUrl": url });
+    if let Some(secret) = secret {
+        structured["signature"] = json!(share::sign(&input.cart_id, claims.as_ref(), secret));
+        if let Some(claims) = &claims {
+            structured["exp"] = json!(claims.exp);
+            structured["nonce"] = json!(claims.nonce);
+        }
+    }
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": format!("Share this cart: {}", url) }],
+        "structuredContent": structured
+    }))
+}
+
+/// Handles the cart_qr_code tool functionality. Small carts are encoded directly as
+/// `{"cartId", "items": [[name, quantity], ...]}`; larger ones fall back to a share link.
+fn handle_cart_qr_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+
+    let items: Vec<Value> = state
+        .carts
+        .get(&input.cart_id)
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?
+        .items
+        .iter()
+        .map(|item| json!([item.name, item.quantity]))
+        .collect();
+
+    let compact = json!({ "cartId": input.cart_id, "items": items }).to_string();
+    let (encoding, payload) = if compact.len() <= MAX_QR_PAYLOAD_BYTES {
+        ("items", compact)
+    } else {
+        let base_url = state.config.share_base_url.as_deref().ok_or_else(|| {
+            "Cart is too large for a QR code; configure SHARE_BASE_URL to encode a link instead"
+                .to_string()
+        })?;
+        let claims = state.config.share_link_ttl().map(share::Claims::new);
+        let secret = state.config.share_secret.as_deref();
+        let link = share::share_link(base_url, &input.cart_id, secret, claims.as_ref());
+        ("link", link)
+    };
+    let png = qr::png_base64(&payload)?;
+
+    Ok(json!({
+        "content": [{ "type": "image", "data": png, "mimeType": "image/png" }],
+        "structuredContent": {
+            "cartId": input.cart_id,
+            "encoding": encoding,
+            "payloadBytes": payload.len()
+        }
+    }))
+}
+
+/// Handles the load_shared_cart tool functionality
+fn handle_load_shared_cart_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: SharedCartInput = parse_tool_args(args)?;
+
+    let claims = match (input.exp, input.nonce) {
+        (Some(exp), Some(nonce)) => Some(share::Claims { exp, nonce }),
+        _ => None,
+    };
+
+    if let Some(secret) = state.config.share_secret.as_deref() {
+        if claims.is_none() && state.config.share_link_ttl().is_some() {
+            return Err("Share link is missing its expiry".to_string().into());
+        }
+        let signature = input.signature.as_deref().unwrap_or_default();
+        if !share::verify(&input.cart_id, claims.as_ref(), signature, secret) {
+            return Err("Invalid share link signature".to_string().into());
+        }
+        if claims.as_ref().is_some_and(share::Claims::is_expired) {
+            return Err("Share link has expired".to_string().into());
+        }
+    }
+
+    let cart = state
+        .carts
+        .get(&input.cart_id)
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?;
+
+    // Spend the nonce last so a failed load doesn't burn the link
+    if state.config.share_secret.is_some() {
+        if let Some(claims) = &claims {
+            if !state.redeem_share_nonce(claims) {
+                return Err("Share link has already been used".to_string().into());
+            }
+        }
+    }
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("Loaded shared cart with {}.", pluralize(cart.items.len(), "item"))
+        }],
+        "structuredContent": cart_structured_content(state, &input.cart_id, &cart),
+        "_meta": widget_meta()
+    }))
+}
+
+/// Handles the apply_patch tool functionality
+fn handle_apply_patch_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: PatchInput = parse_tool_args(args)?;
+
+    state
+        .with_existing_cart_mut(&input.cart_id, |cart| {
+            // Patch a copy so a rejected patch leaves the cart untouched
+            let mut items = apply_items_patch(&cart.items, input.patch)?;
+            let warnings = state.prepare_items(&mut items)?;
+            state.admit_items(&input.cart_id, &items)?;
+            let dropped = cart.replace_items(items, state.config.soft_delete_window());
+            record_replacement(state, cart, dropped);
+
+            let mut structured = cart_structured_content(state, &input.cart_id, cart);
+            if !warnings.is_empty() {
+                structured["warnings"] = json!(warnings);
+            }
+
+            Ok(json!({
+                "content": [{
+                    "type": "text",
+                    "text": format!("Cart {} now has {}.", input.cart_id, pluralize(cart.items.len(), "item"))
+                }],
+                "structuredContent": structured,
+                "_meta": widget_meta()
+            }))
+        })
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?
+}
+
+/// Records a sync or patch in the cart's history, with one entry per dropped line
+pub fn record_replacement(state: &AppState, cart: &mut Cart, dropped: Vec<String>) {
+    let limit = state.config.cart_history_limit();
+    let summary = format_item_summary(&cart.items, state.config.summary_max_items());
+    cart.record(limit, CartMutation::Set, summary);
+    for name in dropped {
+        cart.record(limit, CartMutation::Remove, name);
+    }
+}
+
+/// Handles the cart_history tool functionality (read-only)
+fn handle_cart_history_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+    if state.config.cart_history_limit().is_none() {
+        return Err("Cart history is not enabled; set CART_HISTORY_LEN"
+            .to_string()
+            .into());
+    }
+
+    let cart = state
+        .carts
+        .get(&input.cart_id)
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?;
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("{} recorded for cart {}.", pluralize(cart.history.len(), "change"), input.cart_id)
+        }],
+        "structuredContent": {
+            "cartId": input.cart_id,
+            "history": cart.history
+        }
+    }))
+}
+
+/// Handles the undo_remove tool functionality
+fn handle_undo_remove_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+    let window = state
+        .config
+        .soft_delete_window()
+        .ok_or_else(|| "Undo is not enabled; set SOFT_DELETE_SECONDS".to_string())?;
+
+    state
+        .with_existing_cart_mut(&input.cart_id, |cart| {
+            let mut candidate = cart.clone();
+            let restored = candidate
+                .undo_remove(window)
+                .ok_or_else(|| format!("Nothing to undo in cart {}", input.cart_id))?;
+            state.admit_items(&input.cart_id, &candidate.items)?;
+            *cart = candidate;
+            cart.record(
+                state.config.cart_history_limit(),
+                CartMutation::Add,
+                format!("restored {}x {}", restored.quantity, restored.name),
+            );
+
+            Ok(json!({
+                "content": [{
+                    "type": "text",
+                    "text": format!("Restored {}x {} to cart {}.", restored.quantity, restored.name, input.cart_id)
+                }],
+                "structuredContent": cart_structured_content(state, &input.cart_id, cart),
+                "_meta": widget_meta()
+            }))
+        })
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?
+}
+
+/// Handles the set_cart_metadata tool functionality
+fn handle_set_cart_metadata_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: MetadataInput = parse_tool_args(args)?;
+
+    state
+        .with_existing_cart_mut(&input.cart_id, |cart| {
+            cart.metadata = merge_cart_metadata(&cart.metadata, input.metadata)?;
+
+            Ok(json!({
+                "content": [{
+                    "type": "text",
+                    "text": format!("Cart {} has {}.", input.cart_id, pluralize(cart.metadata.len(), "metadata key"))
+                }],
+                "structuredContent": cart_structured_content(state, &input.cart_id, cart),
+                "_meta": widget_meta()
+            }))
+        })
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?
+}
+
+/// Handles the shipping_estimate tool functionality (read-only)
+fn handle_shipping_estimate_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: CartRefInput = parse_tool_args(args)?;
+
+    if state.shipping_tiers.is_empty() {
+        return Err("No shipping tiers configured".to_string().into());
+    }
+
+    let cart = state
+        .carts
+        .get(&input.cart_id)
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?;
+
+    // Free-shipping thresholds count what the customer pays for the goods
+    let totals = cart.total_value(&state.pricing_policy());
+    let subtotal = totals.subtotal - totals.discount;
+    let weight = total_weight(&cart.items);
+    let tier = select_tier(&state.shipping_tiers, subtotal, weight)
+        .ok_or_else(|| format!("No shipping tier applies to cart {}", input.cart_id))?;
+
+    let info = currency_info(state.config.currency());
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("{} shipping: {}{:.*}", tier.name, info.symbol, info.decimal_places as usize, tier.cost)
+        }],
+        "structuredContent": {
+            "cartId": input.cart_id,
+            "tier": tier.name,
+            "shippingCost": tier.cost,
+            "freeShipping": tier.cost == 0.0,
+            "subtotal": subtotal,
+            "totalWeight": weight,
+            "weightUnit": state.config.weight_unit(),
+            "currencyInfo": info
+        }
+    }))
+}
+
+/// Handles the recommend tool functionality (read-only)
+fn handle_recommend_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: RecommendInput = parse_tool_args(args)?;
+
+    let items = state
+        .carts
+        .get(&input.cart_id)
+        .map(|cart| cart.items.clone())
+        .unwrap_or_default();
+    let suggestions = recommend(
+        &state.recommendations,
+        &state.catalog,
+        &items,
+        input.limit.unwrap_or(3),
+    );
+
+    let text = if suggestions.is_empty() {
+        "No recommendations for this cart.".to_string()
+    } else {
+        let names: Vec<&str> = suggestions.iter().map(|s| s.name.as_str()).collect();
+        format!("You might also like: {}", names.join(", "))
+    };
+    Ok(json!({
+        "content": [{ "type": "text", "text": text }],
+        "structuredContent": {
+            "cartId": input.cart_id,
+            "recommendations": suggestions
+        }
+    }))
+}
+
+/// Handles the tax_estimate tool functionality (read-only)
+fn handle_tax_estimate_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: TaxEstimateInput = parse_tool_args(args)?;
+
+    let cart = state
+        .carts
+        .get(&input.cart_id)
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?;
+
+    let region = normalize_region(&input.region);
+    let mut warnings = Vec::new();
+    let tax_rate = match state.tax_table.get(&region) {
+        Some(rate) => *rate,
+        None => {
+            warnings.push(format!("No tax rate for region {}; assuming 0", region));
+            0.0
+        }
+    };
+
+    let policy = PricingPolicy {
+        tax_rate,
+        ..state.pricing_policy()
+    };
+    let totals = cart.total_value(&policy);
+
+    let mut structured = json!({
+        "cartId": input.cart_id,
+        "region": region,
+        "taxRate": tax_rate,
+        "currencyInfo": currency_info(state.config.currency())
+    });
+    merge_totals(&mut structured, &totals);
+    if !warnings.is_empty() {
+        structured["warnings"] = json!(warnings);
+    }
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("Estimated {} tax: {}", region, structured["tax"])
+        }],
+        "structuredContent": structured
+    }))
+}
+
+/// Handles the convert_total tool functionality (read-only)
+fn handle_convert_total_tool(state: &AppState, args: Value) -> Result<Value, ToolError> {
+    let input: ConvertTotalInput = parse_tool_args(args)?;
+
+    let cart = state
+        .carts
+        .get(&input.cart_id)
+        .ok_or_else(|| format!("Cart not found: {}", input.cart_id))?;
+    let totals = cart.total_value(&state.pricing_policy());
+    let base = currency_info(state.config.currency());
+
+    let mut conversions = Vec::new();
+    let mut warnings = Vec::new();
+    for code in &input.currencies {
+        let code = code.trim().to_ascii_uppercase();
+        let rate = match state.fx_rates.get(&code) {
+            Some(rate) => *rate,
+            None if code == base.code => 1.0,
+            None => {
+                warnings.push(format!("No exchange rate for {}; skipped", code));
+                continue;
+            }
+        };
+        let info = currency_info(&code);
+        conversions.push(json!({
+            "currency": code,
+            "rate": rate,
+            "total": round_to(totals.total * rate, info.decimal_places),
+            "currencyInfo": info
+        }));
+    }
+
+    let total = round_to(totals.total, totals.decimals);
+    let summary: Vec<String> = conversions
+        .iter()
+        .map(|c| format!("{} {}", c["total"], c["currency"].as_str().unwrap_or("")))
+        .collect();
+    let text = if summary.is_empty() {
+        format!("Total: {} {}", total, base.code)
+    } else {
+        format!("Total: {} {} ({})", total, base.code, summary.join(", "))
+    };
+
+    let mut structured = json!({
+        "cartId": input.cart_id,
+        "total": total,
+        "currencyInfo": base,
+        "conversions": conversions
+    });
+    if !warnings.is_empty() {
+        structured["warnings"] = json!(warnings);
+    }
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": text }],
+        "structuredContent": structured
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::model::{AppState, SharedState, SERVER_NAME};
+    use crate::router::create_app_router;
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn default_state() -> SharedState {
+        Arc::new(AppState::with_config(Config::default()))
+    }
+
+    fn app() -> axum::Router {
+        create_app_router(default_state())
+    }
+
+    async fn post_mcp(state: &SharedState, body: serde_json::Value) -> serde_json::Value {
+        let res = create_app_router(state.clone())
+            .oneshot(
+                Request::post("/mcp")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_root_get_returns_landing_info() {
+        let res = app()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["content-type"], "application/json");
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["name"], SERVER_NAME);
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+
+        // /mcp keeps serving the SSE handshake
+        let res = app()
+            .oneshot(Request::get("/mcp").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["content-type"], "text/event-stream");
+    }
+
+    #[tokio::test]
+    async fn test_validation_error_carries_data_fields() {
+        let state = Arc::new(AppState::with_config(Config {
+            strict_item_schema: true,
+            ..Config::default()
+        }));
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "tools/call",
+            "params": {
+                "name": "add_to_cart",
+                "arguments": { "items": [{ "name": "Pear", "color": "green" }] }
+            }
+        });
+        let response = post_mcp(&state, body).await;
+        assert_eq!(response["error"]["code"], -32602);
+        assert_eq!(
+            response["error"]["data"]["fields"],
+            serde_json::json!([{ "index": 0, "field": "color", "reason": "unsupported field" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strict_ids_reject_fractional_id() {
+        let send = |strict_jsonrpc_ids: bool| async move {
+            let state = Arc::new(AppState::with_config(Config {
+                strict_jsonrpc_ids,
+                ..Config::default()
+            }));
+            let body = serde_json::json!({ "jsonrpc": "2.0", "id": 1.5, "method": "ping" });
+            post_mcp(&state, body).await
+        };
+
+        let response = send(true).await;
+        assert_eq!(response["error"]["code"], -32600);
+        assert_eq!(response["id"], serde_json::Value::Null);
+
+        // Lenient by default
+        let response = send(false).await;
+        assert_eq!(response["id"], 1.5);
+        assert!(response.get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sse_connections_are_capped() {
+        let state = Arc::new(AppState::with_config(Config {
+            max_sse_connections: Some(1),
+            ..Config::default()
+        }));
+        let open = || {
+            create_app_router(state.clone())
+                .oneshot(Request::get("/mcp").body(Body::empty()).unwrap())
+        };
+
+        let first = open().await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = open().await.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Disconnecting frees the slot
+        drop(first);
+        let third = open().await.unwrap();
+        assert_eq!(third.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_strict_params_rejects_tools_list_params() {
+        let send = |strict_params: bool| async move {
+            let state = Arc::new(AppState::with_config(Config {
+                strict_params,
+                ..Config::default()
+            }));
+            let body = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/list",
+                "params": { "filter": "cart", "_meta": {} }
+            });
+            post_mcp(&state, body).await
+        };
+
+        let response = send(true).await;
+        assert_eq!(response["error"]["code"], -32602);
+        assert_eq!(
+            response["error"]["data"]["unexpected"],
+            serde_json::json!(["filter"])
+        );
+
+        // Lenient by default
+        let response = send(false).await;
+        assert!(response["result"]["tools"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_body_is_reported_as_incomplete() {
+        let chunks: Vec<Result<axum::body::Bytes, std::io::Error>> = vec![
+            Ok(axum::body::Bytes::from_static(
+                b"{\"jsonrpc\": \"2.0\", \"id\": 1, ",
+            )),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed before message completed",
+            )),
+        ];
+        let res = app()
+            .oneshot(
+                Request::post("/mcp")
+                    .header("content-type", "application/json")
+                    .header("content-length", "1048576")
+                    .body(Body::from_stream(futures_util::stream::iter(chunks)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+        assert_eq!(
+            response["error"]["message"],
+            "Parse error: incomplete request body"
+        );
+
+        // Malformed but complete JSON keeps the plain parse error
+        let res = app()
+            .oneshot(
+                Request::post("/mcp")
+                    .header("content-type", "application/json")
+                    .body(Body::from("{\"jsonrpc\": "))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["error"]["message"], "Parse error");
+    }
+
+    #[tokio::test]
+    async fn test_registered_tool_is_listed_and_called() {
+        use crate::tools::{Tool, ToolFuture, ToolRegistry};
+
+        struct PingCartTool;
+
+        impl Tool for PingCartTool {
+            fn name(&self) -> &str {
+                "ping_cart"
+            }
+
+            fn schema(&self) -> serde_json::Value {
+                serde_json::json!({
+                    "name": "ping_cart",
+                    "title": "Ping cart",
+                    "description": "Reports whether a cart exists.",
+                    "inputSchema": { "type": "object" }
+                })
+            }
+
+            fn call<'a>(&'a self, state: &'a AppState, args: serde_json::Value) -> ToolFuture<'a> {
+                let cart_id = args["cartId"].as_str().unwrap_or_default();
+                let exists = state.carts.contains_key(cart_id);
+                Box::pin(async move {
+                    Ok(serde_json::json!({ "structuredContent": { "exists": exists } }))
+                })
+            }
+        }
+
+        let mut tools = ToolRegistry::default();
+        tools.register(PingCartTool);
+        let state = Arc::new(AppState::with_config(Config::default()).with_tools(tools));
+        let call = |body: serde_json::Value| post_mcp(&state, body);
+
+        let listed =
+            call(serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" })).await;
+        let names: Vec<&str> = listed["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|tool| tool["name"].as_str())
+            .collect();
+        assert!(names.contains(&"ping_cart"));
+        assert_eq!(
+            names.iter().filter(|name| **name == "add_to_cart").count(),
+            1
+        );
+
+        let response = call(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "tools/call",
+            "params": { "name": "ping_cart", "arguments": { "cartId": "nowhere" } }
+        }))
+        .await;
+        assert_eq!(response["result"]["structuredContent"]["exists"], false);
+    }
+
+    #[tokio::test]
+    async fn test_snake_case_output() {
+        use crate::config::OutputCase;
+
+        let state = Arc::new(AppState::with_config(Config {
+            output_case: OutputCase::Snake,
+            ..Config::default()
+        }));
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 9,
+            "method": "tools/call",
+            "params": {
+                "name": "add_to_cart",
+                "arguments": { "cartId": "snake_cart", "items": [
+                    { "name": "Apple", "imageUrl": "http://cdn/apple.png", "taxCategory": "food" }
+                ] }
+            }
+        });
+        state
+            .carts
+            .entry("snake_cart".to_string())
+            .or_default()
+            .metadata
+            .insert("tableNumber".to_string(), serde_json::json!(4));
+        let response = post_mcp(&state, body).await;
+
+        // The envelope keeps its protocol keys; only structured content is recased
+        assert_eq!(response["jsonrpc"], "2.0");
+        let content = &response["result"]["structuredContent"];
+        assert_eq!(content["cart_id"], "snake_cart");
+        assert!(content.get("cartId").is_none());
+        assert!(content.get("currency_info").is_some());
+        assert_eq!(content["item_actions"][0]["name"], "Apple");
+        // Client data keeps the keys the client chose, so it can be synced back as is
+        assert_eq!(content["metadata"], serde_json::json!({ "tableNumber": 4 }));
+        assert_eq!(content["items"][0]["imageUrl"], "http://cdn/apple.png");
+        assert_eq!(content["items"][0]["taxCategory"], "food");
+        // The cart was still saved under its id and found again
+        assert!(state.carts.contains_key("snake_cart"));
+    }
+
+    #[tokio::test]
+    async fn test_widget_session_id_is_echoed() {
+        let config = Config {
+            echo_widget_session_id: true,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::with_config(config));
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 8,
+            "method": "tools/call",
+            "params": {
+                "name": "add_to_cart",
+                "arguments": { "cartId": "session_cart", "items": [{ "name": "Apple" }] },
+                "_meta": { "openai/widgetSessionId": "ws_4f2a-Z" }
+            }
+        });
+        let response = post_mcp(&state, body).await;
+
+        let result = &response["result"];
+        assert_eq!(result["_meta"]["openai/widgetSessionId"], "ws_4f2a-Z");
+        assert_eq!(result["structuredContent"]["cartId"], "session_cart");
+    }
+
+    #[tokio::test]
+    async fn test_tool_result_diff() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let call = |arguments: serde_json::Value, include_diff: bool| {
+            let body = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "add_to_cart", "arguments": arguments, "includeDiff": include_diff }
+            });
+            post_mcp(&state, body)
+        };
+
+        // Off unless asked for
+        let response = call(
+            serde_json::json!({ "cartId": "diff_cart", "items": [{ "name": "Apple" }] }),
+            false,
+        )
+        .await;
+        assert!(response["result"]["structuredContent"]
+            .get("diff")
+            .is_none());
+
+        let response = call(
+            serde_json::json!({ "cartId": "diff_cart", "items": [
+                { "name": "Apple", "quantity": 2 },
+                { "name": "Pear" }
+            ] }),
+            true,
+        )
+        .await;
+        assert_eq!(
+            response["result"]["structuredContent"]["diff"],
+            serde_json::json!({
+                "added": [{ "name": "Pear", "quantity": 1 }],
+                "removed": [],
+                "changed": [{
+                    "name": "Apple",
+                    "before": { "name": "Apple", "quantity": 1 },
+                    "after": { "name": "Apple", "quantity": 3 }
+                }]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aliases_resolve_to_current_names() {
+        use crate::aliases::Aliases;
+        use std::{collections::HashMap, sync::atomic::Ordering};
+
+        let aliases = Aliases {
+            methods: HashMap::from([("tools/invoke".to_string(), "tools/call".to_string())]),
+            tools: HashMap::from([("add_items".to_string(), "add_to_cart".to_string())]),
+        };
+        let state = Arc::new(AppState::with_config(Config::default()).with_aliases(aliases));
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "tools/invoke",
+            "params": {
+                "name": "add_items",
+                "arguments": { "cartId": "alias_cart", "items": [{ "name": "Apple" }] }
+            }
+        });
+        let response = post_mcp(&state, body).await;
+
+        // Both old names reached the add_to_cart handler
+        assert_eq!(
+            response["result"]["structuredContent"]["items"][0]["name"],
+            "Apple"
+        );
+        assert_eq!(state.carts.get("alias_cart").unwrap().items.len(), 1);
+        assert_eq!(state.deprecated_calls.load(Ordering::Relaxed), 2);
+        assert_eq!(state.aliases.tool("checkout"), ("checkout", None));
+    }
+
+    #[test]
+    fn test_self_check_reports_mismatched_examples() {
+        use super::{self_check, BuiltinTool};
+        use crate::model::{AddToCartInput, TaxEstimateInput};
+        use crate::tools::ToolRegistry;
+
+        assert_eq!(
+            self_check(&AppState::with_config(Config::default())),
+            Vec::<String>::new()
+        );
+
+        // Registered replacements for built-ins are checked like the built-ins
+        let handler = |_: &AppState, _| Ok(serde_json::json!({}));
+        let mut tools = ToolRegistry::default();
+        tools.register(BuiltinTool::<AddToCartInput>::new(
+            serde_json::json!({
+                "name": "add_to_cart",
+                "examples": [{ "cartId": "cart_1", "items": "Apple" }]
+            }),
+            handler,
+        ));
+        tools.register(BuiltinTool::<TaxEstimateInput>::new(
+            serde_json::json!({ "name": "tax_estimate" }),
+            handler,
+        ));
+        // A custom tool with no input type to check against only needs examples
+        tools.register(BuiltinTool::<serde_json::Value>::new(
+            serde_json::json!({ "name": "ping_cart", "examples": [{ "anything": true }] }),
+            handler,
+        ));
+        let state = AppState::with_config(Config::default()).with_tools(tools);
+        let problems = self_check(&state);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("add_to_cart: example 0 doesn't match its input"));
+        assert_eq!(problems[1], "tax_estimate: no examples advertised");
+    }
+
+    #[tokio::test]
+    async fn test_batch_requests() {
+        let state = default_state();
+        let responses = post_mcp(
+            &state,
+            serde_json::json!([
+                { "jsonrpc": "2.0", "id": 1, "method": "ping" },
+                { "jsonrpc": "2.0", "method": "notifications/initialized" },
+                { "jsonrpc": "2.0", "id": "two", "method": "tools/list" },
+                { "jsonrpc": "2.0", "id": 3 }
+            ]),
+        )
+        .await;
+
+        // One response per request, in order; the notification gets none
+        let responses = responses.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"], serde_json::json!({}));
+        assert_eq!(responses[1]["id"], "two");
+        assert!(responses[1]["result"]["tools"].is_array());
+        assert_eq!(responses[2]["error"]["code"], -32600);
+
+        let empty = post_mcp(&state, serde_json::json!([])).await;
+        assert_eq!(empty["error"]["code"], -32600);
+
+        let ping = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+        let oversized = post_mcp(&state, serde_json::Value::Array(vec![ping; 51])).await;
+        assert_eq!(oversized["error"]["code"], -32600);
+    }
+
+    #[tokio::test]
+    async fn test_tools_list_without_meta() {
+        let state = default_state();
+        fn has_meta(value: &serde_json::Value) -> bool {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.contains_key("_meta") || map.values().any(has_meta)
+                }
+                serde_json::Value::Array(values) => values.iter().any(has_meta),
+                _ => false,
+            }
+        }
+
+        let list = |params: serde_json::Value| {
+            post_mcp(
+                &state,
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": 4,
+                    "method": "tools/list",
+                    "params": params
+                }),
+            )
+        };
+
+        let with_meta = list(serde_json::json!({})).await;
+        assert!(has_meta(&with_meta["result"]));
+
+        let without_meta = list(serde_json::json!({ "includeMeta": false })).await;
+        assert!(!has_meta(&without_meta["result"]));
+        assert_eq!(
+            without_meta["result"]["tools"].as_array().unwrap().len(),
+            with_meta["result"]["tools"].as_array().unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tools_advertise_valid_examples() {
+        let state = default_state();
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "tools/list"
+            }),
+        )
+        .await;
+        let tools = response["result"]["tools"].as_array().unwrap();
+        for tool in tools {
+            let schema = &tool["inputSchema"];
+            let examples = tool["examples"].as_array().unwrap();
+            assert!(!examples.is_empty(), "{} has no examples", tool["name"]);
+            for example in examples {
+                let example = example.as_object().unwrap();
+                for required in schema["required"].as_array().unwrap() {
+                    assert!(example.contains_key(required.as_str().unwrap()));
+                }
+                for key in example.keys() {
+                    assert!(schema["properties"].get(key).is_some(), "{}", key);
+                }
+            }
+        }
+
+        let add = tools
+            .iter()
+            .find(|t| t["name"] == crate::model::TOOL_NAME)
+            .unwrap();
+        let input: crate::model::AddToCartInput =
+            serde_json::from_value(add["examples"][0].clone()).unwrap();
+        assert_eq!(input.items.len(), 2);
+
+        // The schema advertises the same bounds `AddToCartInput::validate` enforces
+        let item = &add["inputSchema"]["properties"]["items"]["items"]["properties"];
+        assert_eq!(item["quantity"]["minimum"], 1);
+        assert_eq!(item["name"]["minLength"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_params_are_typed() {
+        let state = default_state();
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 4,
+                "method": "tools/call",
+                "params": {
+                    "name": "add_to_cart",
+                    "arguments": { "cartId": "typed_cart", "items": [{ "name": "Apple" }] }
+                }
+            }),
+        )
+        .await;
+        assert_eq!(
+            response["result"]["structuredContent"]["cartId"],
+            "typed_cart"
+        );
+
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 5,
+                "method": "tools/call",
+                "params": { "arguments": {} }
+            }),
+        )
+        .await;
+        assert_eq!(response["id"], 5);
+        assert_eq!(response["error"]["code"], -32602);
+        assert_eq!(
+            response["error"]["message"],
+            "Invalid params: missing field `name`"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bom_and_leading_whitespace_are_tolerated() {
+        let post_raw = |body: Vec<u8>| async move {
+            let res = app()
+                .oneshot(
+                    Request::post("/mcp")
+                        .header("content-type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+        let ping = br#"{ "jsonrpc": "2.0", "id": 9, "method": "ping" }"#;
+
+        let mut body = b"\xEF\xBB\xBF \r\n".to_vec();
+        body.extend_from_slice(ping);
+        let response = post_raw(body).await;
+        assert_eq!(response["id"], 9);
+        assert_eq!(response["result"], serde_json::json!({}));
+
+        // Malformed JSON is still a parse error
+        let mut body = b"\xEF\xBB\xBF".to_vec();
+        body.extend_from_slice(&ping[..10]);
+        let response = post_raw(body).await;
+        assert_eq!(response["error"]["code"], -32700);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tool_code_differs_from_invalid_arguments() {
+        let state = default_state();
+        use crate::model::{INVALID_PARAMS_CODE, UNKNOWN_TOOL_CODE};
+
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 6,
+                "method": "tools/call",
+                "params": { "name": "no_such_tool", "arguments": {} }
+            }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], UNKNOWN_TOOL_CODE);
+        assert_eq!(response["error"]["data"]["tool"], "no_such_tool");
+
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "method": "tools/call",
+                "params": { "name": "add_to_cart", "arguments": { "items": "Apple" } }
+            }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS_CODE);
+        assert_eq!(response["error"]["data"]["path"], "items");
+        assert_ne!(UNKNOWN_TOOL_CODE, INVALID_PARAMS_CODE);
+
+        // The path reaches into nested arguments
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 8,
+                "method": "tools/call",
+                "params": {
+                    "name": "add_to_cart",
+                    "arguments": { "items": [{ "name": "Apple", "quantity": "two" }] }
+                }
+            }),
+        )
+        .await;
+        assert_eq!(response["error"]["data"]["path"], "items[0].quantity");
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_get_reports_features() {
+        let state = Arc::new(AppState::with_config(Config {
+            strict_params: true,
+            ..Config::default()
+        }));
+        let body = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "capabilities/get" });
+        let response = post_mcp(&state, body).await;
+        assert_eq!(response["result"]["strictParams"], true);
+        assert_eq!(response["result"]["coupons"], false);
+    }
+
+    #[tokio::test]
+    async fn test_resource_templates_list() {
+        let state = default_state();
+        let response = post_mcp(
+            &state,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "resources/templates/list"
+            }),
+        )
+        .await;
+        assert_eq!(response["id"], 3);
+        assert!(response.get("error").is_none());
+        assert_eq!(
+            response["result"]["resourceTemplates"][0]["uriTemplate"],
+            crate::model::WIDGET_TEMPLATE_URI
+        );
+    }
+
+    #[tokio::test]
+    async fn test_options_lists_allowed_methods() {
+        let res = app()
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/mcp")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let allow = res.headers()["allow"].to_str().unwrap();
+        assert!(allow.contains("GET") && allow.contains("POST"));
+
+        // CORS preflights are still answered by the CORS layer
+        let res = app()
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/mcp")
+                    .header("origin", "https://chat.example")
+                    .header("access-control-request-method", "POST")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert!(res.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_initialize_does_not_satisfy_strict_init() {
+        let state = Arc::new(AppState::with_config(Config {
+            strict_init: true,
+            ..Config::default()
+        }));
+        let send = |body: serde_json::Value, session: Option<String>| {
+            let state = state.clone();
+            async move {
+                let mut req = Request::post("/mcp").header("content-type", "application/json");
+                if let Some(session) = session {
+                    req = req.header("mcp-session-id", session);
+                }
+                let res = create_app_router(state)
+                    .oneshot(req.body(Body::from(body.to_string())).unwrap())
+                    .await
+                    .unwrap();
+                let session = res
+                    .headers()
+                    .get("mcp-session-id")
+                    .map(|v| v.to_str().unwrap().to_string());
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    session,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+        let list = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" });
+
+        let (session, probe) = send(
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "_meta": { "probe": true } }
+            }),
+            None,
+        )
+        .await;
+        assert!(probe["result"]["capabilities"].is_object());
+        assert!(session.is_none());
+        assert_eq!(send(list.clone(), None).await.1["error"]["code"], -32002);
+
+        let (session, _) = send(
+            serde_json::json!({ "jsonrpc": "2.0", "id": 3, "method": "initialize", "params": {} }),
+            None,
+        )
+        .await;
+        let session = session.expect("initialize should return a session id");
+        assert!(send(list.clone(), Some(session)).await.1["result"]["tools"].is_array());
+
+        // Another client's handshake doesn't unlock requests outside its session
+        assert_eq!(send(list.clone(), None).await.1["error"]["code"], -32002);
+        let forged = Some("not-a-session".to_string());
+        assert_eq!(send(list, forged).await.1["error"]["code"], -32002);
+    }
+
+    #[tokio::test]
+    async fn test_progress_frames_precede_result() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 9,
+            "method": "tools/call",
+            "params": {
+                "name": "add_to_cart",
+                "arguments": { "cartId": "progress_cart", "items": [{ "name": "Apple" }] },
+                "_meta": { "progressToken": "tok-1" }
+            }
+        });
+        let res = create_app_router(state)
+            .oneshot(
+                Request::post("/mcp")
+                    .header("content-type", "application/json")
+                    .header("accept", "application/json, text/event-stream")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["content-type"], "text/event-stream");
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let frames: Vec<serde_json::Value> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+
+        let (result, progress) = frames.split_last().unwrap();
+        assert!(!progress.is_empty());
+        for frame in progress {
+            assert_eq!(frame["method"], "notifications/progress");
+            assert_eq!(frame["params"]["progressToken"], "tok-1");
+        }
+        assert_eq!(result["id"], 9);
+        assert_eq!(
+            result["result"]["structuredContent"]["cartId"],
+            "progress_cart"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_websocket_transport_answers_initialize() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app()).await });
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/mcp/ws", addr))
+            .await
+            .unwrap();
+        let initialize = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize" });
+        socket
+            .send(Message::Text(initialize.to_string()))
+            .await
+            .unwrap();
+
+        let frame = socket.next().await.unwrap().unwrap();
+        let response: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["serverInfo"]["name"], SERVER_NAME);
+
+        // Garbage gets a parse error frame rather than closing the socket
+        socket.send(Message::Text("{".into())).await.unwrap();
+        let frame = socket.next().await.unwrap().unwrap();
+        let response: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+    }
+}
@@ -1,23 +1,36 @@
 //! Routing module for the shopping cart application
 
+pub mod admin;
 pub mod cart;
+pub mod features;
+pub mod health;
 pub mod mcp;
+pub mod schema;
+pub mod session;
 
 use crate::model::SharedState;
-use axum::{body::Body, extract::Request, middleware::Next, Router};
-use tower_http::cors::{Any, CorsLayer};
+use crate::rate_limit::{client_ip, ClientIp};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    cors::{Any, CorsLayer},
+};
 
 /// Creates and configures the application router with all routes and middleware
 pub fn create_app_router(state: SharedState) -> Router {
-    // Middleware: Log requests
-    let log_layer = axum::middleware::from_fn(|req: Request<Body>, next: Next| async move {
-        println!("REQ: {} {}", req.method(), req.uri());
-        let res = next.run(req).await;
-        if !res.status().is_success() {
-            println!("RES: {} (Error)", res.status());
-        }
-        res
-    });
+    // Middleware: Log requests and enforce the per-client rate limit
+    let log_layer = axum::middleware::from_fn_with_state(state.clone(), log_and_rate_limit);
 
     // Middleware: CORS (Permissive for local dev)
     let cors_layer = CorsLayer::new()
@@ -25,11 +38,251 @@ pub fn create_app_router(state: SharedState) -> Router {
         .allow_methods(Any)
         .allow_headers(Any);
 
+    // Middleware: gzip responses large enough to be worth it (never SSE, which must stream)
+    let compression_layer = CompressionLayer::new().compress_when(
+        SizeAbove::new(state.config.compress_min_bytes())
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::SSE),
+    );
+
     // Routes
-    Router::new()
+    let routes = Router::new()
         .merge(mcp::routes())
         .merge(cart::routes())
+        .merge(features::routes())
+        .merge(health::routes())
+        .merge(schema::routes())
+        .merge(admin::routes(state.clone()))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            session::session_cookie,
+        ))
         .layer(log_layer)
+        .layer(compression_layer)
+        .with_state(state);
+
+    // The CORS layer answers every OPTIONS request as a preflight, so plain OPTIONS
+    // requests (no Access-Control-Request-Method) skip it and reach the routes' `allow`
+    let plain_options = routes.clone();
+    Router::new()
+        .fallback_service(routes)
         .layer(cors_layer)
-        .with_state(state)
+        .layer(axum::middleware::from_fn(
+            move |req: Request, next: Next| {
+                let routes = plain_options.clone();
+                async move {
+                    let is_preflight = req
+                        .headers()
+                        .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+                    if req.method() == Method::OPTIONS && !is_preflight {
+                        routes.oneshot(req).await.into_response()
+                    } else {
+                        next.run(req).await
+                    }
+                }
+            },
+        ))
+        .layer(axum::middleware::map_response(add_version_header))
+}
+
+/// Middleware: tags every response with the server version for debugging
+async fn add_version_header(mut res: Response) -> Response {
+    res.headers_mut().insert(
+        "x-server-version",
+        HeaderValue::from_static(env!("CARGO_PKG_VERSION")),
+    );
+    res
+}
+
+/// Handler for plain `OPTIONS` requests: replies 204 with an `Allow` header listing the
+/// route's methods
+pub fn allow(
+    methods: &'static str,
+) -> impl Fn() -> std::future::Ready<Response> + Clone + Send + Sync + 'static {
+    move || std::future::ready((StatusCode::NO_CONTENT, [(header::ALLOW, methods)]).into_response())
+}
+
+/// Middleware: logs each request with the client IP and rejects clients over the rate limit
+async fn log_and_rate_limit(
+    State(state): State<SharedState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let ip = client_ip(req.headers(), peer, state.config.trust_proxy);
+    let client = ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+    req.extensions_mut().insert(ClientIp(ip));
+
+    println!("REQ: {} {} {}", client, req.method(), req.uri());
+    if let Some(ip) = ip {
+        if !state.rate_limiter.check(ip) {
+            println!("RES: 429 (Rate limited {})", client);
+            return StatusCode::TOO_MANY_REQUESTS.into_response();
+        }
+    }
+
+    let res = next.run(req).await;
+    if !res.status().is_success() {
+        println!("RES: {} (Error)", res.status());
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::create_app_router;
+    use crate::config::Config;
+    use crate::model::AppState;
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn rpc(method: &str) -> Request<Body> {
+        let body = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+        Request::post("/mcp")
+            .header("content-type", "application/json")
+            .header("accept-encoding", "gzip")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_large_responses_are_compressed() {
+        let assets = std::env::temp_dir().join(format!("compress_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(assets.join("shopping-cart.html"), "<p>cart</p>".repeat(500)).unwrap();
+
+        let mut state = AppState::with_config(Config {
+            compress_min_bytes: Some(512),
+            ..Config::default()
+        });
+        state.assets_dir = assets.clone();
+        let state = Arc::new(state);
+
+        let res = create_app_router(state.clone())
+            .oneshot(rpc("ping"))
+            .await
+            .unwrap();
+        assert!(res.headers().get("content-encoding").is_none());
+
+        let res = create_app_router(state)
+            .oneshot(rpc("resources/read"))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["content-encoding"], "gzip");
+
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_widget_is_fetched_from_remote_url() {
+        let cdn = axum::Router::new().route(
+            "/widget.html",
+            axum::routing::get(|| async { "<p>cdn widget</p>" }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, cdn).await });
+
+        let assets = std::env::temp_dir().join(format!("remote_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(assets.join("shopping-cart.html"), "<p>local widget</p>").unwrap();
+
+        let read_widget = |widget_url: String| {
+            let mut state = AppState::with_config(Config {
+                widget_url: Some(widget_url),
+                ..Config::default()
+            });
+            state.assets_dir = assets.clone();
+            async move {
+                let res = create_app_router(Arc::new(state))
+                    .oneshot(rpc("resources/read"))
+                    .await
+                    .unwrap();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                body["result"]["contents"][0]["text"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+
+        let html = read_widget(format!("http://{}/widget.html", addr)).await;
+        assert_eq!(html, "<p>cdn widget</p>");
+
+        // A failed fetch falls back to the local assets
+        let html = read_widget(format!("http://{}/missing.html", addr)).await;
+        assert_eq!(html, "<p>local widget</p>");
+
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_server_version() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let res = create_app_router(state).oneshot(rpc("ping")).await.unwrap();
+        assert_eq!(res.headers()["x-server-version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_keys_on_forwarded_ip() {
+        let state = Arc::new(AppState::with_config(Config {
+            rate_limit_per_minute: Some(1),
+            trust_proxy: true,
+            ..Config::default()
+        }));
+        let from = |ip: &str| {
+            let mut req = rpc("ping");
+            req.headers_mut().insert(
+                "x-forwarded-for",
+                format!("198.51.100.9, {}", ip).parse().unwrap(),
+            );
+            create_app_router(state.clone()).oneshot(req)
+        };
+
+        assert_eq!(from("203.0.113.1").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            from("203.0.113.1").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // Another client behind the same proxy has its own budget
+        assert_eq!(from("203.0.113.2").await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_batch_entries_count_against_rate_limit() {
+        let state = Arc::new(AppState::with_config(Config {
+            rate_limit_per_minute: Some(3),
+            trust_proxy: true,
+            ..Config::default()
+        }));
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "ping" },
+            { "jsonrpc": "2.0", "id": 2, "method": "ping" },
+            { "jsonrpc": "2.0", "id": 3, "method": "ping" },
+            { "jsonrpc": "2.0", "id": 4, "method": "ping" }
+        ]);
+        let req = Request::post("/mcp")
+            .header("content-type", "application/json")
+            .header("x-forwarded-for", "203.0.113.1")
+            .body(Body::from(batch.to_string()))
+            .unwrap();
+        let res = create_app_router(state).oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let responses: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        // Three entries fit the budget; the fourth is refused without running
+        assert_eq!(responses[2]["result"], serde_json::json!({}));
+        assert_eq!(responses[3]["id"], 4);
+        assert_eq!(responses[3]["error"]["code"], -32000);
+    }
 }
@@ -0,0 +1,64 @@
+//! Data model schema route handlers
+//!
+//! Publishes JSON Schemas for the request and response models so client code can be
+//! generated from the server's own types instead of drifting from them.
+
+use crate::model::{AddToCartInput, CartItem, CheckoutInput, SharedState, SyncResponse};
+use crate::router::allow;
+use axum::{response::IntoResponse, routing::get, Json, Router};
+use schemars::gen::SchemaSettings;
+use serde_json::json;
+
+/// Creates routes for schema reporting
+pub fn routes() -> Router<SharedState> {
+    Router::new().route("/schema", get(get_schema).options(allow("GET, OPTIONS")))
+}
+
+/// Endpoint: GET /schema
+/// Returns a draft-07 document whose `definitions` hold every model and the types
+/// they reference.
+async fn get_schema() -> impl IntoResponse {
+    let settings = SchemaSettings::draft07();
+    let meta_schema = settings.meta_schema.clone();
+    let mut generator = settings.into_generator();
+    generator.subschema_for::<CartItem>();
+    generator.subschema_for::<AddToCartInput>();
+    generator.subschema_for::<CheckoutInput>();
+    generator.subschema_for::<SyncResponse>();
+
+    Json(json!({
+        "$schema": meta_schema,
+        "definitions": generator.definitions()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::model::AppState;
+    use crate::router::create_app_router;
+    use axum::{body::Body, http::Request};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_schema_describes_models() {
+        let state = Arc::new(AppState::with_config(Config::default()));
+        let res = create_app_router(state)
+            .oneshot(Request::get("/schema").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let schema: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let definitions = &schema["definitions"];
+        let item = &definitions["CartItem"];
+        assert_eq!(item["properties"]["quantity"]["type"], "integer");
+        assert_eq!(item["required"], serde_json::json!(["name"]));
+        assert!(definitions["AddToCartInput"]["properties"]["cartId"].is_object());
+        assert!(definitions["CheckoutInput"].is_object());
+        assert!(definitions["SyncResponse"]["properties"]["cartId"].is_object());
+    }
+}
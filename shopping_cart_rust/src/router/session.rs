@@ -0,0 +1,72 @@
+//! Session cookie middleware
+//!
+//! Every response establishes a `cart_session` cookie on first contact, so REST
+//! calls that omit `cartId` keep reusing the same default cart.
+//!
+//! Each cookie-less request is minted its own session. Nothing about a request
+//! without the cookie (address, user agent) reliably identifies one client, so a
+//! client firing several requests before storing the cookie gets a session per
+//! request and should wait for the first response. A request already carrying a
+//! `cart_session` cookie never gets a new one, unless the cookie's value isn't a
+//! valid cart id (e.g. it was tampered with). Since the session doubles as the
+//! default cart id, such a cookie is replaced as if it were missing.
+
+use crate::model::SharedState;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// Name of the cookie holding the session's default cart id
+pub const SESSION_COOKIE: &str = "cart_session";
+
+/// The caller's session id, inserted into request extensions by `session_cookie`
+#[derive(Debug, Clone)]
+pub struct Session(pub String);
+
+impl Session {
+    /// Returns the explicit cart id, or the session's default cart
+    pub fn cart_id(&self, explicit: Option<String>) -> String {
+        explicit.unwrap_or_else(|| self.0.clone())
+    }
+}
+
+/// Middleware: attaches the session to the request, minting and setting a cookie when absent
+pub async fn session_cookie(
+    State(state): State<SharedState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // A tampered cookie is replaced like a missing one, since it would become a cart id
+    let existing = session_from_cookies(req.headers()).filter(|id| state.check_cart_id(id).is_ok());
+    let session = existing
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    req.extensions_mut().insert(Session(session.clone()));
+
+    let mut res = next.run(req).await;
+    if existing.is_none() {
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax",
+            SESSION_COOKIE, session
+        );
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            res.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    res
+}
+
+/// Reads the session id from the request's `Cookie` headers
+fn session_from_cookies(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, value)| *name == SESSION_COOKIE && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
@@ -0,0 +1,228 @@
+//! HTTP accept loop
+//!
+//! `axum::serve` offers no per-connection settings, so connections are served
+//! through hyper directly. This lets each socket be wrapped in [`IdleTimeout`],
+//! which closes connections that have seen no traffic in either direction for
+//! the configured time. SSE streams stay open as long as their keep-alive
+//! comments go out more often than the timeout.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+    time::{Instant, Sleep},
+};
+use tower::ServiceExt;
+
+/// Serves the app until `signal` resolves, then waits for open connections to finish
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    idle_timeout: Option<Duration>,
+    signal: impl Future<Output = ()>,
+) {
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(conn) => conn,
+                Err(e) => {
+                    eprintln!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+
+        let service = app.clone().map_request(move |req: Request<Incoming>| {
+            let mut req = req.map(Body::new);
+            req.extensions_mut().insert(ConnectInfo(remote_addr));
+            req
+        });
+        let io = TokioIo::new(IdleTimeout::new(stream, idle_timeout));
+        let conn = Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(io, TowerToHyperService::new(service))
+            .into_owned();
+        let conn = graceful.watch(conn);
+        tokio::spawn(async move {
+            // Errors here are clients hanging up or idle connections timing out
+            let _ = conn.await;
+        });
+    }
+
+    graceful.shutdown().await;
+}
+
+/// A stream that fails with `TimedOut` once it has been idle for too long.
+/// Successful reads and writes push the deadline back.
+pub struct IdleTimeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<S> IdleTimeout<S> {
+    /// Wraps a stream; with no timeout the wrapper passes everything through
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        let deadline = Box::pin(tokio::time::sleep(timeout.unwrap_or(Duration::ZERO)));
+        Self {
+            inner,
+            timeout,
+            deadline,
+        }
+    }
+
+    fn touch(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.deadline.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        if self.timeout.is_none() {
+            return Poll::Pending;
+        }
+        self.deadline
+            .as_mut()
+            .poll(cx)
+            .map(|_| io::Error::new(io::ErrorKind::TimedOut, "connection idle timeout"))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleTimeout<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                self.touch();
+                Poll::Ready(result)
+            }
+            Poll::Pending => self.poll_expired(cx).map(Err),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                self.touch();
+                Poll::Ready(result)
+            }
+            Poll::Pending => self.poll_expired(cx).map(Err),
+        }
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.inner).poll_write_vectored(cx, bufs) {
+            Poll::Ready(result) => {
+                self.touch();
+                Poll::Ready(result)
+            }
+            Poll::Pending => self.poll_expired(cx).map(Err),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, model::AppState};
+    use std::{net::SocketAddr, sync::Arc};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    /// Starts a server with a one-second idle timeout and connects to it
+    async fn connect() -> TcpStream {
+        let state = Arc::new(AppState::with_config(Config {
+            http_idle_timeout_seconds: Some(1),
+            ..Config::default()
+        }));
+        let idle_timeout = state.config.http_idle_timeout();
+        let app = crate::router::create_app_router(state);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app, idle_timeout, std::future::pending()));
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_is_closed() {
+        let mut stream = connect().await;
+        stream
+            .write_all(b"GET /features HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        // The response arrives, then the server hangs up once the connection idles
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+            .await
+            .expect("idle connection was not closed")
+            .unwrap();
+        assert!(received.starts_with(b"HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_outlives_idle_timeout() {
+        let mut stream = connect().await;
+        stream
+            .write_all(b"GET /mcp HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n")
+            .await
+            .unwrap();
+
+        // Keep-alive comments hold the stream open across several timeouts
+        let mut received = Vec::new();
+        let read = tokio::time::timeout(
+            Duration::from_millis(2500),
+            stream.read_to_end(&mut received),
+        )
+        .await;
+        assert!(read.is_err(), "SSE stream was closed while idle");
+        assert!(String::from_utf8_lossy(&received).contains("event: endpoint"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Shareable cart links
+//!
+//! Links point at `SHARE_BASE_URL` with the cart id in the query string. When
+//! `SHARE_SECRET` is set they also carry an HMAC-SHA256 signature of the cart id,
+//! so `load_shared_cart` can refuse ids that were not shared by this server.
+//!
+//! With `SHARE_LINK_TTL_SECONDS` set, signed links also carry an `exp` timestamp
+//! and a one-time `nonce`, both covered by the signature, so a link stops working
+//! once it expires or has been opened.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Expiry and one-time nonce bound into a signed link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claims {
+    /// Unix time (seconds) after which the link is refused
+    pub exp: u64,
+
+    /// Random value a link may be redeemed with only once
+    pub nonce: String,
+}
+
+impl Claims {
+    /// Creates claims for a link valid for `ttl` from now
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            exp: unix_now() + ttl.as_secs(),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+        }
+    }
+
+    /// Reports whether the link's expiry has passed
+    pub fn is_expired(&self) -> bool {
+        unix_now() >= self.exp
+    }
+}
+
+/// Builds a share link for a cart, signed when a secret is provided
+pub fn share_link(
+    base_url: &str,
+    cart_id: &str,
+    secret: Option<&str>,
+    claims: Option<&Claims>,
+) -> String {
+    let separator = if base_url.contains('?') { '&' } else { '?' };
+    let mut link = format!("{}{}cartId={}", base_url, separator, encode_query(cart_id));
+    if let Some(secret) = secret {
+        if let Some(claims) = claims {
+            link.push_str(&format!("&exp={}&nonce={}", claims.exp, claims.nonce));
+        }
+        link.push_str("&sig=");
+        link.push_str(&sign(cart_id, claims, secret));
+    }
+    link
+}
+
+/// Computes the hex-encoded HMAC-SHA256 signature of a cart id and its claims
+pub fn sign(cart_id: &str, claims: Option<&Claims>, secret: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.update(signed_payload(cart_id, claims).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks a signature in constant time
+pub fn verify(cart_id: &str, claims: Option<&Claims>, signature: &str, secret: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.update(signed_payload(cart_id, claims).as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Returns the current Unix time in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Joins the signed fields; cart ids never contain '.', so the encoding is unambiguous
+fn signed_payload(cart_id: &str, claims: Option<&Claims>) -> String {
+    match claims {
+        Some(claims) => format!("{}.{}.{}", cart_id, claims.exp, claims.nonce),
+        None => cart_id.to_string(),
+    }
+}
+
+/// Percent-encodes everything except RFC 3986 unreserved characters
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_link_contains_cart_id_and_signature() {
+        let link = share_link("https://shop.example/cart", "cart 1", None, None);
+        assert_eq!(link, "https://shop.example/cart?cartId=cart%201");
+
+        let link = share_link(
+            "https://shop.example/?ref=x",
+            "cart_1",
+            Some("secret"),
+            None,
+        );
+        let signature = link.split("&sig=").nth(1).unwrap();
+        assert!(link.starts_with("https://shop.example/?ref=x&cartId=cart_1&sig="));
+        assert!(verify("cart_1", None, signature, "secret"));
+        assert!(!verify("cart_2", None, signature, "secret"));
+        assert!(!verify("cart_1", None, signature, "other"));
+        assert!(!verify("cart_1", None, "not-hex", "secret"));
+    }
+
+    #[test]
+    fn test_claims_are_signed() {
+        let claims = Claims::new(Duration::from_secs(60));
+        let link = share_link("https://shop.example/", "cart_1", Some("k"), Some(&claims));
+        assert!(link.contains(&format!("&exp={}&nonce={}&sig=", claims.exp, claims.nonce)));
+
+        let signature = sign("cart_1", Some(&claims), "k");
+        assert!(verify("cart_1", Some(&claims), &signature, "k"));
+        assert!(!verify("cart_1", None, &signature, "k"));
+        let extended = Claims {
+            exp: claims.exp + 3600,
+            ..claims.clone()
+        };
+        assert!(!verify("cart_1", Some(&extended), &signature, "k"));
+        assert!(!claims.is_expired());
+    }
+}
@@ -0,0 +1,72 @@
+//! Shipping cost tiers
+//!
+//! Tiers are loaded from a JSON list and checked in order; the first tier whose
+//! conditions the cart meets sets the shipping cost, e.g.
+//!
+//! ```json
+//! [
+//!   { "name": "Free shipping", "minSubtotal": 50, "cost": 0 },
+//!   { "name": "Standard", "maxWeight": 10, "cost": 5 },
+//!   { "name": "Freight", "cost": 25 }
+//! ]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A shipping rate and the cart conditions it applies under
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShippingTier {
+    /// Label shown to the customer
+    pub name: String,
+
+    /// Shipping cost charged for this tier
+    pub cost: f64,
+
+    /// Applies only when the cart subtotal is at least this amount
+    #[serde(default)]
+    pub min_subtotal: Option<f64>,
+
+    /// Applies only when the cart weighs at most this much
+    #[serde(default)]
+    pub max_weight: Option<f64>,
+}
+
+impl ShippingTier {
+    /// Reports whether a cart with this subtotal and weight qualifies for the tier
+    pub fn applies(&self, subtotal: f64, weight: f64) -> bool {
+        self.min_subtotal.is_none_or(|min| subtotal >= min)
+            && self.max_weight.is_none_or(|max| weight <= max)
+    }
+}
+
+/// Loads shipping tiers from a JSON file, keeping their order
+pub fn load_shipping_tiers(path: &Path) -> Result<Vec<ShippingTier>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read shipping tiers {:?}: {}", path, e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("Invalid shipping tiers {:?}: {}", path, e))
+}
+
+/// Returns the first tier the cart qualifies for
+pub fn select_tier(tiers: &[ShippingTier], subtotal: f64, weight: f64) -> Option<&ShippingTier> {
+    tiers.iter().find(|tier| tier.applies(subtotal, weight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_matching_tier_wins() {
+        let tiers: Vec<ShippingTier> = serde_json::from_value(serde_json::json!([
+            { "name": "Free", "minSubtotal": 50, "cost": 0 },
+            { "name": "Standard", "maxWeight": 10, "cost": 5 }
+        ]))
+        .unwrap();
+
+        assert_eq!(select_tier(&tiers, 60.0, 20.0).unwrap().name, "Free");
+        assert_eq!(select_tier(&tiers, 20.0, 2.0).unwrap().name, "Standard");
+        assert!(select_tier(&tiers, 20.0, 12.0).is_none());
+    }
+}
@@ -0,0 +1,68 @@
+//! Tool registry
+//!
+//! Every tool `tools/list` advertises and `tools/call` runs implements [`Tool`]. The
+//! built-ins live in the MCP router; tools registered on `AppState` at startup (see
+//! `custom_tools` in `main.rs`) are served alongside them, and one registered under
+//! a built-in's name replaces it.
+
+use crate::model::{AppState, ToolError};
+use futures_util::future::BoxFuture;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Outcome of a tool call, which may await (e.g. loading the widget HTML)
+pub type ToolFuture<'a> = BoxFuture<'a, Result<Value, ToolError>>;
+
+/// A tool callable through `tools/call`
+pub trait Tool: Send + Sync {
+    /// Name the tool is listed and called by
+    fn name(&self) -> &str;
+
+    /// Descriptor advertised by `tools/list`: name, title, description, examples and
+    /// input schema
+    fn schema(&self) -> Value;
+
+    /// Whether a call changes the cart it names, which is then saved afterwards
+    fn is_mutating(&self) -> bool {
+        false
+    }
+
+    /// Checks arguments against the tool's input type without running it; the
+    /// startup self-check validates advertised examples with it
+    fn validate_input(&self, _args: &Value) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs the tool with the call's arguments
+    fn call<'a>(&'a self, state: &'a AppState, args: Value) -> ToolFuture<'a>;
+}
+
+/// Registered tools, in the order they're listed
+#[derive(Default, Clone)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    /// Adds a tool, replacing (in place) any registered tool with the same name
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        let tool: Arc<dyn Tool> = Arc::new(tool);
+        match self.tools.iter_mut().find(|t| t.name() == tool.name()) {
+            Some(existing) => *existing = tool,
+            None => self.tools.push(tool),
+        }
+    }
+
+    /// Looks up a tool by name
+    pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools
+            .iter()
+            .find(|tool| tool.name() == name)
+            .map(|tool| tool.as_ref())
+    }
+
+    /// Iterates over the tools in registration order
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Tool> {
+        self.tools.iter().map(|tool| tool.as_ref())
+    }
+}
@@ -0,0 +1,64 @@
+//! Development file watcher for widget assets
+//!
+//! When `ASSET_HOT_RELOAD` is enabled, changes under the assets directory drop
+//! the cached widget HTML so edits show up without restarting the server.
+
+use crate::model::{AppState, SharedState};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Starts watching the assets directory. The returned watcher must be kept alive.
+pub fn spawn_asset_watcher(state: SharedState) -> notify::Result<RecommendedWatcher> {
+    let assets_dir = state.assets_dir.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => handle_asset_event(&state, &event),
+        Err(e) => eprintln!("Asset watcher error: {}", e),
+    })?;
+
+    watcher.watch(&assets_dir, RecursiveMode::Recursive)?;
+    println!("Watching {:?} for asset changes", assets_dir);
+    Ok(watcher)
+}
+
+/// Invalidates the widget cache when an HTML asset is created, modified, or removed
+pub fn handle_asset_event(state: &AppState, event: &Event) {
+    let relevant_kind = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    );
+    let touches_html = event
+        .paths
+        .iter()
+        .any(|p| p.extension().is_some_and(|ext| ext == "html"));
+
+    if relevant_kind && touches_html {
+        println!("Asset changed, reloading widget HTML: {:?}", event.paths);
+        state.invalidate_widget_cache();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use notify::event::{AccessKind, ModifyKind};
+
+    #[test]
+    fn test_html_change_invalidates_cache() {
+        let state = AppState::with_config(Config::default());
+        *state.widget_cache.write().unwrap() = Some("<html>old</html>".into());
+
+        // Access events and non-HTML files are ignored
+        let access = Event::new(EventKind::Access(AccessKind::Any))
+            .add_path(state.assets_dir.join("shopping-cart.html"));
+        handle_asset_event(&state, &access);
+        let css = Event::new(EventKind::Modify(ModifyKind::Any))
+            .add_path(state.assets_dir.join("shopping-cart.css"));
+        handle_asset_event(&state, &css);
+        assert!(state.widget_cache.read().unwrap().is_some());
+
+        let modify = Event::new(EventKind::Modify(ModifyKind::Any))
+            .add_path(state.assets_dir.join("shopping-cart.html"));
+        handle_asset_event(&state, &modify);
+        assert!(state.widget_cache.read().unwrap().is_none());
+    }
+}